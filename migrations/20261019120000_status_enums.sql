@@ -0,0 +1,66 @@
+-- Typed challenge statuses, transaction types and transaction states
+
+CREATE TYPE challenge_status AS ENUM ('WAITING FOR ACCEPTANCE', 'ACCEPTED', 'COMPLETED');
+
+UPDATE challenge SET status='WAITING FOR ACCEPTANCE' WHERE status IS NULL;
+ALTER TABLE challenge
+  ALTER COLUMN status TYPE challenge_status USING status::challenge_status,
+  ALTER COLUMN status SET DEFAULT 'WAITING FOR ACCEPTANCE',
+  ALTER COLUMN status SET NOT NULL;
+
+CREATE TYPE transaction_type AS ENUM ('invoice', 'withdrawal', 'create challenge', 'accept challenge', 'fee', 'winnings', 'draw');
+
+-- OPEN, ACCEPTED, SETTLED and CANCELED mirror the lnd invoice states
+CREATE TYPE transaction_state AS ENUM ('OPEN', 'ACCEPTED', 'SETTLED', 'CANCELED', 'FAILED');
+
+UPDATE lightningchess_transaction SET state='OPEN' WHERE state IS NULL;
+ALTER TABLE lightningchess_transaction
+  ALTER COLUMN ttype TYPE transaction_type USING ttype::transaction_type,
+  ALTER COLUMN state TYPE transaction_state USING state::transaction_state,
+  ALTER COLUMN state SET NOT NULL;
+
+-- Allowed moves, kept in step with ChallengeStatus::can_become and
+-- TransactionState::can_become in src/models.rs
+CREATE OR REPLACE FUNCTION challenge_status_transition(old_status challenge_status, new_status challenge_status) RETURNS boolean AS $$
+  SELECT (old_status, new_status) IN (
+    ('WAITING FOR ACCEPTANCE'::challenge_status, 'ACCEPTED'::challenge_status),
+    ('ACCEPTED'::challenge_status, 'COMPLETED'::challenge_status)
+  );
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION transaction_state_transition(old_state transaction_state, new_state transaction_state) RETURNS boolean AS $$
+  SELECT (old_state, new_state) IN (
+    ('OPEN'::transaction_state, 'ACCEPTED'::transaction_state),
+    ('OPEN'::transaction_state, 'SETTLED'::transaction_state),
+    ('OPEN'::transaction_state, 'CANCELED'::transaction_state),
+    ('OPEN'::transaction_state, 'FAILED'::transaction_state),
+    ('ACCEPTED'::transaction_state, 'SETTLED'::transaction_state),
+    ('ACCEPTED'::transaction_state, 'CANCELED'::transaction_state)
+  );
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION check_challenge_status_transition() RETURNS trigger AS $$
+BEGIN
+  IF NOT challenge_status_transition(OLD.status, NEW.status) THEN
+    RAISE EXCEPTION 'invalid challenge status transition % -> %', OLD.status, NEW.status
+      USING ERRCODE = 'check_violation';
+  END IF;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION check_transaction_state_transition() RETURNS trigger AS $$
+BEGIN
+  IF NOT transaction_state_transition(OLD.state, NEW.state) THEN
+    RAISE EXCEPTION 'invalid transaction state transition % -> %', OLD.state, NEW.state
+      USING ERRCODE = 'check_violation';
+  END IF;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER challenge_status_transition BEFORE UPDATE OF status ON challenge
+  FOR EACH ROW EXECUTE PROCEDURE check_challenge_status_transition();
+
+CREATE TRIGGER transaction_state_transition BEFORE UPDATE OF state ON lightningchess_transaction
+  FOR EACH ROW EXECUTE PROCEDURE check_transaction_state_transition();
@@ -0,0 +1,41 @@
+-- Every ledger row belongs to a user, balances never go negative and
+-- amounts are always set
+
+CREATE TABLE IF NOT EXISTS users (
+  user_id serial PRIMARY KEY,
+  username VARCHAR (255) NOT NULL UNIQUE
+);
+
+INSERT INTO users (username)
+  SELECT username FROM challenge
+  UNION SELECT opp_username FROM challenge
+  UNION SELECT username FROM lightningchess_balance
+  UNION SELECT username FROM lightningchess_transaction
+ON CONFLICT DO NOTHING;
+
+UPDATE lightningchess_balance SET balance=0 WHERE balance IS NULL;
+ALTER TABLE lightningchess_balance
+  ALTER COLUMN balance SET NOT NULL,
+  ALTER COLUMN balance SET DEFAULT 0,
+  ADD CONSTRAINT balance_not_negative CHECK (balance >= 0),
+  ADD CONSTRAINT lightningchess_balance_username_fkey FOREIGN KEY (username) REFERENCES users (username);
+
+ALTER TABLE challenge
+  ALTER COLUMN sats SET NOT NULL,
+  ADD CONSTRAINT sats_positive CHECK (sats > 0),
+  ADD CONSTRAINT challenge_username_fkey FOREIGN KEY (username) REFERENCES users (username),
+  ADD CONSTRAINT challenge_opp_username_fkey FOREIGN KEY (opp_username) REFERENCES users (username);
+
+UPDATE lightningchess_transaction SET amount=0 WHERE amount IS NULL;
+ALTER TABLE lightningchess_transaction
+  ALTER COLUMN amount SET NOT NULL,
+  ADD COLUMN challenge_id INT,
+  ADD CONSTRAINT lightningchess_transaction_username_fkey FOREIGN KEY (username) REFERENCES users (username),
+  ADD CONSTRAINT lightningchess_transaction_challenge_id_fkey FOREIGN KEY (challenge_id) REFERENCES challenge (id);
+
+-- only fee rows recorded the lichess id before challenge_id existed
+UPDATE lightningchess_transaction t SET challenge_id=c.id
+  FROM challenge c
+  WHERE t.lichess_challenge_id = c.lichess_challenge_id;
+
+CREATE INDEX IF NOT EXISTS lightningchess_transaction_challenge_id_idx ON lightningchess_transaction(challenge_id);
@@ -0,0 +1,46 @@
+-- Users are provisioned on first login and every other table refers to
+-- them by id. The username columns stay as the name shown to players.
+
+ALTER TABLE challenge
+  DROP CONSTRAINT challenge_username_fkey,
+  DROP CONSTRAINT challenge_opp_username_fkey;
+ALTER TABLE lightningchess_balance DROP CONSTRAINT lightningchess_balance_username_fkey;
+ALTER TABLE lightningchess_transaction DROP CONSTRAINT lightningchess_transaction_username_fkey;
+
+-- a lichess id is the lowercased username
+ALTER TABLE users
+  ADD COLUMN lichess_id VARCHAR (255),
+  ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  ADD COLUMN last_login TIMESTAMPTZ;
+UPDATE users SET lichess_id = lower(username);
+DELETE FROM users u USING users v WHERE u.lichess_id = v.lichess_id AND u.user_id > v.user_id;
+ALTER TABLE users
+  ALTER COLUMN lichess_id SET NOT NULL,
+  ADD CONSTRAINT users_lichess_id_key UNIQUE (lichess_id);
+
+ALTER TABLE challenge
+  ADD COLUMN user_id INT REFERENCES users (user_id),
+  ADD COLUMN opp_user_id INT REFERENCES users (user_id);
+UPDATE challenge c SET user_id = u.user_id FROM users u WHERE u.lichess_id = lower(c.username);
+UPDATE challenge c SET opp_user_id = u.user_id FROM users u WHERE u.lichess_id = lower(c.opp_username);
+ALTER TABLE challenge
+  ALTER COLUMN user_id SET NOT NULL,
+  ALTER COLUMN opp_user_id SET NOT NULL;
+CREATE INDEX IF NOT EXISTS challenge_user_id_idx ON challenge(user_id);
+CREATE INDEX IF NOT EXISTS challenge_opp_user_id_idx ON challenge(opp_user_id);
+
+ALTER TABLE lightningchess_balance ADD COLUMN user_id INT REFERENCES users (user_id);
+UPDATE lightningchess_balance b SET user_id = u.user_id FROM users u WHERE u.lichess_id = lower(b.username);
+ALTER TABLE lightningchess_balance
+  ALTER COLUMN user_id SET NOT NULL,
+  ADD CONSTRAINT lightningchess_balance_user_id_key UNIQUE (user_id);
+
+-- every user starts with a zero balance
+INSERT INTO lightningchess_balance (user_id, username, balance)
+  SELECT user_id, username, 0 FROM users
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE lightningchess_transaction ADD COLUMN user_id INT REFERENCES users (user_id);
+UPDATE lightningchess_transaction t SET user_id = u.user_id FROM users u WHERE u.lichess_id = lower(t.username);
+ALTER TABLE lightningchess_transaction ALTER COLUMN user_id SET NOT NULL;
+CREATE INDEX IF NOT EXISTS lightningchess_transaction_user_id_idx ON lightningchess_transaction(user_id);
@@ -0,0 +1,44 @@
+-- Every table records when a row was created and last changed, in UTC.
+-- Same function the old diesel setup used, recreated since that migration is gone.
+CREATE OR REPLACE FUNCTION set_updated_at() RETURNS trigger AS $$
+BEGIN
+  IF NEW IS DISTINCT FROM OLD AND NEW.updated_at IS NOT DISTINCT FROM OLD.updated_at THEN
+    NEW.updated_at := now();
+  END IF;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- created_on was stored as UTC wall-clock time
+ALTER TABLE challenge RENAME COLUMN created_on TO created_at;
+ALTER TABLE challenge
+  ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'utc',
+  ALTER COLUMN created_at SET DEFAULT now();
+UPDATE challenge SET created_at = now() WHERE created_at IS NULL;
+ALTER TABLE challenge
+  ALTER COLUMN created_at SET NOT NULL,
+  ADD COLUMN updated_at TIMESTAMPTZ;
+UPDATE challenge SET updated_at = created_at;
+ALTER TABLE challenge
+  ALTER COLUMN updated_at SET NOT NULL,
+  ALTER COLUMN updated_at SET DEFAULT now();
+CREATE INDEX IF NOT EXISTS challenge_created_at_idx ON challenge(created_at);
+
+-- existing rows have no history, so they start from the time of this migration
+ALTER TABLE users ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE lightningchess_balance
+  ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE lightningchess_transaction
+  ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+CREATE INDEX IF NOT EXISTS lightningchess_transaction_created_at_idx ON lightningchess_transaction(created_at);
+
+CREATE TRIGGER challenge_updated_at BEFORE UPDATE ON challenge
+  FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+CREATE TRIGGER users_updated_at BEFORE UPDATE ON users
+  FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+CREATE TRIGGER lightningchess_balance_updated_at BEFORE UPDATE ON lightningchess_balance
+  FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+CREATE TRIGGER lightningchess_transaction_updated_at BEFORE UPDATE ON lightningchess_transaction
+  FOR EACH ROW EXECUTE FUNCTION set_updated_at();
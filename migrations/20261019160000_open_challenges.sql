@@ -0,0 +1,15 @@
+-- Open challenges have no opponent until someone accepts them.
+ALTER TABLE challenge
+  ALTER COLUMN opp_username DROP NOT NULL,
+  ALTER COLUMN opp_user_id DROP NOT NULL,
+  ADD CONSTRAINT challenge_opponent_once_accepted
+    CHECK (opp_user_id IS NOT NULL OR status = 'WAITING FOR ACCEPTANCE'),
+  -- optional restrictions on who may accept, checked against lichess
+  ADD COLUMN min_rating INT,
+  ADD COLUMN max_rating INT,
+  ADD COLUMN min_account_age_days INT,
+  ADD CONSTRAINT challenge_rating_range CHECK (min_rating IS NULL OR max_rating IS NULL OR min_rating <= max_rating),
+  ADD CONSTRAINT challenge_min_account_age CHECK (min_account_age_days IS NULL OR min_account_age_days >= 0);
+
+CREATE INDEX IF NOT EXISTS challenge_lobby_idx ON challenge(id)
+  WHERE opp_user_id IS NULL AND status = 'WAITING FOR ACCEPTANCE';
@@ -0,0 +1,8 @@
+-- Variants a challenge can be played in, named as in the lichess api.
+-- fromPosition is left out since challenges don't carry a starting position.
+CREATE TYPE chess_variant AS ENUM ('standard', 'chess960', 'crazyhouse', 'antichess', 'atomic', 'horde', 'kingOfTheHill', 'racingKings', 'threeCheck');
+
+-- existing challenges were all sent to lichess as rated standard games
+ALTER TABLE challenge
+  ADD COLUMN variant chess_variant NOT NULL DEFAULT 'standard',
+  ADD COLUMN rated BOOLEAN NOT NULL DEFAULT true;
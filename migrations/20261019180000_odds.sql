@@ -0,0 +1,9 @@
+-- Handicaps: the opponent can get a different clock (opponent_time_limit,
+-- NULL meaning the same as time_limit) and risk a different stake.
+ALTER TABLE challenge ADD COLUMN opponent_sats BIGINT;
+UPDATE challenge SET opponent_sats = sats;
+ALTER TABLE challenge
+  ALTER COLUMN opponent_sats SET NOT NULL,
+  ADD CONSTRAINT challenge_opponent_sats_positive CHECK (opponent_sats > 0),
+  -- lichess games start on the shorter clock and the extra time is added once the game exists
+  ADD COLUMN time_odds_applied BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,9 @@
+-- The creator's colour. random is resolved to white or black when the
+-- challenge is accepted. Older challenges with any other value are random.
+CREATE TYPE piece_color AS ENUM ('white', 'black', 'random');
+
+UPDATE challenge SET color = 'random' WHERE color IS NULL OR color NOT IN ('white', 'black');
+ALTER TABLE challenge
+  ALTER COLUMN color TYPE piece_color USING color::piece_color,
+  ALTER COLUMN color SET DEFAULT 'random',
+  ALTER COLUMN color SET NOT NULL;
@@ -0,0 +1,5 @@
+-- A rematch points at the challenge it was created from, chains of them
+-- make up a head-to-head series. Each challenge can be rematched once.
+ALTER TABLE challenge
+  ADD COLUMN rematch_of INT REFERENCES challenge (id),
+  ADD CONSTRAINT challenge_rematch_of_key UNIQUE (rematch_of);
@@ -0,0 +1,62 @@
+-- A match is a series of lichess games between two players under one stake.
+-- best of: the first to more than target / 2 points, at most target games before the tiebreak.
+-- first to: the first to target wins, draws don't count.
+CREATE TYPE match_format AS ENUM ('best of', 'first to');
+-- how a best of match that ends level is decided
+CREATE TYPE match_tiebreak AS ENUM ('split', 'sudden death', 'armageddon');
+CREATE TYPE game_outcome AS ENUM ('creator', 'opponent', 'draw', 'aborted');
+
+ALTER TYPE transaction_type ADD VALUE 'create match';
+ALTER TYPE transaction_type ADD VALUE 'accept match';
+
+CREATE TABLE match_series (
+  id serial PRIMARY KEY,
+  user_id INT NOT NULL REFERENCES users (user_id),
+  username VARCHAR (255) NOT NULL,
+  opp_user_id INT NOT NULL REFERENCES users (user_id),
+  opp_username VARCHAR (255) NOT NULL,
+  format match_format NOT NULL,
+  target INT NOT NULL CONSTRAINT match_series_target_positive CHECK (target > 0),
+  tiebreak match_tiebreak NOT NULL DEFAULT 'split',
+  sats BIGINT NOT NULL CONSTRAINT match_series_sats_positive CHECK (sats > 0),
+  opponent_sats BIGINT NOT NULL CONSTRAINT match_series_opponent_sats_positive CHECK (opponent_sats > 0),
+  time_limit INT NOT NULL,
+  increment INT NOT NULL,
+  variant chess_variant NOT NULL DEFAULT 'standard',
+  rated BOOLEAN NOT NULL DEFAULT true,
+  -- the creator's colour in the first game, it alternates after that
+  color piece_color NOT NULL DEFAULT 'random',
+  status challenge_status NOT NULL DEFAULT 'WAITING FOR ACCEPTANCE',
+  -- NULL until completed, and for a split pot
+  winner_user_id INT REFERENCES users (user_id),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS match_series_user_id_idx ON match_series(user_id);
+CREATE INDEX IF NOT EXISTS match_series_opp_user_id_idx ON match_series(opp_user_id);
+
+CREATE TABLE match_game (
+  id serial PRIMARY KEY,
+  match_id INT NOT NULL REFERENCES match_series (id),
+  game_number INT NOT NULL,
+  lichess_game_id VARCHAR (255) NOT NULL UNIQUE,
+  creator_color piece_color NOT NULL,
+  -- a draw counts as a win for black
+  armageddon BOOLEAN NOT NULL DEFAULT false,
+  -- NULL while the game is being played
+  outcome game_outcome,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  UNIQUE (match_id, game_number)
+);
+
+ALTER TABLE lightningchess_transaction ADD COLUMN match_id INT REFERENCES match_series (id);
+CREATE INDEX IF NOT EXISTS lightningchess_transaction_match_id_idx ON lightningchess_transaction(match_id);
+
+CREATE TRIGGER match_series_status_transition BEFORE UPDATE OF status ON match_series
+  FOR EACH ROW EXECUTE PROCEDURE check_challenge_status_transition();
+CREATE TRIGGER match_series_updated_at BEFORE UPDATE ON match_series
+  FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+CREATE TRIGGER match_game_updated_at BEFORE UPDATE ON match_game
+  FOR EACH ROW EXECUTE FUNCTION set_updated_at();
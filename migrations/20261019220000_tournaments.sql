@@ -0,0 +1,99 @@
+-- Swiss tournaments paired by the server, each pairing is a lichess challenge.
+-- Entrants pay the buy-in into the prize pool, which is paid out by place after the house fee.
+CREATE TYPE tournament_status AS ENUM ('open', 'running', 'completed', 'canceled');
+CREATE TYPE pairing_result AS ENUM ('white', 'black', 'draw', 'bye');
+
+ALTER TYPE transaction_type ADD VALUE 'tournament entry';
+ALTER TYPE transaction_type ADD VALUE 'tournament prize';
+ALTER TYPE transaction_type ADD VALUE 'tournament refund';
+
+-- Allowed moves, kept in step with TournamentStatus::can_become in src/models.rs
+CREATE OR REPLACE FUNCTION tournament_status_transition(old_status tournament_status, new_status tournament_status) RETURNS boolean AS $$
+  SELECT (old_status, new_status) IN (
+    ('open'::tournament_status, 'running'::tournament_status),
+    ('open'::tournament_status, 'canceled'::tournament_status),
+    ('running'::tournament_status, 'completed'::tournament_status)
+  );
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION check_tournament_status_transition() RETURNS trigger AS $$
+BEGIN
+  IF NOT tournament_status_transition(OLD.status, NEW.status) THEN
+    RAISE EXCEPTION 'invalid tournament status transition % -> %', OLD.status, NEW.status
+      USING ERRCODE = 'check_violation';
+  END IF;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TABLE tournament (
+  id serial PRIMARY KEY,
+  -- the organiser, who doesn't have to play
+  user_id INT NOT NULL REFERENCES users (user_id),
+  username VARCHAR (255) NOT NULL,
+  name VARCHAR (255) NOT NULL,
+  buy_in BIGINT NOT NULL CONSTRAINT tournament_buy_in_positive CHECK (buy_in > 0),
+  min_players INT NOT NULL CONSTRAINT tournament_min_players CHECK (min_players >= 2),
+  max_players INT CONSTRAINT tournament_max_players CHECK (max_players >= min_players),
+  rounds INT NOT NULL CONSTRAINT tournament_rounds_positive CHECK (rounds > 0),
+  time_limit INT NOT NULL,
+  increment INT NOT NULL,
+  variant chess_variant NOT NULL DEFAULT 'standard',
+  rated BOOLEAN NOT NULL DEFAULT true,
+  -- percent of the pool after the fee for 1st, 2nd, ...
+  prizes INT[] NOT NULL,
+  starts_at TIMESTAMPTZ NOT NULL,
+  status tournament_status NOT NULL DEFAULT 'open',
+  current_round INT NOT NULL DEFAULT 0,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS tournament_open_idx ON tournament(starts_at) WHERE status='open';
+
+CREATE TABLE tournament_entry (
+  id serial PRIMARY KEY,
+  tournament_id INT NOT NULL REFERENCES tournament (id),
+  user_id INT NOT NULL REFERENCES users (user_id),
+  username VARCHAR (255) NOT NULL,
+  -- a win is 2, a draw 1
+  half_points INT NOT NULL DEFAULT 0,
+  -- set when the tournament completes, tied players share a place
+  place INT,
+  prize BIGINT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  CONSTRAINT tournament_entry_once UNIQUE (tournament_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS tournament_entry_user_id_idx ON tournament_entry(user_id);
+
+CREATE TABLE tournament_pairing (
+  id serial PRIMARY KEY,
+  tournament_id INT NOT NULL REFERENCES tournament (id),
+  round INT NOT NULL,
+  white_user_id INT NOT NULL REFERENCES users (user_id),
+  white_username VARCHAR (255) NOT NULL,
+  -- NULL for a bye
+  black_user_id INT REFERENCES users (user_id),
+  black_username VARCHAR (255),
+  -- NULL until one of the players polls and sends the challenge
+  lichess_game_id VARCHAR (255) UNIQUE,
+  result pairing_result,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS tournament_pairing_round_idx ON tournament_pairing(tournament_id, round);
+
+ALTER TABLE lightningchess_transaction ADD COLUMN tournament_id INT REFERENCES tournament (id);
+CREATE INDEX IF NOT EXISTS lightningchess_transaction_tournament_id_idx ON lightningchess_transaction(tournament_id);
+
+CREATE TRIGGER tournament_status_transition BEFORE UPDATE OF status ON tournament
+  FOR EACH ROW EXECUTE PROCEDURE check_tournament_status_transition();
+CREATE TRIGGER tournament_updated_at BEFORE UPDATE ON tournament
+  FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+CREATE TRIGGER tournament_entry_updated_at BEFORE UPDATE ON tournament_entry
+  FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+CREATE TRIGGER tournament_pairing_updated_at BEFORE UPDATE ON tournament_pairing
+  FOR EACH ROW EXECUTE FUNCTION set_updated_at();
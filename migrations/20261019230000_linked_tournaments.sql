@@ -0,0 +1,12 @@
+-- Tournaments can also be an arena or Swiss event already set up on lichess, linked by its id.
+-- Lichess pairs and runs it, we hold the buy-ins and pay out from its final standings.
+CREATE TYPE tournament_kind AS ENUM ('swiss', 'lichess arena', 'lichess swiss');
+
+ALTER TABLE tournament ADD COLUMN kind tournament_kind NOT NULL DEFAULT 'swiss';
+ALTER TABLE tournament ADD COLUMN lichess_tournament_id VARCHAR (255);
+ALTER TABLE tournament ALTER COLUMN rounds DROP NOT NULL;
+
+-- only tournaments we pair have rounds, only linked ones have a lichess id
+ALTER TABLE tournament ADD CONSTRAINT tournament_rounds_when_paired CHECK ((kind = 'swiss') = (rounds IS NOT NULL));
+ALTER TABLE tournament ADD CONSTRAINT tournament_lichess_id_when_linked CHECK ((kind = 'swiss') = (lichess_tournament_id IS NULL));
+ALTER TABLE tournament ADD CONSTRAINT tournament_lichess_id_key UNIQUE (kind, lichess_tournament_id);
@@ -0,0 +1,26 @@
+-- House fees: the fee schedule lives in Rocket config, and what it charged is fixed when a
+-- challenge or match is created so changing the schedule doesn't touch games already running.
+-- Existing rows keep what they would have paid, 1% of the pot made even.
+ALTER TABLE challenge ADD COLUMN fee BIGINT;
+UPDATE challenge SET fee = (sats + opponent_sats) / 100 - (sats + opponent_sats) / 100 % 2;
+ALTER TABLE challenge
+  ALTER COLUMN fee SET NOT NULL,
+  ADD CONSTRAINT challenge_fee_within_pot CHECK (fee >= 0 AND fee <= sats + opponent_sats);
+
+ALTER TABLE match_series ADD COLUMN fee BIGINT;
+UPDATE match_series SET fee = (sats + opponent_sats) / 100 - (sats + opponent_sats) / 100 % 2;
+ALTER TABLE match_series
+  ALTER COLUMN fee SET NOT NULL,
+  ADD CONSTRAINT match_series_fee_within_pot CHECK (fee >= 0 AND fee <= sats + opponent_sats);
+
+-- a tournament's pool isn't known until it starts, so it keeps the rule instead of an amount
+ALTER TABLE tournament
+  ADD COLUMN fee_rate_bps INT NOT NULL DEFAULT 100,
+  ADD COLUMN fee_flat BIGINT NOT NULL DEFAULT 0,
+  ADD COLUMN fee_min BIGINT NOT NULL DEFAULT 0,
+  ADD COLUMN fee_max BIGINT;
+ALTER TABLE tournament
+  ALTER COLUMN fee_rate_bps DROP DEFAULT,
+  ALTER COLUMN fee_flat DROP DEFAULT,
+  ALTER COLUMN fee_min DROP DEFAULT,
+  ADD CONSTRAINT tournament_fee_valid CHECK (fee_rate_bps BETWEEN 0 AND 10000 AND fee_flat >= 0 AND fee_min >= 0 AND (fee_max IS NULL OR fee_max >= fee_min));
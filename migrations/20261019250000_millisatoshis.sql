@@ -0,0 +1,25 @@
+-- The ledger moves to millisatoshis so fees and splits don't have to round to whole sats.
+-- Balances, transaction amounts and stored fees are msat; stakes and buy-ins stay whole sats.
+ALTER TABLE lightningchess_balance RENAME COLUMN balance TO balance_msat;
+UPDATE lightningchess_balance SET balance_msat = balance_msat * 1000;
+
+ALTER TABLE lightningchess_transaction RENAME COLUMN amount TO amount_msat;
+UPDATE lightningchess_transaction SET amount_msat = amount_msat * 1000;
+
+ALTER TABLE challenge RENAME COLUMN fee TO fee_msat;
+UPDATE challenge SET fee_msat = fee_msat * 1000;
+ALTER TABLE challenge
+  DROP CONSTRAINT challenge_fee_within_pot,
+  ADD CONSTRAINT challenge_fee_within_pot CHECK (fee_msat >= 0 AND fee_msat <= (sats + opponent_sats) * 1000);
+
+ALTER TABLE match_series RENAME COLUMN fee TO fee_msat;
+UPDATE match_series SET fee_msat = fee_msat * 1000;
+ALTER TABLE match_series
+  DROP CONSTRAINT match_series_fee_within_pot,
+  ADD CONSTRAINT match_series_fee_within_pot CHECK (fee_msat >= 0 AND fee_msat <= (sats + opponent_sats) * 1000);
+
+ALTER TABLE tournament_entry RENAME COLUMN prize TO prize_msat;
+UPDATE tournament_entry SET prize_msat = prize_msat * 1000;
+
+-- shares of a pot round down to the msat, what's left over is paid to the house as its own row
+ALTER TYPE transaction_type ADD VALUE 'rounding';
@@ -0,0 +1,17 @@
+-- Risk limits in whole sats. The global ones live in Rocket config, a row here overrides
+-- them for one user; a NULL column falls back to the global limit.
+CREATE TABLE IF NOT EXISTS user_limits (
+  user_id INT PRIMARY KEY REFERENCES users (user_id),
+  max_deposit BIGINT CONSTRAINT user_limits_max_deposit_positive CHECK (max_deposit > 0), -- a single invoice
+  daily_withdrawal BIGINT CONSTRAINT user_limits_daily_withdrawal_positive CHECK (daily_withdrawal > 0), -- over the last 24 hours
+  max_stake BIGINT CONSTRAINT user_limits_max_stake_positive CHECK (max_stake > 0), -- a single stake or buy-in
+  max_exposure BIGINT CONSTRAINT user_limits_max_exposure_positive CHECK (max_exposure > 0), -- stakes in play at once
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TRIGGER user_limits_updated_at BEFORE UPDATE ON user_limits
+  FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+-- withdrawals are summed over a day on every payment
+CREATE INDEX IF NOT EXISTS lightningchess_transaction_withdrawal_idx ON lightningchess_transaction(user_id, created_at) WHERE ttype='withdrawal';
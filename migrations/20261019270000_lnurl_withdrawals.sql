@@ -0,0 +1,23 @@
+-- One-time LNURL-withdraw links. The amount is reserved as an OPEN withdrawal when the link
+-- is made; a wallet claims the link once with an invoice for it, an unclaimed link expires
+-- and its withdrawal is canceled and refunded.
+CREATE TABLE lnurl_withdrawal (
+  id serial PRIMARY KEY,
+  -- the secret in the link, a wallet needs it to claim
+  k1 VARCHAR (64) NOT NULL UNIQUE,
+  user_id INT NOT NULL REFERENCES users (user_id),
+  transaction_id INT NOT NULL UNIQUE REFERENCES lightningchess_transaction (transaction_id),
+  amount_msat BIGINT NOT NULL CONSTRAINT lnurl_withdrawal_amount_positive CHECK (amount_msat > 0),
+  expires_at TIMESTAMPTZ NOT NULL,
+  -- set when a wallet claims it, the invoice it was paid with
+  claimed_at TIMESTAMPTZ,
+  payment_request TEXT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  CONSTRAINT lnurl_withdrawal_claimed_with_invoice CHECK ((claimed_at IS NULL) = (payment_request IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS lnurl_withdrawal_unclaimed_idx ON lnurl_withdrawal(user_id, expires_at) WHERE claimed_at IS NULL;
+
+CREATE TRIGGER lnurl_withdrawal_updated_at BEFORE UPDATE ON lnurl_withdrawal
+  FOR EACH ROW EXECUTE FUNCTION set_updated_at();
@@ -0,0 +1,3 @@
+-- The player a match game's lichess challenge was sent to, who forfeits the game
+-- if it isn't started before the deadline. NULL for games created before this.
+ALTER TABLE match_game ADD COLUMN challenged_user_id INT REFERENCES users (user_id);
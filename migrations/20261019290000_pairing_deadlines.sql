@@ -0,0 +1,8 @@
+-- A pairing's game has to start within the start deadline. The player a challenge
+-- was sent to loses if it doesn't, and if nobody sent one both players score nothing.
+ALTER TYPE pairing_result ADD VALUE 'forfeit';
+
+-- who has to accept the lichess challenge, NULL until it's sent
+ALTER TABLE tournament_pairing ADD COLUMN challenged_user_id INT REFERENCES users (user_id);
+-- when the deadline started, the pairing's created_at until a challenge is sent
+ALTER TABLE tournament_pairing ADD COLUMN waiting_since TIMESTAMPTZ;
@@ -0,0 +1,3 @@
+-- when lichess last refused to create a pairing's challenge, NULL once one is sent;
+-- the player who polls next tries again without holding up the rest of the round
+ALTER TABLE tournament_pairing ADD COLUMN send_failed_at TIMESTAMPTZ;
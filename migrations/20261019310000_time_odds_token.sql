@@ -0,0 +1,7 @@
+-- Time odds are added to lichess with the token of the player on the shorter clock.
+-- It's kept from their create or accept so the odds go on when either player polls,
+-- and cleared once they have, or once the challenge is over.
+ALTER TABLE challenge ADD COLUMN time_odds_token TEXT;
+
+-- a game that ended before its time odds could be added is void, both stakes go back in full
+ALTER TYPE transaction_type ADD VALUE 'challenge refund';
@@ -0,0 +1,5 @@
+-- Lightning address invoices are handed to anyone who asks, and are told apart from
+-- a player's own deposits by who asked for them: the requesting client's IP address.
+ALTER TABLE lightningchess_transaction ADD COLUMN lnurl_requester TEXT;
+CREATE INDEX IF NOT EXISTS lightningchess_transaction_lnurl_requester_idx
+  ON lightningchess_transaction(lnurl_requester, created_at) WHERE lnurl_requester IS NOT NULL;
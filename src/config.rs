@@ -1,5 +1,9 @@
 use rocket::{Build, Rocket};
 use crate::AppConfig;
+use crate::models::{FeeSchedule, Limits, Network};
+
+// how long a player has to accept a game's lichess challenge, in seconds
+const DEFAULT_START_DEADLINE: i64 = 15 * 60;
 
 pub async fn parse_config(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
     let fe_url: String = match rocket.figment().extract_inner::<String>("fe_url") {
@@ -13,10 +17,100 @@ pub async fn parse_config(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket
         }
     };
 
+    let lichess_url: String = match rocket.figment().extract_inner::<String>("lichess_url") {
+        Ok(value) => {
+            info!("lichess url: {value}");
+            value
+        },
+        Err(_) => "https://lichess.org".to_string()
+    };
+
+    let lnd_url: String = match rocket.figment().extract_inner::<String>("lnd_url") {
+        Ok(value) => {
+            info!("lnd url: {value}");
+            value
+        },
+        Err(_) => "https://lightningchess.m.voltageapp.io:8080".to_string()
+    };
+
+    // a broken fee schedule shouldn't quietly fall back to the default one
+    let fees: FeeSchedule = match rocket.figment().extract_inner::<FeeSchedule>("fees") {
+        Ok(value) if value.is_valid() => {
+            info!("fees: {value:?}");
+            value
+        },
+        Ok(value) => {
+            info!("error: invalid fee schedule {value:?}");
+            return Err(rocket)
+        },
+        Err(e) if e.missing() => FeeSchedule::default(),
+        Err(e) => {
+            info!("error: {e}");
+            return Err(rocket)
+        }
+    };
+
+    let limits: Limits = match rocket.figment().extract_inner::<Limits>("limits") {
+        Ok(value) if value.is_valid() => {
+            info!("limits: {value:?}");
+            value
+        },
+        Ok(value) => {
+            info!("error: invalid limits {value:?}");
+            return Err(rocket)
+        },
+        Err(e) if e.missing() => Limits::default(),
+        Err(e) => {
+            info!("error: {e}");
+            return Err(rocket)
+        }
+    };
+
+    let network: Network = match rocket.figment().extract_inner::<Network>("network") {
+        Ok(value) => {
+            info!("network: {value:?}");
+            value
+        },
+        Err(e) if e.missing() => Network::default(),
+        Err(e) => {
+            info!("error: {e}");
+            return Err(rocket)
+        }
+    };
+
+    let start_deadline: i64 = match rocket.figment().extract_inner::<i64>("start_deadline") {
+        Ok(value) if value > 0 => {
+            info!("start deadline: {value}s");
+            value
+        },
+        Ok(value) => {
+            info!("error: start_deadline {value} must be positive");
+            return Err(rocket)
+        },
+        Err(e) if e.missing() => DEFAULT_START_DEADLINE,
+        Err(e) => {
+            info!("error: {e}");
+            return Err(rocket)
+        }
+    };
+
+    // only tests run an lnurl service on this machine, in production it would let anyone make us call it
+    let allow_loopback_lnurl: bool = match rocket.figment().extract_inner::<bool>("allow_loopback_lnurl") {
+        Ok(value) => {
+            info!("allow loopback lnurl: {value}");
+            value
+        },
+        Err(e) if e.missing() => false,
+        Err(e) => {
+            info!("error: {e}");
+            return Err(rocket)
+        }
+    };
+
     match rocket.figment().extract_inner("url") {
         Ok(value) => {
             info!("api host: {value}");
-            Ok(rocket.manage(AppConfig { url: value, fe_url } ))
+            Ok(rocket.manage(AppConfig { url: value, fe_url, lichess_url, lnd_url, fees, limits, network, start_deadline, allow_loopback_lnurl } ))
         },
         Err(e) => {
             info!("error: {e}");
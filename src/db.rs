@@ -0,0 +1,93 @@
+use rocket::{Build, Rocket};
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::{Executor, Pool, Postgres};
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+// Applies any pending migrations on ignite. With run_migrations = false the
+// schema is only checked, and ignite fails if a migration is missing or has
+// been changed since it was applied.
+pub async fn run_migrations(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
+    let run_migrations = rocket.figment().extract_inner::<bool>("run_migrations").unwrap_or(true);
+    let pool = match rocket.state::<Pool<Postgres>>() {
+        Some(pool) => pool,
+        None => {
+            error!("no database pool to migrate");
+            return Err(rocket)
+        }
+    };
+
+    if run_migrations {
+        return match MIGRATOR.run(pool).await {
+            Ok(_) => {
+                info!("database migrations applied");
+                Ok(rocket)
+            },
+            Err(e) => {
+                error!("error running database migrations: {e}");
+                Err(rocket)
+            }
+        }
+    }
+
+    match check_schema(pool).await {
+        Ok(_) => Ok(rocket),
+        Err(e) => {
+            error!("database schema is out of date: {e}. Apply the migrations in migrations/ or set run_migrations = true");
+            Err(rocket)
+        }
+    }
+}
+
+async fn check_schema(pool: &Pool<Postgres>) -> Result<(), String> {
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    // fails when there is no _sqlx_migrations table yet
+    let applied = conn.list_applied_migrations().await.unwrap_or_default();
+
+    for migration in MIGRATOR.iter() {
+        match applied.iter().find(|a| a.version == migration.version) {
+            Some(a) if a.checksum != migration.checksum => {
+                return Err(format!("migration {} ({}) was modified after it was applied", migration.version, migration.description))
+            },
+            Some(_) => (),
+            None => return Err(format!("migration {} ({}) has not been applied", migration.version, migration.description))
+        }
+    }
+    Ok(())
+}
+
+// balance_not_negative is the CHECK on lightningchess_balance.balance_msat
+pub fn is_insufficient_funds(e: &sqlx::Error) -> bool {
+    is_violation(e, "balance_not_negative")
+}
+
+pub fn is_violation(e: &sqlx::Error, constraint: &str) -> bool {
+    match e.as_database_error() {
+        Some(db_error) => db_error.constraint() == Some(constraint),
+        None => false
+    }
+}
+
+// provisions a user with a zero balance the first time they are seen and
+// returns their id. Only the OAuth callback and the admin account create users.
+pub async fn ensure_user<'e, E: Executor<'e, Database = Postgres>>(executor: E, username: &str) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar("WITH u AS ( \
+            INSERT INTO users (lichess_id, username) VALUES ($1, $2) \
+            ON CONFLICT (lichess_id) DO UPDATE SET lichess_id=EXCLUDED.lichess_id \
+            RETURNING user_id \
+        ), b AS ( \
+            INSERT INTO lightningchess_balance (user_id, username, balance_msat) SELECT user_id, $2, 0 FROM u \
+            ON CONFLICT DO NOTHING \
+        ) \
+        SELECT user_id FROM u")
+        .bind(username.to_lowercase())
+        .bind(username)
+        .fetch_one(executor).await
+}
+
+// the id of a user who has logged in before, None for a name we've never seen
+pub async fn find_user_id<'e, E: Executor<'e, Database = Postgres>>(executor: E, username: &str) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar("SELECT user_id FROM users WHERE lichess_id=lower($1)")
+        .bind(username)
+        .fetch_optional(executor).await
+}
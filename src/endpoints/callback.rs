@@ -5,10 +5,13 @@ use rocket::http::{Cookie, CookieJar};
 use rocket::response::Redirect;
 use rocket::State;
 use serde_json::json;
+use sqlx::{Pool, Postgres};
+use crate::db::ensure_user;
+use crate::guard::auth::fetch_account;
 use crate::models::{AppConfig, TokenResponse};
 
 #[get("/callback?<code>")]
-pub async fn callback(code: String, app_config: &State<AppConfig>, cookies: &CookieJar<'_>) -> Option<Redirect> {
+pub async fn callback(code: String, app_config: &State<AppConfig>, pool: &State<Pool<Postgres>>, cookies: &CookieJar<'_>) -> Option<Redirect> {
     let redirect_uri = format!("{}/callback", &app_config.url);
     let code_verifier: String = match cookies.get_private("codeVerifier") {
         Some(cookie) => {
@@ -29,10 +32,10 @@ pub async fn callback(code: String, app_config: &State<AppConfig>, cookies: &Coo
         "code": code,
         "code_verifier": code_verifier
     });
-    println!("body: {}", body.to_string());
+    println!("body: {}", body);
 
-    return match Client::new()
-        .post("https://lichess.org/api/token")
+    match Client::new()
+        .post(format!("{}/api/token", &app_config.lichess_url))
         .json(&body)
         .send().await {
         Ok(res) => {
@@ -43,6 +46,7 @@ pub async fn callback(code: String, app_config: &State<AppConfig>, cookies: &Coo
                 Ok(text) => {
                     println!("text!: {}", text);
                     let token_response: TokenResponse = serde_json::from_str(&text).unwrap();
+                    provision_user(&app_config.lichess_url, &token_response.access_token, pool).await;
                     let cookie = Cookie::build("llchess_access_token", token_response.access_token)
                         .same_site(SameSite::None)
                         .secure(true)
@@ -61,4 +65,29 @@ pub async fn callback(code: String, app_config: &State<AppConfig>, cookies: &Coo
             None
         },
     }
-}
\ No newline at end of file
+}
+
+// first login creates the user and their balance, later logins pick up username changes
+async fn provision_user(lichess_url: &str, access_token: &str, pool: &Pool<Postgres>) {
+    let account = match fetch_account(lichess_url, access_token).await {
+        Some(account) => account,
+        None => {
+            println!("could not fetch account for new token");
+            return;
+        }
+    };
+    let user_id = match ensure_user(pool, &account.username).await {
+        Ok(id) => id,
+        Err(e) => {
+            println!("error provisioning user {}: {}", account.username, e);
+            return;
+        }
+    };
+    let updated = sqlx::query("UPDATE users SET username=$1, last_login=now() WHERE user_id=$2")
+        .bind(&account.username)
+        .bind(user_id)
+        .execute(pool).await;
+    if let Err(e) = updated {
+        println!("error recording login for {}: {}", account.username, e);
+    }
+}
@@ -1,12 +1,20 @@
+use rand::Rng;
 use reqwest::Client;
 use rocket::http::{Status};
 use rocket::State;
-use crate::models::{Balance, Challenge, ChallengeAcceptRequest, LichessChallenge, LichessChallengeClock, LichessChallengeResponse, Transaction, User};
+use crate::db::{find_user_id, is_insufficient_funds, is_violation};
+use crate::ledger::msat;
+use crate::lichess::clock::{creator_limit, is_valid_clock, lichess_limit, opponent_limit, speed, time_odds};
+use crate::lichess::users::{fetch_user, is_eligible};
+use crate::endpoints::limits::check_stake;
+use crate::endpoints::params::{page_size, parse_enum, parse_id, parse_time};
+use crate::models::{AppConfig, Balance, Challenge, Color, ChallengeAcceptRequest, ChallengeCounts, ChallengePage, ChallengeStatus, LichessChallenge, LichessChallengeClock, LichessChallengeResponse, Transaction, TransactionState, TransactionType, User};
 use sqlx::Postgres;
 use sqlx::Pool;
+use sqlx::types::chrono::Utc;
 
 #[post("/api/challenge", data = "<challenge_request>")]
-pub async fn create_challenge(user: User, pool: &State<Pool<Postgres>>, challenge_request: String) -> Result<String, Status> {
+pub async fn create_challenge(user: User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, challenge_request: String) -> Result<String, Status> {
     println!("challenge request!: {}", challenge_request);
     let challenge_result: Result<Challenge, serde_json::Error> = serde_json::from_str(&challenge_request);
     let challenge = match challenge_result {
@@ -17,21 +25,51 @@ pub async fn create_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
         }
     };
 
-    // only allow creation of challenge if user has enough funds
-    let balance_result = sqlx::query_as::<_,Balance>( "SELECT * FROM lightningchess_balance WHERE username=$1")
-        .bind(&user.username)
-        .fetch_one(&**pool).await;
-    match balance_result {
-        Ok(balance) => {
-            if balance.balance < 0 || balance.balance < challenge.sats.unwrap() {
-                return Err(Status::PaymentRequired)
-            }
-        },
-        Err(e) => {
-            println!("error: {}", e);
-            return Err(Status::InternalServerError)
+    if challenge.sats <= 0 {
+        return Err(Status::BadRequest)
+    }
+
+    // with time odds each side's clock has to be one lichess allows
+    let increment = challenge.increment.unwrap_or(0);
+    if !is_valid_clock(challenge.variant, creator_limit(&challenge), increment) || !is_valid_clock(challenge.variant, opponent_limit(&challenge), increment) {
+        println!("clock not allowed for {}", challenge.variant.as_str());
+        return Err(Status::BadRequest)
+    }
+
+    if challenge.opponent_sats.is_some_and(|sats| sats <= 0) {
+        return Err(Status::BadRequest)
+    }
+
+    // restrictions only make sense when anyone can accept
+    let restricted = challenge.min_rating.is_some() || challenge.max_rating.is_some() || challenge.min_account_age_days.is_some();
+    if restricted && challenge.opp_username.is_some() {
+        return Err(Status::BadRequest)
+    }
+    if let (Some(min), Some(max)) = (challenge.min_rating, challenge.max_rating) {
+        if min > max {
+            return Err(Status::BadRequest)
         }
     }
+    if challenge.min_account_age_days.is_some_and(|days| days < 0) {
+        return Err(Status::BadRequest)
+    }
+    // only the rematch endpoint links a challenge to the one it follows
+    if challenge.rematch_of.is_some() {
+        return Err(Status::BadRequest)
+    }
+
+    match escrow_challenge(&user, pool, app_config, &challenge, None).await {
+        Ok(created) => Ok(serde_json::to_string(&created).unwrap()),
+        Err(status) => Err(status)
+    }
+}
+
+// saves a new challenge and takes the creator's stake, all or nothing
+// the fee is fixed here, from the schedule for the speed lichess will play it at
+// rematch_of is the completed challenge a rematch follows, never taken from the client
+async fn escrow_challenge(user: &User, pool: &Pool<Postgres>, app_config: &AppConfig, challenge: &Challenge, rematch_of: Option<i32>) -> Result<Challenge, Status> {
+    let fee = app_config.fees.rule_for_speed(speed(lichess_limit(challenge), challenge.increment.unwrap_or(0)), Utc::now())
+        .fee_msat(msat(challenge.pot()));
 
     //create transaction
     let tx_result = pool.begin().await;
@@ -43,45 +81,64 @@ pub async fn create_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
         }
     };
 
+    check_stake(&mut tx, app_config, user.user_id, challenge.sats).await?;
+
+    // the opponent has to have logged in before, open challenges don't have one
+    let opp_user_id = match &challenge.opp_username {
+        Some(opp_username) => match find_user_id(&mut tx, opp_username).await {
+            Ok(Some(id)) => Some(id),
+            Ok(None) => return Err(Status::NotFound),
+            Err(e) => {
+                println!("error getting opponent: {}", e);
+                return Err(Status::InternalServerError)
+            }
+        },
+        None => None
+    };
+
     // save challenge to db
-    let status = "WAITING FOR ACCEPTANCE";
-    let challenge_result = sqlx::query_as::<_,Challenge>("INSERT INTO challenge (username, time_limit, opponent_time_limit, increment, color, sats, opp_username, status, expire_after) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *")
+    let status = ChallengeStatus::WaitingForAcceptance;
+    let challenge_result = sqlx::query_as::<_,Challenge>("INSERT INTO challenge (username, time_limit, opponent_time_limit, increment, color, sats, opp_username, status, expire_after, user_id, opp_user_id, min_rating, max_rating, min_account_age_days, variant, rated, opponent_sats, rematch_of, fee_msat, time_odds_token) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20) RETURNING *")
         .bind(&user.username)
-        .bind(&challenge.time_limit)
-        .bind(&challenge.opponent_time_limit)
-        .bind(&challenge.increment)
-        .bind(&challenge.color)
-        .bind(&challenge.sats)
+        .bind(challenge.time_limit)
+        .bind(challenge.opponent_time_limit)
+        .bind(challenge.increment)
+        .bind(challenge.color)
+        .bind(challenge.sats)
         .bind(&challenge.opp_username)
-        .bind(&status)
-        .bind(&challenge.expire_after)
+        .bind(status)
+        .bind(challenge.expire_after)
+        .bind(user.user_id)
+        .bind(opp_user_id)
+        .bind(challenge.min_rating)
+        .bind(challenge.max_rating)
+        .bind(challenge.min_account_age_days)
+        .bind(challenge.variant)
+        .bind(challenge.rated)
+        .bind(challenge.opponent_stake())
+        .bind(rematch_of)
+        .bind(fee)
+        .bind(time_odds_token(challenge, true, &user.access_token))
         .fetch_one(&mut tx).await;
 
-    let challenge_json_result = match challenge_result {
-        Ok(r) => {
-            Ok(serde_json::to_string(&r).unwrap())
-        },
+    let created = match challenge_result {
+        Ok(r) => r,
+        Err(e) if is_violation(&e, "challenge_rematch_of_key") => return Err(Status::Conflict),
         Err(e) => {
             println!("insert challenge error: {}", e);
             return Err(Status::InternalServerError)
         }
     };
 
-    // deduct from balance
-    let balance_result = sqlx::query_as::<_,Balance>( "UPDATE lightningchess_balance SET balance=balance - $1 WHERE username=$2 RETURNING * ")
-        .bind(challenge.sats.unwrap())
-        .bind(&user.username)
+    // deduct from balance, balance_not_negative rejects overdrafts
+    let balance_result = sqlx::query_as::<_,Balance>( "UPDATE lightningchess_balance SET balance_msat=balance_msat - $1 WHERE user_id=$2 RETURNING * ")
+        .bind(msat(challenge.sats))
+        .bind(user.user_id)
         .fetch_one(&mut tx).await;
 
     match balance_result {
-        Ok(balance) => {
-            // just another check
-            if balance.balance < 0 {
-                println!("balance is less than 0");
-                return Err(Status::InternalServerError)
-            }
-            println!("updated balance")
-        },
+        Ok(_) => println!("updated balance"),
+        Err(e) if is_insufficient_funds(&e) => return Err(Status::PaymentRequired),
         Err(e) => {
             println!("error updating balance: {}", e);
             return Err(Status::InternalServerError)
@@ -89,15 +146,20 @@ pub async fn create_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
     }
 
     // insert transaction into transaction db
-    let ttype = "create challenge";
-    let detail = format!("challenge vs {}", challenge.opp_username);
-    let state = "SETTLED";
-    let transaction_result = sqlx::query_as::<_,Transaction>("INSERT INTO lightningchess_transaction (username, ttype, detail, amount, state) VALUES ($1, $2, $3, $4, $5) RETURNING *")
+    let ttype = TransactionType::CreateChallenge;
+    let detail = match &challenge.opp_username {
+        Some(opp_username) => format!("challenge vs {}", opp_username),
+        None => "open challenge".to_string()
+    };
+    let state = TransactionState::Settled;
+    let transaction_result = sqlx::query_as::<_,Transaction>("INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, challenge_id, user_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *")
         .bind(&user.username)
         .bind(ttype)
         .bind(&detail)
-        .bind(-challenge.sats.unwrap())
+        .bind(-msat(challenge.sats))
         .bind(state)
+        .bind(created.id)
+        .bind(user.user_id)
         .fetch_one(&mut tx).await;
 
     match transaction_result {
@@ -109,10 +171,8 @@ pub async fn create_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
     }
 
     let commit_result = tx.commit().await;
-    return match commit_result {
-        Ok(_) => {
-            challenge_json_result
-        },
+    match commit_result {
+        Ok(_) => Ok(created),
         Err(e) => {
             println!("error committing: {}", e);
             Err(Status::InternalServerError)
@@ -120,8 +180,62 @@ pub async fn create_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
     }
 }
 
+// same terms with colours swapped, sent to the other player of a completed challenge
+// each player keeps their own clock and stake, so odds carry over
+#[post("/api/challenge/<challenge_id>/rematch")]
+pub async fn rematch(user: User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, challenge_id: String) -> Result<String, Status> {
+    let challenge_id_int = match challenge_id.parse::<i32>() {
+        Ok(i) => i,
+        Err(_) => return Err(Status::BadRequest)
+    };
+    let original = sqlx::query_as::<_,Challenge>( "SELECT * FROM challenge WHERE id=$1")
+        .bind(challenge_id_int)
+        .fetch_optional(&**pool).await;
+    let original = match original {
+        Ok(Some(c)) => c,
+        Ok(None) => return Err(Status::NotFound),
+        Err(e) => {
+            println!("error: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+
+    let created_original = original.user_id == user.user_id;
+    if !created_original && original.opp_user_id != Some(user.user_id) {
+        return Err(Status::Unauthorized)
+    }
+    if original.status != ChallengeStatus::Completed {
+        return Err(Status::BadRequest)
+    }
+
+    // last_color is the colour the caller played in the original game, the rematch gives them the other one
+    let (last_color, time_limit, opponent_time_limit, sats, opponent_sats, opp_username) = if created_original {
+        (original.color, original.time_limit, original.opponent_time_limit, original.sats, original.opponent_stake(), original.opp_username.clone().unwrap())
+    } else {
+        (original.color.opposite(), Some(opponent_limit(&original)), Some(creator_limit(&original)), original.opponent_stake(), original.sats, original.username.clone())
+    };
+    let challenge = Challenge {
+        color: last_color.opposite(),
+        time_limit,
+        opponent_time_limit,
+        sats,
+        opponent_sats: Some(opponent_sats),
+        opp_username: Some(opp_username),
+        // an open lobby challenge's requirements mean nothing once it's sent to one player
+        min_rating: None,
+        max_rating: None,
+        min_account_age_days: None,
+        ..original
+    };
+
+    match escrow_challenge(&user, pool, app_config, &challenge, Some(original.id)).await {
+        Ok(created) => Ok(serde_json::to_string(&created).unwrap()),
+        Err(status) => Err(status)
+    }
+}
+
 #[post("/api/accept-challenge", data = "<challenge_accept_request>")]
-pub async fn accept_challenge(user: User, pool: &State<Pool<Postgres>>, challenge_accept_request: String) -> Result<String, Status> {
+pub async fn accept_challenge(user: User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, challenge_accept_request: String) -> Result<String, Status> {
     println!("challenge_accept_request!: {}", challenge_accept_request);
     let challenge_accept_request_result: Result<ChallengeAcceptRequest, serde_json::Error> = serde_json::from_str(&challenge_accept_request);
     let challenge_accept_request = match challenge_accept_request_result {
@@ -132,63 +246,73 @@ pub async fn accept_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
         }
     };
 
-    let challenge_result = sqlx::query_as::<_,Challenge>( "SELECT * FROM challenge WHERE id=$1")
+    //create transaction
+    let tx_result = pool.begin().await;
+    let mut tx = match tx_result {
+        Ok(t) => t,
+        Err(e) => {
+            println!("error creating tx: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+
+    // locked until commit, so a concurrent accept waits and finds it accepted instead of sending a second lichess challenge
+    let challenge_result = sqlx::query_as::<_,Challenge>( "SELECT * FROM challenge WHERE id=$1 FOR UPDATE")
         .bind(challenge_accept_request.id)
-        .fetch_one(&**pool).await;
+        .fetch_one(&mut tx).await;
 
-    let challenge = match challenge_result {
+    let mut challenge = match challenge_result {
         Ok(c) => c,
         Err(e) => {
-            println!("error getting challenge in challenge accept: {}", e.as_database_error().unwrap().message());
+            println!("error getting challenge in challenge accept: {}", e);
             return Err(Status::InternalServerError)
         }
     };
 
     // only opponent can accept the challenge and challenge must be in correct status
-    if challenge.opp_username != user.username || challenge.status.as_ref().unwrap() != "WAITING FOR ACCEPTANCE" {
+    // anyone but the creator can accept an open challenge
+    let open = challenge.opp_user_id.is_none();
+    if !challenge.status.can_become(ChallengeStatus::Accepted) {
+        return Err(Status::BadRequest)
+    }
+    if open && challenge.user_id == user.user_id || !open && challenge.opp_user_id != Some(user.user_id) {
         return Err(Status::BadRequest)
     }
 
-    // only allow accept of challenge if user has enough funds
-    let balance_result = sqlx::query_as::<_,Balance>( "SELECT * FROM lightningchess_balance WHERE username=$1")
+    if open && !meets_restrictions(&challenge, &user, app_config).await {
+        return Err(Status::Forbidden)
+    }
+
+    check_stake(&mut tx, app_config, user.user_id, challenge.opponent_stake()).await?;
+
+    // the acceptor takes the challenge, an open one goes to them; expiry is checked here, by the database clock
+    let claimed = sqlx::query("UPDATE challenge SET opp_user_id=$1, opp_username=coalesce(opp_username, $2) WHERE id=$3 \
+        AND (opp_user_id IS NULL OR opp_user_id=$1) AND (expire_after IS NULL OR created_at + expire_after * INTERVAL '1 second' > now())")
+        .bind(user.user_id)
         .bind(&user.username)
-        .fetch_one(&**pool).await;
-    match balance_result {
-        Ok(balance) => {
-            if balance.balance < 0 || balance.balance < challenge.sats.unwrap() {
-                return Err(Status::PaymentRequired)
-            }
+        .bind(challenge.id)
+        .execute(&mut tx).await;
+    match claimed {
+        Ok(r) if r.rows_affected() == 1 => println!("claimed challenge {}", challenge.id),
+        Ok(_) => {
+            println!("challenge {} expired", challenge.id);
+            return Err(Status::BadRequest)
         },
         Err(e) => {
-            println!("error: {}", e);
+            println!("error claiming challenge: {}", e);
             return Err(Status::InternalServerError)
         }
     }
 
-    //create transaction
-    let tx_result = pool.begin().await;
-    let mut tx = match tx_result {
-        Ok(t) => t,
-        Err(e) => {
-            println!("error creating tx: {}", e);
-            return Err(Status::InternalServerError)
-        }
-    };
-
-    // deduct balance
-    let balance_result = sqlx::query_as::<_,Balance>( "UPDATE lightningchess_balance SET balance=balance - $1 WHERE username=$2 RETURNING *")
-        .bind(challenge.sats.unwrap())
-        .bind(&user.username)
+    // deduct balance, balance_not_negative rejects overdrafts
+    let balance_result = sqlx::query_as::<_,Balance>( "UPDATE lightningchess_balance SET balance_msat=balance_msat - $1 WHERE user_id=$2 RETURNING *")
+        .bind(msat(challenge.opponent_stake()))
+        .bind(user.user_id)
         .fetch_one(&mut tx).await;
 
     match balance_result {
-        Ok(balance) => {
-            if balance.balance < 0 {
-                println!("balance is less than 0");
-                return Err(Status::InternalServerError)
-            }
-            println!("updated balance")
-        },
+        Ok(_) => println!("updated balance"),
+        Err(e) if is_insufficient_funds(&e) => return Err(Status::PaymentRequired),
         Err(e) => {
             println!("error updating balance: {}", e);
             return Err(Status::InternalServerError)
@@ -196,15 +320,17 @@ pub async fn accept_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
     };
 
     // insert transaction into transaction db
-    let ttype = "accept challenge";
+    let ttype = TransactionType::AcceptChallenge;
     let detail = format!("challenge vs {}", challenge.username);
-    let state = "SETTLED";
-    let transaction_result = sqlx::query_as::<_,Transaction>("INSERT INTO lightningchess_transaction (username, ttype, detail, amount, state) VALUES ($1, $2, $3, $4, $5) RETURNING *")
+    let state = TransactionState::Settled;
+    let transaction_result = sqlx::query_as::<_,Transaction>("INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, challenge_id, user_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *")
         .bind(&user.username)
         .bind(ttype)
         .bind(&detail)
-        .bind(-challenge.sats.unwrap())
+        .bind(-msat(challenge.opponent_stake()))
         .bind(state)
+        .bind(challenge.id)
+        .bind(user.user_id)
         .fetch_one(&mut tx).await;
 
     match transaction_result {
@@ -215,8 +341,13 @@ pub async fn accept_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
         }
     }
 
+    // pick a random colour here so lichess and settlement agree on who is white
+    if challenge.color == Color::Random {
+        challenge.color = if rand::thread_rng().gen_bool(0.5) { Color::White } else { Color::Black };
+    }
+
     // create on lichess
-    let url = format!("https://lichess.org/api/challenge/{}", &challenge.username);
+    let url = format!("{}/api/challenge/{}", &app_config.lichess_url, &challenge.username);
     let access_token = user.access_token;
     let bearer = format!("Bearer {access_token}");
     let body = parse_to_lichess_challenge(&challenge);
@@ -250,10 +381,12 @@ pub async fn accept_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
     };
 
     // update challenge in db
-    let status = "ACCEPTED";
-    let pg_query_result = sqlx::query_as::<_,Challenge>("UPDATE challenge SET status=$1, lichess_challenge_id=$2 WHERE id=$3 RETURNING *")
+    let status = ChallengeStatus::Accepted;
+    let pg_query_result = sqlx::query_as::<_,Challenge>("UPDATE challenge SET status=$1, lichess_challenge_id=$2, color=$3, time_odds_token=coalesce(time_odds_token, $4) WHERE id=$5 RETURNING *")
         .bind(status)
         .bind(&lichess_challenge_response.challenge.id)
+        .bind(challenge.color)
+        .bind(time_odds_token(&challenge, false, &access_token))
         .bind(challenge_accept_request.id)
         .fetch_one(&mut tx).await;
 
@@ -269,7 +402,7 @@ pub async fn accept_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
 
     // commit transaction, return challenge
     let commit_result = tx.commit().await;
-    return match commit_result {
+    match commit_result {
         Ok(_) => {
             challenge_json_result
         },
@@ -280,13 +413,107 @@ pub async fn accept_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
     }
 
 }
-#[get("/api/challenges")]
-pub async fn challenges(user: User, pool: &State<Pool<Postgres>>) -> Result<String, Status> {
-    let challenges = sqlx::query_as::<_,Challenge>( "SELECT * FROM challenge WHERE username=$1 OR opp_username=$1 ORDER BY created_on DESC LIMIT 100")
-        .bind(&user.username)
+// newest first; the cursor is the id of the last challenge on the previous page
+// role is created (user is the challenger) or received, opponent is the other player's lichess username
+#[allow(clippy::too_many_arguments)]
+#[get("/api/challenges?<cursor>&<limit>&<role>&<status>&<opponent>&<from>&<to>")]
+pub async fn challenges(user: User, pool: &State<Pool<Postgres>>, cursor: Option<String>, limit: Option<String>, role: Option<String>, status: Option<String>, opponent: Option<String>, from: Option<String>, to: Option<String>) -> Result<String, Status> {
+    let cursor = parse_id("cursor", cursor)?;
+    let limit = page_size(limit)?;
+    let (created, received) = match role.as_deref() {
+        None => (true, true),
+        Some("created") => (true, false),
+        Some("received") => (false, true),
+        Some(r) => {
+            println!("bad role {}", r);
+            return Err(Status::BadRequest)
+        }
+    };
+    let status: Option<ChallengeStatus> = parse_enum("status", status)?;
+    let from = parse_time("from", from)?;
+    let to = parse_time("to", to)?;
+
+    // one extra row tells us whether there is another page
+    let challenges = sqlx::query_as::<_,Challenge>( "SELECT * FROM challenge WHERE ((user_id=$1 AND $2) OR (opp_user_id=$1 AND $3)) \
+        AND ($4::INT IS NULL OR id < $4) \
+        AND ($5::challenge_status IS NULL OR status=$5) \
+        AND ($6::VARCHAR IS NULL OR lower(CASE WHEN user_id=$1 THEN opp_username ELSE username END)=lower($6)) \
+        AND ($7::TIMESTAMPTZ IS NULL OR created_at >= $7) \
+        AND ($8::TIMESTAMPTZ IS NULL OR created_at < $8) \
+        ORDER BY id DESC LIMIT $9")
+        .bind(user.user_id)
+        .bind(created)
+        .bind(received)
+        .bind(cursor)
+        .bind(status)
+        .bind(opponent)
+        .bind(from)
+        .bind(to)
+        .bind(limit + 1)
+        .fetch_all(&**pool).await;
+    match challenges {
+        Ok(mut challenges) => {
+            let next_cursor = if challenges.len() as i64 > limit {
+                challenges.truncate(limit as usize);
+                challenges.last().map(|last| last.id)
+            } else {
+                None
+            };
+            Ok(serde_json::to_string(&ChallengePage { challenges, next_cursor }).unwrap())
+        },
+        Err(e) => {
+            println!("error: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// e.g. received WAITING FOR ACCEPTANCE is the number of incoming challenges
+#[get("/api/challenges/counts")]
+pub async fn challenge_counts(user: User, pool: &State<Pool<Postgres>>) -> Result<String, Status> {
+    let rows = sqlx::query_as::<_,(bool, ChallengeStatus, i64)>( "SELECT user_id=$1 AS created, status, COUNT(*) FROM challenge \
+        WHERE user_id=$1 OR opp_user_id=$1 GROUP BY 1, 2")
+        .bind(user.user_id)
+        .fetch_all(&**pool).await;
+    match rows {
+        Ok(rows) => {
+            let mut counts = ChallengeCounts::default();
+            for (created, status, count) in rows {
+                let by_status = if created { &mut counts.created } else { &mut counts.received };
+                by_status.insert(status, count);
+            }
+            Ok(serde_json::to_string(&counts).unwrap())
+        },
+        Err(e) => {
+            println!("error: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// open challenges anyone can accept, newest first, no login needed
+#[get("/api/lobby?<cursor>&<limit>")]
+pub async fn lobby(pool: &State<Pool<Postgres>>, cursor: Option<String>, limit: Option<String>) -> Result<String, Status> {
+    let cursor = parse_id("cursor", cursor)?;
+    let limit = page_size(limit)?;
+
+    let challenges = sqlx::query_as::<_,Challenge>( "SELECT * FROM challenge WHERE opp_user_id IS NULL AND status='WAITING FOR ACCEPTANCE' \
+        AND (expire_after IS NULL OR created_at + expire_after * INTERVAL '1 second' > now()) \
+        AND ($1::INT IS NULL OR id < $1) \
+        ORDER BY id DESC LIMIT $2")
+        .bind(cursor)
+        .bind(limit + 1)
         .fetch_all(&**pool).await;
     match challenges {
-        Ok(challenges) => Ok(serde_json::to_string(&challenges).unwrap()),
+        Ok(mut challenges) => {
+            let next_cursor = if challenges.len() as i64 > limit {
+                challenges.truncate(limit as usize);
+                challenges.last().map(|last| last.id)
+            } else {
+                None
+            };
+            Ok(serde_json::to_string(&ChallengePage { challenges, next_cursor }).unwrap())
+        },
         Err(e) => {
             println!("error: {}", e);
             Err(Status::InternalServerError)
@@ -307,7 +534,9 @@ pub async fn lookup_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
     match challenge {
         Ok(challenge) =>  {
             // only be able to look up own games
-            if challenge.username != user.username && challenge.opp_username != user.username {
+            // open challenges are public, see the lobby
+            let open = challenge.opp_user_id.is_none();
+            if !open && challenge.user_id != user.user_id && challenge.opp_user_id != Some(user.user_id) {
                 Err(Status::Unauthorized)
             } else {
                 Ok(serde_json::to_string(&challenge).unwrap())
@@ -320,20 +549,35 @@ pub async fn lookup_challenge(user: User, pool: &State<Pool<Postgres>>, challeng
     }
 }
 
+async fn meets_restrictions(challenge: &Challenge, user: &User, app_config: &AppConfig) -> bool {
+    if challenge.min_rating.is_none() && challenge.max_rating.is_none() && challenge.min_account_age_days.is_none() {
+        return true
+    }
+    match fetch_user(&app_config.lichess_url, &user.username).await {
+        Some(lichess_user) => is_eligible(challenge, &lichess_user, Utc::now()),
+        None => false
+    }
+}
+
+// the token of whoever has to add time odds on lichess, kept if it's the creator's or the acceptor's
+fn time_odds_token(challenge: &Challenge, creating: bool, access_token: &str) -> Option<String> {
+    match time_odds(challenge) {
+        Some((creator_gives, _)) if creator_gives == creating => Some(access_token.to_string()),
+        _ => None
+    }
+}
+
 fn parse_to_lichess_challenge(challenge: &Challenge) -> LichessChallenge {
-    let color = match challenge.color.as_deref() {
-        Some("white") => "black".to_string(),
-        Some("black") => "white".to_string(),
-        _ => "".to_string()
-    };
-    return LichessChallenge {
-        rated: true,
+    // the acceptor sends the lichess challenge, so they ask for the other colour
+    let color = challenge.color.opposite().as_str().to_string();
+    LichessChallenge {
+        rated: challenge.rated,
         clock: LichessChallengeClock {
-            limit: challenge.time_limit.unwrap_or(300).to_string(),
+            limit: lichess_limit(challenge).to_string(),
             increment: challenge.increment.unwrap_or(0).to_string(),
         },
         color,
-        variant: "standard".to_string(),
+        variant: challenge.variant.as_str().to_string(),
         rules: "noClaimWin".to_string(),
-    };
+    }
 }
\ No newline at end of file
@@ -0,0 +1,99 @@
+use rocket::futures::StreamExt;
+use rocket::http::{ContentType, Status};
+use rocket::response::stream::TextStream;
+use rocket::State;
+use sqlx::{Pool, Postgres};
+use crate::models::{LedgerEntry, User};
+
+const LEDGER_QUERY: &str = "SELECT t.transaction_id, t.created_at, t.ttype, t.state, t.amount_msat, t.detail, t.challenge_id, \
+    CASE WHEN t.ttype IN ('fee', 'rounding') THEN NULL WHEN c.user_id=t.user_id THEN c.opp_username WHEN c.id IS NOT NULL THEN c.username \
+        WHEN m.user_id=t.user_id THEN m.opp_username ELSE m.username END AS counterparty, \
+    (SUM(CASE WHEN t.state IN ('FAILED', 'CANCELED') THEN 0 ELSE t.amount_msat END) OVER (ORDER BY t.transaction_id))::BIGINT AS running_balance_msat \
+    FROM lightningchess_transaction t \
+    LEFT JOIN challenge c ON c.id=t.challenge_id \
+    LEFT JOIN match_series m ON m.id=t.match_id \
+    WHERE t.user_id=$1 \
+    ORDER BY t.transaction_id";
+
+const CSV_HEADER: &str = "transaction_id,created_at,ttype,state,amount_msat,detail,counterparty,challenge_id,running_balance_msat\n";
+
+// rows are written as they come off the cursor so the whole history is never held in memory
+#[get("/api/transactions/export?<format>")]
+pub async fn export_transactions(user: User, pool: &State<Pool<Postgres>>, format: Option<String>) -> Result<(ContentType, TextStream![String]), Status> {
+    let csv = match format.as_deref() {
+        Some("csv") => true,
+        Some("json") | None => false,
+        Some(f) => {
+            println!("unknown export format {}", f);
+            return Err(Status::BadRequest)
+        }
+    };
+    let content_type = if csv { ContentType::CSV } else { ContentType::JSON };
+    let pool = pool.inner().clone();
+    let user_id = user.user_id;
+
+    let stream = TextStream! {
+        yield if csv { CSV_HEADER.to_string() } else { "[".to_string() };
+        let mut rows = sqlx::query_as::<_, LedgerEntry>(LEDGER_QUERY)
+            .bind(user_id)
+            .fetch(&pool);
+        let mut first = true;
+        while let Some(row) = rows.next().await {
+            let entry = match row {
+                Ok(entry) => entry,
+                Err(e) => {
+                    // the status line has already gone out, so the export is just cut short
+                    println!("error exporting transactions for user {}: {}", user_id, e);
+                    break;
+                }
+            };
+            if csv {
+                yield csv_line(&entry);
+            } else {
+                let separator = if first { "" } else { "," };
+                yield format!("{}{}", separator, serde_json::to_string(&entry).unwrap());
+            }
+            first = false;
+        }
+        if !csv {
+            yield "]".to_string();
+        }
+    };
+    Ok((content_type, stream))
+}
+
+fn csv_line(entry: &LedgerEntry) -> String {
+    // enums are written the same way as in the json export
+    let ttype = serde_json::to_value(entry.ttype).unwrap();
+    let state = serde_json::to_value(entry.state).unwrap();
+    let fields = [
+        entry.transaction_id.to_string(),
+        entry.created_at.to_rfc3339(),
+        ttype.as_str().unwrap_or_default().to_string(),
+        state.as_str().unwrap_or_default().to_string(),
+        entry.amount_msat.to_string(),
+        neutralize(&entry.detail),
+        neutralize(entry.counterparty.as_deref().unwrap_or_default()),
+        entry.challenge_id.map(|id| id.to_string()).unwrap_or_default(),
+        entry.running_balance_msat.to_string(),
+    ];
+    let escaped: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+    format!("{}\n", escaped.join(","))
+}
+
+// detail and counterparty come from users, so a spreadsheet mustn't read them as a formula
+fn neutralize(text: &str) -> String {
+    if text.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,126 @@
+use rocket::http::Status;
+use rocket::State;
+use sqlx::{Executor, Pool, Postgres};
+use crate::ledger::msat;
+use crate::models::{AppConfig, Limits, LimitsResponse, User};
+
+// stakes taken from a balance and not yet paid back: challenges and matches until they're
+// settled, the opponent's side once they've accepted, and entries to tournaments still going
+const EXPOSURE_QUERY: &str = "SELECT (\
+    COALESCE((SELECT SUM(CASE WHEN user_id=$1 THEN sats ELSE opponent_sats END) FROM challenge \
+        WHERE (user_id=$1 AND status IN ('WAITING FOR ACCEPTANCE', 'ACCEPTED')) OR (opp_user_id=$1 AND status='ACCEPTED')), 0) + \
+    COALESCE((SELECT SUM(CASE WHEN user_id=$1 THEN sats ELSE opponent_sats END) FROM match_series \
+        WHERE (user_id=$1 AND status IN ('WAITING FOR ACCEPTANCE', 'ACCEPTED')) OR (opp_user_id=$1 AND status='ACCEPTED')), 0) + \
+    COALESCE((SELECT SUM(t.buy_in) FROM tournament_entry e JOIN tournament t ON t.id=e.tournament_id \
+        WHERE e.user_id=$1 AND t.status IN ('open', 'running')), 0))::BIGINT";
+
+// withdrawals that left or may still leave the node
+const WITHDRAWN_QUERY: &str = "SELECT COALESCE(-SUM(amount_msat), 0)::BIGINT FROM lightningchess_transaction \
+    WHERE user_id=$1 AND ttype='withdrawal' AND state NOT IN ('FAILED', 'CANCELED') AND created_at > now() - INTERVAL '1 day'";
+
+#[get("/api/limits")]
+pub async fn limits(user: User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>) -> Result<String, Status> {
+    let limits = user_limits(&**pool, app_config, user.user_id).await?;
+    let withdrawn_msat = sqlx::query_scalar::<_,i64>(WITHDRAWN_QUERY)
+        .bind(user.user_id)
+        .fetch_one(&**pool).await;
+    let exposure = sqlx::query_scalar::<_,i64>(EXPOSURE_QUERY)
+        .bind(user.user_id)
+        .fetch_one(&**pool).await;
+    match (withdrawn_msat, exposure) {
+        (Ok(withdrawn_msat), Ok(exposure)) => Ok(serde_json::to_string(&LimitsResponse { limits, withdrawn_msat, exposure }).unwrap()),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("error getting limits usage: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// the global limits with the user's own on top
+pub async fn user_limits<'e, E: Executor<'e, Database = Postgres>>(executor: E, app_config: &AppConfig, user_id: i32) -> Result<Limits, Status> {
+    let own = sqlx::query_as::<_,Limits>("SELECT * FROM user_limits WHERE user_id=$1")
+        .bind(user_id)
+        .fetch_optional(executor).await;
+    match own {
+        Ok(own) => Ok(own.unwrap_or_default().or(app_config.limits)),
+        Err(e) => {
+            println!("error getting limits for user {}: {}", user_id, e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// the balance row lock keeps two stakes or withdrawals by the same user from both passing a check
+async fn lock_balance(tx: &mut sqlx::Transaction<'_, Postgres>, user_id: i32) -> Result<(), Status> {
+    let locked = sqlx::query("SELECT 1 FROM lightningchess_balance WHERE user_id=$1 FOR UPDATE")
+        .bind(user_id)
+        .execute(&mut *tx).await;
+    match locked {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            println!("error locking balance for user {}: {}", user_id, e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+pub async fn check_deposit(pool: &Pool<Postgres>, app_config: &AppConfig, user_id: i32, amount_msat: i64) -> Result<(), Status> {
+    let limits = user_limits(pool, app_config, user_id).await?;
+    if limits.max_deposit.is_some_and(|max| amount_msat > msat(max)) {
+        println!("deposit of {} msat over the limit for user {}", amount_msat, user_id);
+        return Err(Status::Forbidden)
+    }
+    Ok(())
+}
+
+// call before taking the stake, in the transaction that takes it
+pub async fn check_stake(tx: &mut sqlx::Transaction<'_, Postgres>, app_config: &AppConfig, user_id: i32, sats: i64) -> Result<(), Status> {
+    lock_balance(tx, user_id).await?;
+    let limits = user_limits(&mut *tx, app_config, user_id).await?;
+    if limits.max_stake.is_some_and(|max| sats > max) {
+        println!("stake of {} over the limit for user {}", sats, user_id);
+        return Err(Status::Forbidden)
+    }
+    let max_exposure = match limits.max_exposure {
+        Some(max) => max,
+        None => return Ok(())
+    };
+    let exposure = sqlx::query_scalar::<_,i64>(EXPOSURE_QUERY)
+        .bind(user_id)
+        .fetch_one(&mut *tx).await;
+    match exposure {
+        Ok(exposure) if exposure + sats > max_exposure => {
+            println!("stake of {} would put user {} over their exposure limit, {} in play", sats, user_id, exposure);
+            Err(Status::Forbidden)
+        },
+        Ok(_) => Ok(()),
+        Err(e) => {
+            println!("error getting exposure for user {}: {}", user_id, e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// call before reserving the withdrawal, in the transaction that reserves it
+pub async fn check_withdrawal(tx: &mut sqlx::Transaction<'_, Postgres>, app_config: &AppConfig, user_id: i32, amount_msat: i64) -> Result<(), Status> {
+    lock_balance(tx, user_id).await?;
+    let limits = user_limits(&mut *tx, app_config, user_id).await?;
+    let daily = match limits.daily_withdrawal {
+        Some(daily) => daily,
+        None => return Ok(())
+    };
+    let withdrawn = sqlx::query_scalar::<_,i64>(WITHDRAWN_QUERY)
+        .bind(user_id)
+        .fetch_one(&mut *tx).await;
+    match withdrawn {
+        Ok(withdrawn) if withdrawn + amount_msat > msat(daily) => {
+            println!("withdrawal of {} msat would put user {} over their daily limit, {} msat withdrawn", amount_msat, user_id, withdrawn);
+            Err(Status::Forbidden)
+        },
+        Ok(_) => Ok(()),
+        Err(e) => {
+            println!("error getting withdrawals for user {}: {}", user_id, e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use bech32::ToBase32;
+use rand::Rng;
+use reqwest::Url;
+use rocket::http::{ContentType, Status};
+use rocket::State;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use sqlx::{Pool, Postgres};
+use chrono::Duration;
+use sqlx::types::chrono::Utc;
+use crate::db::is_insufficient_funds;
+use crate::endpoints::limits::{check_withdrawal, user_limits};
+use crate::endpoints::money::{create_invoice, finish_withdrawal};
+use crate::ledger::msat;
+use crate::lightning::bolt11;
+use crate::models::{AppConfig, LnurlPayInvoice, LnurlPayRequest, LnurlStatus, LnurlWithdrawal, LnurlWithdrawLink, LnurlWithdrawLinkRequest, LnurlWithdrawRequest, Transaction, TransactionState, TransactionType, User};
+
+// long enough to find a wallet and scan the QR code
+const WITHDRAW_LINK_MINUTES: i64 = 10;
+
+// what a lightning address takes without a max_deposit limit, 1 sat to 1 btc
+const MIN_SENDABLE_MSAT: i64 = 1000;
+const MAX_SENDABLE_MSAT: i64 = 100_000_000_000;
+
+// anyone can call a lightning address's callback, so each requester only has this many
+// unpaid invoices across every address at once; lnd expires them after 30 minutes
+const MAX_OPEN_PAY_INVOICES: i64 = 5;
+
+// every user's lightning address, username@ the api's host (LUD-16)
+#[get("/.well-known/lnurlp/<username>")]
+pub async fn pay_request(pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, username: String) -> (ContentType, String) {
+    let user = match find_user(pool, &username).await {
+        Ok(u) => u,
+        Err(reason) => return lnurl_error(reason)
+    };
+    let max_sendable = match user_limits(&**pool, app_config, user.user_id).await {
+        Ok(limits) => limits.max_deposit.map(msat).unwrap_or(MAX_SENDABLE_MSAT),
+        Err(_) => return lnurl_error("internal error")
+    };
+    (ContentType::JSON, serde_json::to_string(&LnurlPayRequest {
+        tag: "payRequest".to_string(),
+        callback: format!("{}/api/lnurlp/{}/callback", app_config.url, user.username.to_lowercase()),
+        min_sendable: MIN_SENDABLE_MSAT,
+        max_sendable,
+        metadata: pay_metadata(app_config, &user.username)
+    }).unwrap())
+}
+
+// a deposit invoice for the amount a wallet asks for, committing to the address's metadata
+#[get("/api/lnurlp/<username>/callback?<amount>")]
+pub async fn pay_callback(pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, client_ip: Option<IpAddr>, username: String, amount: Option<String>) -> (ContentType, String) {
+    let user = match find_user(pool, &username).await {
+        Ok(u) => u,
+        Err(reason) => return lnurl_error(reason)
+    };
+    let amount_msat = match amount.and_then(|a| a.parse::<i64>().ok()) {
+        Some(a) if (MIN_SENDABLE_MSAT..=MAX_SENDABLE_MSAT).contains(&a) => a,
+        _ => return lnurl_error("amount out of range")
+    };
+    // clients without an address share one allowance
+    let requester = client_ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string());
+
+    let unpaid = sqlx::query_scalar::<_,i64>("SELECT count(*) FROM lightningchess_transaction \
+        WHERE lnurl_requester=$1 AND state='OPEN' AND created_at > now() - INTERVAL '30 minutes'")
+        .bind(&requester)
+        .fetch_one(&**pool).await;
+    match unpaid {
+        Ok(count) if count >= MAX_OPEN_PAY_INVOICES => {
+            println!("{} has {} unpaid lightning address invoices", requester, count);
+            return lnurl_error("too many unpaid invoices, try again later")
+        },
+        Ok(_) => (),
+        Err(e) => {
+            println!("error counting unpaid invoices for {}: {}", requester, e);
+            return lnurl_error("internal error")
+        }
+    }
+
+    let metadata = pay_metadata(app_config, &user.username);
+    let description_hash = Sha256::digest(metadata.as_bytes());
+    let memo = format!("lightning address payment to {} on lightningchess.io", &user.username);
+    match create_invoice(pool, app_config, &user, amount_msat, &memo, Some(&description_hash), Some(&requester)).await {
+        Ok(invoice) => (ContentType::JSON, serde_json::to_string(&LnurlPayInvoice { pr: invoice.payment_request.unwrap_or_default(), routes: vec![] }).unwrap()),
+        Err(status) if status == Status::Forbidden => lnurl_error("amount over the deposit limit"),
+        Err(_) => lnurl_error("unable to create invoice")
+    }
+}
+
+// lnurl errors are a 200 with an ERROR status, as json like every other lnurl response
+fn lnurl_error(reason: &str) -> (ContentType, String) {
+    (ContentType::JSON, serde_json::to_string(&LnurlStatus::error(reason)).unwrap())
+}
+
+// the invoice's description_hash is the sha256 of this, so it has to come out the same every time
+fn pay_metadata(app_config: &AppConfig, username: &str) -> String {
+    let host = Url::parse(&app_config.url).ok()
+        .and_then(|url| url.host_str().map(|host| match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string()
+        }))
+        .unwrap_or_default();
+    serde_json::to_string(&[
+        ["text/plain", &format!("fund {} on lightningchess.io", username)],
+        ["text/identifier", &format!("{}@{}", username.to_lowercase(), host)]
+    ]).unwrap()
+}
+
+async fn find_user(pool: &Pool<Postgres>, username: &str) -> Result<User, &'static str> {
+    let user = sqlx::query_as::<_,(i32, String)>("SELECT user_id, username FROM users WHERE lichess_id=lower($1)")
+        .bind(username)
+        .fetch_optional(pool).await;
+    match user {
+        Ok(Some((user_id, username))) => Ok(User { access_token: String::new(), username, user_id }),
+        Ok(None) => Err("unknown user"),
+        Err(e) => {
+            println!("error getting user {}: {}", username, e);
+            Err("internal error")
+        }
+    }
+}
+
+#[post("/api/withdraw/lnurl", data = "<link_request_str>")]
+pub async fn create_withdraw_link(user: User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, link_request_str: String) -> Result<String, Status> {
+    println!("withdraw link request: {}", link_request_str);
+    let link_request: LnurlWithdrawLinkRequest = match serde_json::from_str(&link_request_str) {
+        Ok(r) => r,
+        Err(e) => {
+            println!("error: {}", e);
+            return Err(Status::BadRequest)
+        }
+    };
+    let amount_msat = match (link_request.sats, link_request.msat) {
+        (Some(sats), None) => msat(sats),
+        (None, Some(value_msat)) => value_msat,
+        _ => return Err(Status::BadRequest)
+    };
+    if amount_msat <= 0 {
+        return Err(Status::BadRequest)
+    }
+
+    let k1 = hex::encode(rand::thread_rng().gen::<[u8; 32]>());
+    let mut tx = match pool.begin().await {
+        Ok(t) => t,
+        Err(e) => {
+            println!("error creating tx: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+    check_withdrawal(&mut tx, app_config, user.user_id, amount_msat).await?;
+
+    // the amount is reserved as a withdrawal now, paid when a wallet claims the link and refunded if none does
+    let withdrawal = sqlx::query_as::<_,Transaction>("INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, user_id) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *")
+        .bind(&user.username)
+        .bind(TransactionType::Withdrawal)
+        .bind("lnurl-withdraw")
+        .bind(-amount_msat)
+        .bind(TransactionState::Open)
+        .bind(user.user_id)
+        .fetch_one(&mut tx).await;
+    let withdrawal = match withdrawal {
+        Ok(t) => t,
+        Err(e) => {
+            println!("error inserting withdrawal: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+
+    // balance_not_negative rejects overdrafts
+    let reserved = sqlx::query("UPDATE lightningchess_balance SET balance_msat=balance_msat - $1 WHERE user_id=$2")
+        .bind(amount_msat)
+        .bind(user.user_id)
+        .execute(&mut tx).await;
+    match reserved {
+        Ok(r) if r.rows_affected() == 1 => println!("reserved withdrawal link"),
+        Ok(_) => return Err(Status::PaymentRequired),
+        Err(e) if is_insufficient_funds(&e) => return Err(Status::PaymentRequired),
+        Err(e) => {
+            println!("error reserving withdrawal link: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    }
+
+    let link = sqlx::query_as::<_,LnurlWithdrawal>("INSERT INTO lnurl_withdrawal (k1, user_id, transaction_id, amount_msat, expires_at) VALUES ($1, $2, $3, $4, $5) RETURNING *")
+        .bind(&k1)
+        .bind(user.user_id)
+        .bind(withdrawal.transaction_id)
+        .bind(amount_msat)
+        .bind(Utc::now() + Duration::minutes(WITHDRAW_LINK_MINUTES))
+        .fetch_one(&mut tx).await;
+    let link = match link {
+        Ok(l) => l,
+        Err(e) => {
+            println!("error inserting withdrawal link: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+
+    if let Err(e) = tx.commit().await {
+        println!("error committing: {}", e);
+        return Err(Status::InternalServerError)
+    }
+
+    let url = format!("{}/api/withdraw/lnurl/{}", app_config.url, k1);
+    let lnurl = bech32::encode("lnurl", url.as_bytes().to_base32(), bech32::Variant::Bech32).unwrap().to_uppercase();
+    Ok(serde_json::to_string(&LnurlWithdrawLink {
+        lnurl,
+        transaction_id: link.transaction_id,
+        amount_msat: link.amount_msat,
+        expires_at: link.expires_at
+    }).unwrap())
+}
+
+// what a wallet finds behind the link, lnurl errors are a 200 with an ERROR status
+#[get("/api/withdraw/lnurl/<k1>")]
+pub async fn withdraw_request(pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, k1: String) -> (ContentType, String) {
+    let link = match claimable_link(pool, &k1).await {
+        Ok(l) => l,
+        Err(reason) => return lnurl_error(reason)
+    };
+    (ContentType::JSON, serde_json::to_string(&LnurlWithdrawRequest {
+        tag: "withdrawRequest".to_string(),
+        callback: format!("{}/api/withdraw/lnurl/callback", app_config.url),
+        k1: link.k1,
+        default_description: "withdrawal from lightningchess.io".to_string(),
+        min_withdrawable: link.amount_msat,
+        max_withdrawable: link.amount_msat
+    }).unwrap())
+}
+
+// the wallet's invoice for the link, paid if it's for the amount reserved
+#[get("/api/withdraw/lnurl/callback?<k1>&<pr>")]
+pub async fn withdraw_callback(pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, k1: String, pr: Option<String>) -> (ContentType, String) {
+    let pr = match pr {
+        Some(pr) => pr,
+        None => return lnurl_error("missing invoice")
+    };
+    let status = match pay_withdraw_link(pool, app_config, &k1, &pr).await {
+        Ok(_) => LnurlStatus::ok(),
+        Err(reason) => LnurlStatus::error(reason)
+    };
+    (ContentType::JSON, serde_json::to_string(&status).unwrap())
+}
+
+async fn pay_withdraw_link(pool: &Pool<Postgres>, app_config: &AppConfig, k1: &str, pr: &str) -> Result<(), &'static str> {
+    let link = claimable_link(pool, k1).await?;
+    let decoded = match bolt11::decode(pr, app_config.network, Utc::now()) {
+        Ok(d) => d,
+        Err(e) => return Err(e.as_str())
+    };
+    // a zero-amount invoice won't do, the wallet has to ask for exactly what's reserved
+    if decoded.amount_msat != Some(link.amount_msat) {
+        println!("invoice for {:?} msat, link is for {}", decoded.amount_msat, link.amount_msat);
+        return Err("invoice amount doesn't match")
+    }
+
+    // claimed and committed before paying, so a second callback can't pay it again
+    // clock_timestamp so a claim that waited on an expiry refund's lock finds the link expired
+    let mut tx = match pool.begin().await {
+        Ok(t) => t,
+        Err(e) => {
+            println!("error creating tx: {}", e);
+            return Err("internal error")
+        }
+    };
+    let claimed = sqlx::query("UPDATE lnurl_withdrawal SET claimed_at=now(), payment_request=$1 WHERE id=$2 AND claimed_at IS NULL AND expires_at > clock_timestamp()")
+        .bind(pr)
+        .bind(link.id)
+        .execute(&mut tx).await;
+    match claimed {
+        Ok(r) if r.rows_affected() == 1 => println!("claimed withdrawal link {}", link.id),
+        Ok(_) => return Err("link already used or expired"),
+        Err(e) => {
+            println!("error claiming withdrawal link: {}", e);
+            return Err("internal error")
+        }
+    }
+    let updated = sqlx::query("UPDATE lightningchess_transaction SET payment_hash=$1, payment_request=$2 WHERE transaction_id=$3")
+        .bind(&decoded.payment_hash)
+        .bind(pr)
+        .bind(link.transaction_id)
+        .execute(&mut tx).await;
+    if let Err(e) = updated {
+        println!("error updating withdrawal: {}", e);
+        return Err("internal error")
+    }
+    if let Err(e) = tx.commit().await {
+        println!("error committing: {}", e);
+        return Err("internal error")
+    }
+
+    // the wallet is answered OK now (LUD-03), the payment settles or refunds the claimed link on its own
+    let pool = pool.clone();
+    let lnd_url = app_config.lnd_url.clone();
+    let pr = pr.to_string();
+    tokio::spawn(async move {
+        match finish_withdrawal(&pool, &lnd_url, link.user_id, link.transaction_id, link.amount_msat, &pr, None).await {
+            Ok(state) => println!("withdrawal link {} payment {:?}", link.id, state),
+            Err(_) => println!("withdrawal link {} payment not confirmed, left for a balance poll to look up", link.id)
+        }
+    });
+    Ok(())
+}
+
+async fn claimable_link(pool: &Pool<Postgres>, k1: &str) -> Result<LnurlWithdrawal, &'static str> {
+    let link = sqlx::query_as::<_,LnurlWithdrawal>("SELECT * FROM lnurl_withdrawal WHERE k1=$1")
+        .bind(k1)
+        .fetch_optional(pool).await;
+    match link {
+        Ok(Some(l)) if l.claimed_at.is_some() => Err("link already used"),
+        Ok(Some(l)) if l.expires_at <= Utc::now() => Err("link expired"),
+        Ok(Some(l)) => Ok(l),
+        Ok(None) => Err("unknown link"),
+        Err(e) => {
+            println!("error getting withdrawal link: {}", e);
+            Err("internal error")
+        }
+    }
+}
+
+// links nobody claimed in time give their reserved amount back
+pub async fn check_expired_withdraw_links_and_update(user: &User, pool: &Pool<Postgres>) {
+    let mut tx = match pool.begin().await {
+        Ok(t) => t,
+        Err(e) => return println!("error creating tx: {}", e)
+    };
+    // the links are locked against a claim at the last moment, the state guard keeps a refund from happening twice
+    let canceled = sqlx::query_scalar::<_,i64>("WITH expired AS (SELECT transaction_id, amount_msat FROM lnurl_withdrawal \
+        WHERE user_id=$1 AND claimed_at IS NULL AND expires_at <= now() FOR UPDATE) \
+        UPDATE lightningchess_transaction t SET state='CANCELED' FROM expired e WHERE t.transaction_id=e.transaction_id AND t.state='OPEN' \
+        RETURNING e.amount_msat")
+        .bind(user.user_id)
+        .fetch_all(&mut tx).await;
+    let refund: i64 = match canceled {
+        Ok(amounts) if amounts.is_empty() => return,
+        Ok(amounts) => amounts.iter().sum(),
+        Err(e) => return println!("error canceling expired withdrawal links: {}", e)
+    };
+    let refunded = sqlx::query("UPDATE lightningchess_balance SET balance_msat=balance_msat + $1 WHERE user_id=$2")
+        .bind(refund)
+        .bind(user.user_id)
+        .execute(&mut tx).await;
+    if let Err(e) = refunded {
+        return println!("error refunding expired withdrawal links: {}", e)
+    }
+    match tx.commit().await {
+        Ok(_) => println!("refunded {} msat of expired withdrawal links to {}", refund, user.username),
+        Err(e) => println!("error committing: {}", e)
+    }
+}
@@ -18,7 +18,7 @@ pub fn login(app_config: &State<AppConfig>, cookies: &CookieJar<'_>) -> Redirect
         .collect();
     let verifier = base64::encode_config(&rand, base64::URL_SAFE_NO_PAD);
     let digest = Sha256::digest(verifier.as_bytes());
-    let challenge = base64::encode_config(&digest, base64::URL_SAFE_NO_PAD);
+    let challenge = base64::encode_config(digest, base64::URL_SAFE_NO_PAD);
 
     // add verifier to private cookie
     let cookie = Cookie::build("codeVerifier", verifier)
@@ -28,12 +28,12 @@ pub fn login(app_config: &State<AppConfig>, cookies: &CookieJar<'_>) -> Redirect
         .finish();
     cookies.add_private(cookie);
 
-    Redirect::to(format!("https://lichess.org/oauth?\
+    Redirect::to(format!("{}/oauth?\
        response_type=code&\
        client_id=lightningchess&\
        redirect_uri={redirect_uri}&\
        scope=preference:read%20challenge:write&\
        code_challenge_method=S256&\
-       code_challenge={challenge}")
+       code_challenge={challenge}", &app_config.lichess_url)
     )
 }
\ No newline at end of file
@@ -0,0 +1,590 @@
+use std::env;
+use rand::Rng;
+use rocket::http::Status;
+use rocket::State;
+use sqlx::{Pool, Postgres};
+use chrono::Duration;
+use sqlx::types::chrono::Utc;
+use crate::db::{ensure_user, find_user_id, is_insufficient_funds};
+use crate::endpoints::limits::check_stake;
+use crate::ledger::{msat, split};
+use crate::lichess::clock::{is_valid_clock, speed, DEFAULT_TIME_LIMIT};
+use crate::lichess::games::{create_challenge, export_game};
+use crate::models::{AppConfig, Balance, ChallengeStatus, Color, GameOutcome, LichessChallenge, LichessChallengeClock, LichessExportGameResponse, MatchDetail, MatchFormat, MatchGame, MatchSeries, Tiebreak, TransactionState, TransactionType, User};
+
+// the most games a best of match can be scheduled over, or wins a first to match can ask for
+pub const MAX_MATCH_TARGET: i32 = 15;
+
+// what happens after the games played so far
+enum Standing {
+    Play { armageddon: bool },
+    Won { creator: bool },
+    Split
+}
+
+#[post("/api/match", data = "<match_request>")]
+pub async fn create_match(user: User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, match_request: String) -> Result<String, Status> {
+    println!("match request!: {}", match_request);
+    let series: MatchSeries = match serde_json::from_str(&match_request) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("error: {}", e);
+            return Err(Status::BadRequest)
+        }
+    };
+
+    if series.sats <= 0 || series.opponent_sats.is_some_and(|sats| sats <= 0) {
+        return Err(Status::BadRequest)
+    }
+    if !(1..=MAX_MATCH_TARGET).contains(&series.target) {
+        return Err(Status::BadRequest)
+    }
+    if series.opp_username.eq_ignore_ascii_case(&user.username) {
+        return Err(Status::BadRequest)
+    }
+    let time_limit = series.time_limit.unwrap_or(DEFAULT_TIME_LIMIT);
+    let increment = series.increment.unwrap_or(0);
+    if !is_valid_clock(series.variant, time_limit, increment) {
+        println!("clock not allowed for {}", series.variant.as_str());
+        return Err(Status::BadRequest)
+    }
+
+    // fixed now so a later change to the schedule doesn't touch the match
+    let fee = app_config.fees.rule_for_speed(speed(time_limit, increment), Utc::now()).fee_msat(msat(series.pot()));
+
+    let mut tx = match pool.begin().await {
+        Ok(t) => t,
+        Err(e) => {
+            println!("error: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+    check_stake(&mut tx, app_config, user.user_id, series.sats).await?;
+
+    // the opponent has to have logged in before
+    let opp_user_id = match find_user_id(&mut tx, &series.opp_username).await {
+        Ok(Some(id)) => id,
+        Ok(None) => return Err(Status::NotFound),
+        Err(e) => {
+            println!("error getting opponent: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+
+    let created = sqlx::query_as::<_,MatchSeries>("INSERT INTO match_series (user_id, username, opp_user_id, opp_username, format, target, tiebreak, sats, opponent_sats, time_limit, increment, variant, rated, color, fee_msat) \
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) RETURNING *")
+        .bind(user.user_id)
+        .bind(&user.username)
+        .bind(opp_user_id)
+        .bind(&series.opp_username)
+        .bind(series.format)
+        .bind(series.target)
+        .bind(series.tiebreak)
+        .bind(series.sats)
+        .bind(series.opponent_stake())
+        .bind(time_limit)
+        .bind(increment)
+        .bind(series.variant)
+        .bind(series.rated)
+        .bind(series.color)
+        .bind(fee)
+        .fetch_one(&mut tx).await;
+    let created = match created {
+        Ok(m) => m,
+        Err(e) => {
+            println!("insert match error: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+
+    // deduct from balance, balance_not_negative rejects overdrafts
+    let balance_result = sqlx::query_as::<_,Balance>( "UPDATE lightningchess_balance SET balance_msat=balance_msat - $1 WHERE user_id=$2 RETURNING *")
+        .bind(msat(created.sats))
+        .bind(user.user_id)
+        .fetch_one(&mut tx).await;
+    match balance_result {
+        Ok(_) => println!("updated balance"),
+        Err(e) if is_insufficient_funds(&e) => return Err(Status::PaymentRequired),
+        Err(e) => {
+            println!("error updating balance: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    }
+
+    let detail = format!("match vs {}", created.opp_username);
+    match insert_transaction(&mut tx, user.user_id, &user.username, TransactionType::CreateMatch, &detail, -msat(created.sats), created.id).await {
+        Ok(_) => println!("successfully inserted transaction"),
+        Err(e) => {
+            println!("error inserting transaction: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_) => Ok(serde_json::to_string(&created).unwrap()),
+        Err(e) => {
+            println!("error committing: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// takes the opponent's stake and sends the first game to lichess
+#[post("/api/match/<match_id>/accept")]
+pub async fn accept_match(user: User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, match_id: String) -> Result<String, Status> {
+    let match_id_int = match match_id.parse::<i32>() {
+        Ok(i) => i,
+        Err(_) => return Err(Status::BadRequest)
+    };
+    let series = match fetch_match(pool, match_id_int).await? {
+        Some(m) => m,
+        None => return Err(Status::NotFound)
+    };
+    if series.opp_user_id != user.user_id || !series.status.can_become(ChallengeStatus::Accepted) {
+        return Err(Status::BadRequest)
+    }
+
+    let mut tx = match pool.begin().await {
+        Ok(t) => t,
+        Err(e) => {
+            println!("error creating tx: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+    check_stake(&mut tx, app_config, user.user_id, series.opponent_stake()).await?;
+
+    // pick a random colour here so lichess and settlement agree on who is white
+    let color = match series.color {
+        Color::Random => if rand::thread_rng().gen_bool(0.5) { Color::White } else { Color::Black },
+        c => c
+    };
+    // the row lock makes a second accept wait and find it already accepted
+    let accepted = sqlx::query_as::<_,MatchSeries>("UPDATE match_series SET status=$1, color=$2 WHERE id=$3 AND status='WAITING FOR ACCEPTANCE' RETURNING *")
+        .bind(ChallengeStatus::Accepted)
+        .bind(color)
+        .bind(series.id)
+        .fetch_optional(&mut tx).await;
+    let accepted = match accepted {
+        Ok(Some(m)) => m,
+        Ok(None) => return Err(Status::Conflict),
+        Err(e) => {
+            println!("error accepting match: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+
+    let balance_result = sqlx::query_as::<_,Balance>( "UPDATE lightningchess_balance SET balance_msat=balance_msat - $1 WHERE user_id=$2 RETURNING *")
+        .bind(msat(accepted.opponent_stake()))
+        .bind(user.user_id)
+        .fetch_one(&mut tx).await;
+    match balance_result {
+        Ok(_) => println!("updated balance"),
+        Err(e) if is_insufficient_funds(&e) => return Err(Status::PaymentRequired),
+        Err(e) => {
+            println!("error updating balance: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    }
+
+    let detail = format!("match vs {}", accepted.username);
+    match insert_transaction(&mut tx, user.user_id, &user.username, TransactionType::AcceptMatch, &detail, -msat(accepted.opponent_stake()), accepted.id).await {
+        Ok(_) => println!("successfully inserted transaction"),
+        Err(e) => {
+            println!("error inserting transaction: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    }
+
+    if !start_game(&mut tx, &user, app_config, &accepted, None, false).await {
+        return Err(Status::InternalServerError)
+    }
+
+    match tx.commit().await {
+        Ok(_) => Ok(serde_json::to_string(&accepted).unwrap()),
+        Err(e) => {
+            println!("error committing: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// the match with its games and the running score, players only
+#[get("/api/match/<match_id>")]
+pub async fn lookup_match(user: User, pool: &State<Pool<Postgres>>, match_id: String) -> Result<String, Status> {
+    let match_id_int = match match_id.parse::<i32>() {
+        Ok(i) => i,
+        Err(_) => return Err(Status::BadRequest)
+    };
+    let series = match fetch_match(pool, match_id_int).await? {
+        Some(m) => m,
+        None => return Err(Status::NotFound)
+    };
+    if series.user_id != user.user_id && series.opp_user_id != user.user_id {
+        return Err(Status::Unauthorized)
+    }
+
+    let games = sqlx::query_as::<_,MatchGame>("SELECT * FROM match_game WHERE match_id=$1 ORDER BY game_number")
+        .bind(series.id)
+        .fetch_all(&**pool).await;
+    let games = match games {
+        Ok(g) => g,
+        Err(e) => {
+            println!("error: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+
+    let (creator, opponent) = half_points(&series, &games);
+    let detail = MatchDetail {
+        series,
+        games,
+        creator_score: creator as f64 / 2.0,
+        opponent_score: opponent as f64 / 2.0
+    };
+    Ok(serde_json::to_string(&detail).unwrap())
+}
+
+async fn fetch_match(pool: &Pool<Postgres>, match_id: i32) -> Result<Option<MatchSeries>, Status> {
+    let series = sqlx::query_as::<_,MatchSeries>("SELECT * FROM match_series WHERE id=$1")
+        .bind(match_id)
+        .fetch_optional(pool).await;
+    match series {
+        Ok(m) => Ok(m),
+        Err(e) => {
+            println!("error getting match: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// records finished games and either starts the next one or settles the match
+// the poller's token exports the game and sends the next challenge, so either player can move a match on
+pub async fn check_pending_matches_and_update(user: &User, pool: &Pool<Postgres>, app_config: &AppConfig) {
+    let matches = sqlx::query_scalar::<_,i32>("SELECT id FROM match_series WHERE (user_id=$1 OR opp_user_id=$1) AND status='ACCEPTED' ORDER BY id DESC LIMIT 100")
+        .bind(user.user_id)
+        .fetch_all(pool).await;
+    let matches = match matches {
+        Ok(ids) => ids,
+        Err(e) => {
+            println!("error getting matches: {}", e);
+            return
+        }
+    };
+
+    // TODO: parallelize this
+    for match_id in matches {
+        println!("processing match {}", match_id);
+        update_match(user, pool, app_config, match_id).await;
+    }
+}
+
+async fn update_match(user: &User, pool: &Pool<Postgres>, app_config: &AppConfig, match_id: i32) {
+    let mut tx = match pool.begin().await {
+        Ok(t) => t,
+        Err(e) => {
+            println!("error creating tx: {}", e);
+            return
+        }
+    };
+
+    // locked so two players polling at once don't both start the next game
+    let series = sqlx::query_as::<_,MatchSeries>("SELECT * FROM match_series WHERE id=$1 AND status='ACCEPTED' FOR UPDATE")
+        .bind(match_id)
+        .fetch_optional(&mut tx).await;
+    let series = match series {
+        Ok(Some(m)) => m,
+        Ok(None) => return,
+        Err(e) => {
+            println!("error locking match {}: {}", match_id, e);
+            return
+        }
+    };
+    let games = sqlx::query_as::<_,MatchGame>("SELECT * FROM match_game WHERE match_id=$1 ORDER BY game_number")
+        .bind(series.id)
+        .fetch_all(&mut tx).await;
+    let mut games = match games {
+        Ok(g) => g,
+        Err(e) => {
+            println!("error getting games for match {}: {}", series.id, e);
+            return
+        }
+    };
+
+    // accepted matches always have a game
+    let current = match games.last_mut() {
+        Some(g) => g,
+        None => return
+    };
+    if current.outcome.is_none() {
+        // lichess has no game for a challenge that was declined or never accepted,
+        // one it couldn't be asked about is left for the next poll
+        let game = match export_game(&app_config.lichess_url, &user.access_token, &current.lichess_game_id).await {
+            Ok(g) => g,
+            Err(_) => return
+        };
+        let started = game.as_ref().is_some_and(|g| g.status != "created");
+        let outcome = match game.as_ref().and_then(|g| game_outcome(current, g)) {
+            Some(o) => o,
+            None if !started && current.created_at + Duration::seconds(app_config.start_deadline) <= Utc::now() => {
+                println!("match {} game {} never started", series.id, current.game_number);
+                no_show_outcome(&series, current)
+            },
+            None => {
+                println!("match {} game {} not over yet", series.id, current.game_number);
+                return
+            }
+        };
+        let recorded = sqlx::query("UPDATE match_game SET outcome=$1 WHERE id=$2")
+            .bind(outcome)
+            .bind(current.id)
+            .execute(&mut tx).await;
+        if let Err(e) = recorded {
+            println!("error recording outcome of game {}: {}", current.lichess_game_id, e);
+            return
+        }
+        current.outcome = Some(outcome);
+    }
+
+    let settled = match standing(&series, &games) {
+        Standing::Play { armageddon } => start_game(&mut tx, user, app_config, &series, games.last(), armageddon).await,
+        Standing::Won { creator } => settle_match(&mut tx, &series, Some(creator)).await,
+        Standing::Split => settle_match(&mut tx, &series, None).await
+    };
+    if !settled {
+        return
+    }
+
+    match tx.commit().await {
+        Ok(_) => println!("successfully committed match {}", series.id),
+        Err(e) => println!("error committing match {}: {}", series.id, e)
+    }
+}
+
+// None while the game is still being played, the outcome is from the creator's side
+fn game_outcome(current: &MatchGame, game: &LichessExportGameResponse) -> Option<GameOutcome> {
+    match game.status.as_str() {
+        "created" | "started" => return None,
+        "aborted" | "noStart" => return Some(GameOutcome::Aborted),
+        _ => ()
+    }
+    // in armageddon black draw odds make every game decisive
+    let winner = match (game.winner.as_deref(), current.armageddon) {
+        (Some(w), _) => w,
+        (None, true) => "black",
+        (None, false) => return Some(GameOutcome::Draw)
+    };
+    if winner == current.creator_color.as_str() { Some(GameOutcome::Creator) } else { Some(GameOutcome::Opponent) }
+}
+
+// a game whose challenge wasn't accepted in time is lost by the player it was sent to,
+// games from before that was recorded are replayed
+fn no_show_outcome(series: &MatchSeries, current: &MatchGame) -> GameOutcome {
+    match current.challenged_user_id {
+        Some(id) if id == series.user_id => GameOutcome::Opponent,
+        Some(_) => GameOutcome::Creator,
+        None => GameOutcome::Aborted
+    }
+}
+
+// both sides' points doubled so a draw is 1, first to matches only count wins
+fn half_points(series: &MatchSeries, games: &[MatchGame]) -> (i32, i32) {
+    let draw = if series.format == MatchFormat::FirstTo { 0 } else { 1 };
+    games.iter().fold((0, 0), |(creator, opponent), game| match game.outcome {
+        Some(GameOutcome::Creator) => (creator + 2, opponent),
+        Some(GameOutcome::Opponent) => (creator, opponent + 2),
+        Some(GameOutcome::Draw) => (creator + draw, opponent + draw),
+        _ => (creator, opponent)
+    })
+}
+
+fn standing(series: &MatchSeries, games: &[MatchGame]) -> Standing {
+    let counted: Vec<&MatchGame> = games.iter()
+        .filter(|g| g.outcome.is_some_and(|o| o != GameOutcome::Aborted))
+        .collect();
+    let target = series.target as usize;
+
+    if series.format == MatchFormat::FirstTo {
+        let (creator, opponent) = half_points(series, games);
+        return if creator >= 2 * series.target {
+            Standing::Won { creator: true }
+        } else if opponent >= 2 * series.target {
+            Standing::Won { creator: false }
+        } else {
+            Standing::Play { armageddon: false }
+        }
+    }
+
+    // more than half the scheduled games' points clinches a best of match
+    let (mut creator, mut opponent) = (0, 0);
+    for game in counted.iter().take(target) {
+        match game.outcome {
+            Some(GameOutcome::Creator) => creator += 2,
+            Some(GameOutcome::Opponent) => opponent += 2,
+            _ => {
+                creator += 1;
+                opponent += 1;
+            }
+        }
+    }
+    if creator > series.target {
+        return Standing::Won { creator: true }
+    }
+    if opponent > series.target {
+        return Standing::Won { creator: false }
+    }
+    if counted.len() < target {
+        return Standing::Play { armageddon: false }
+    }
+
+    // level after every scheduled game
+    let decisive = counted.iter().skip(target)
+        .find_map(|g| match g.outcome {
+            Some(GameOutcome::Creator) => Some(true),
+            Some(GameOutcome::Opponent) => Some(false),
+            _ => None
+        });
+    match (series.tiebreak, decisive) {
+        (Tiebreak::Split, _) => Standing::Split,
+        (_, Some(creator)) => Standing::Won { creator },
+        (Tiebreak::SuddenDeath, None) => Standing::Play { armageddon: false },
+        (Tiebreak::Armageddon, None) => Standing::Play { armageddon: true }
+    }
+}
+
+// challenges the other player from the poller's account, colours alternate and an aborted game is replayed as it was
+async fn start_game(tx: &mut sqlx::Transaction<'_, Postgres>, user: &User, app_config: &AppConfig, series: &MatchSeries, last: Option<&MatchGame>, armageddon: bool) -> bool {
+    let (game_number, creator_color) = match last {
+        Some(g) if g.outcome == Some(GameOutcome::Aborted) => (g.game_number + 1, g.creator_color),
+        Some(g) => (g.game_number + 1, g.creator_color.opposite()),
+        None => (1, series.color)
+    };
+    let poller_is_creator = user.user_id == series.user_id;
+    let (dest_user, dest_user_id, color) = if poller_is_creator {
+        (&series.opp_username, series.opp_user_id, creator_color)
+    } else {
+        (&series.username, series.user_id, creator_color.opposite())
+    };
+    let challenge = LichessChallenge {
+        rated: series.rated,
+        clock: LichessChallengeClock {
+            limit: series.time_limit.unwrap_or(DEFAULT_TIME_LIMIT).to_string(),
+            increment: series.increment.unwrap_or(0).to_string(),
+        },
+        color: color.as_str().to_string(),
+        variant: series.variant.as_str().to_string(),
+        rules: "noClaimWin".to_string(),
+    };
+    let lichess_game_id = match create_challenge(&app_config.lichess_url, &user.access_token, dest_user, &challenge).await {
+        Some(id) => id,
+        None => return false
+    };
+
+    let inserted = sqlx::query("INSERT INTO match_game (match_id, game_number, lichess_game_id, creator_color, armageddon, challenged_user_id) VALUES ($1, $2, $3, $4, $5, $6)")
+        .bind(series.id)
+        .bind(game_number)
+        .bind(&lichess_game_id)
+        .bind(creator_color)
+        .bind(armageddon)
+        .bind(dest_user_id)
+        .execute(&mut *tx).await;
+    match inserted {
+        Ok(_) => {
+            println!("started game {} of match {} on lichess as {}", game_number, series.id, lichess_game_id);
+            true
+        },
+        Err(e) => {
+            println!("error saving game {} of match {}: {}", game_number, series.id, e);
+            false
+        }
+    }
+}
+
+// the fee fixed when the match was created, taken once from the whole pot
+// a split pot goes back in proportion to the stakes like a drawn challenge, rounding to the house
+async fn settle_match(tx: &mut sqlx::Transaction<'_, Postgres>, series: &MatchSeries, creator_won: Option<bool>) -> bool {
+    let admin = match env::var("ADMIN_ACCOUNT") {
+        Ok(a) => a,
+        Err(e) => {
+            println!("error getting admin account: {}", e);
+            return false
+        }
+    };
+    let fee = series.fee_msat;
+    let net = msat(series.pot()) - fee;
+
+    let admin_user_id = match ensure_user(&mut *tx, &admin).await {
+        Ok(id) => id,
+        Err(e) => {
+            println!("error adding admin user {}", e);
+            return false
+        }
+    };
+    let mut payouts = vec![];
+    if fee > 0 {
+        payouts.push((admin_user_id, admin.as_str(), TransactionType::Fee, format!("fee from match {}", series.id), fee));
+    }
+    let winner_user_id = match creator_won {
+        Some(true) => {
+            payouts.push((series.user_id, series.username.as_str(), TransactionType::Winnings, format!("won match vs {}", series.opp_username), net));
+            Some(series.user_id)
+        },
+        Some(false) => {
+            payouts.push((series.opp_user_id, series.opp_username.as_str(), TransactionType::Winnings, format!("won match vs {}", series.username), net));
+            Some(series.opp_user_id)
+        },
+        None => {
+            let (shares, rounding) = split(net, &[series.sats, series.opponent_stake()]);
+            let detail = "initial sats amount minus fee".to_string();
+            payouts.push((series.user_id, series.username.as_str(), TransactionType::Draw, detail.clone(), shares[0]));
+            payouts.push((series.opp_user_id, series.opp_username.as_str(), TransactionType::Draw, detail, shares[1]));
+            if rounding > 0 {
+                payouts.push((admin_user_id, admin.as_str(), TransactionType::Rounding, format!("rounding from match {}", series.id), rounding));
+            }
+            None
+        }
+    };
+
+    for (user_id, username, ttype, detail, amount) in payouts {
+        if let Err(e) = insert_transaction(tx, user_id, username, ttype, &detail, amount, series.id).await {
+            println!("insert transaction failed {}", e);
+            return false
+        }
+        let credited = sqlx::query("UPDATE lightningchess_balance SET balance_msat=balance_msat + $1 WHERE user_id=$2")
+            .bind(amount)
+            .bind(user_id)
+            .execute(&mut *tx).await;
+        if let Err(e) = credited {
+            println!("error paying {} for match {}: {}", username, series.id, e);
+            return false
+        }
+    }
+
+    let completed = sqlx::query("UPDATE match_series SET status=$1, winner_user_id=$2 WHERE id=$3")
+        .bind(ChallengeStatus::Completed)
+        .bind(winner_user_id)
+        .bind(series.id)
+        .execute(&mut *tx).await;
+    match completed {
+        Ok(_) => {
+            println!("settled match {}", series.id);
+            true
+        },
+        Err(e) => {
+            println!("update match failed: {}", e);
+            false
+        }
+    }
+}
+
+async fn insert_transaction(tx: &mut sqlx::Transaction<'_, Postgres>, user_id: i32, username: &str, ttype: TransactionType, detail: &str, amount: i64, match_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, match_id, user_id) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+        .bind(username)
+        .bind(ttype)
+        .bind(detail)
+        .bind(amount)
+        .bind(TransactionState::Settled)
+        .bind(match_id)
+        .bind(user_id)
+        .execute(&mut *tx).await
+        .map(|_| ())
+}
@@ -1,5 +1,11 @@
 pub mod callback;
+pub mod export;
 pub mod challenge;
+pub mod limits;
+pub mod lnurl;
 pub mod login;
+pub mod matches;
 pub mod profile;
+pub mod tournaments;
 pub mod money;
+pub mod params;
@@ -5,12 +5,24 @@ use reqwest::Client;
 use rocket::http::Status;
 use rocket::State;
 use sqlx::{Pool, Postgres};
-use crate::models::{Transaction, AddInvoiceRequest, User, Balance, Challenge, LichessExportGameResponse, SendPaymentRequest, SendPaymentResponse};
+use sqlx::types::chrono::Utc;
+use crate::models::{AppConfig, ChallengeStatus, Color, Transaction, TransactionState, TransactionType, AddInvoiceRequest, User, Balance, Challenge, LichessExportGameResponse, PaymentError, SendPaymentRequest, SendPaymentResponse, TransactionPage, TransactionSummary};
+use crate::endpoints::limits::{check_deposit, check_withdrawal};
+use crate::endpoints::params::{page_size, parse_enum, parse_id, parse_time};
+use crate::db::{ensure_user, is_insufficient_funds};
+use crate::ledger::{msat, split};
+use crate::lichess::clock::time_odds;
+use crate::lichess::games::add_time;
+use crate::endpoints::matches::check_pending_matches_and_update;
+use crate::endpoints::tournaments::check_pending_tournaments_and_update;
+use crate::endpoints::lnurl::check_expired_withdraw_links_and_update;
 use crate::lightning::invoices::add_invoice;
-use crate::lightning::payment::{decode_payment, make_payment};
+use crate::lightning::lnurl::fetch_invoice;
+use crate::lightning::bolt11;
+use crate::lightning::payment::{make_payment, track_payment, PaymentStatus};
 
 #[post("/api/invoice", data = "<invoice_request_str>")]
-pub async fn add_invoice_endpoint(user: User, pool: &State<Pool<Postgres>>, invoice_request_str: String) -> Result<String, Status> {
+pub async fn add_invoice_endpoint(user: User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, invoice_request_str: String) -> Result<String, Status> {
     println!("invoice request: {}", invoice_request_str);
     let invoice_request_result: Result<AddInvoiceRequest, serde_json::Error> = serde_json::from_str(&invoice_request_str);
     let invoice_request = match invoice_request_result {
@@ -21,6 +33,30 @@ pub async fn add_invoice_endpoint(user: User, pool: &State<Pool<Postgres>>, invo
         }
     };
 
+    // sats or msat, not both
+    let value_msat = match (invoice_request.sats, invoice_request.msat) {
+        (Some(sats), None) => msat(sats),
+        (None, Some(value_msat)) => value_msat,
+        _ => return Err(Status::BadRequest)
+    };
+    if value_msat <= 0 {
+        return Err(Status::BadRequest)
+    }
+
+    let memo = format!("funding account {} on lightningchess.io", &user.username);
+    match create_invoice(pool, app_config, &user, value_msat, &memo, None, None).await {
+        Ok(t) => Ok(serde_json::to_string(&t).unwrap()),
+        Err(status) => Err(status)
+    }
+}
+
+// a deposit invoice for user, settled into their balance once lnd reports it paid
+// with a description_hash the invoice commits to that instead of the memo,
+// lnurl_requester is who asked for a lightning address invoice, None for the user's own
+pub async fn create_invoice(pool: &Pool<Postgres>, app_config: &AppConfig, user: &User, value_msat: i64, memo: &str, description_hash: Option<&[u8]>, lnurl_requester: Option<&str>) -> Result<Transaction, Status> {
+    // checked against the invoice, what's paid into it is up to lnd
+    check_deposit(pool, app_config, user.user_id, value_msat).await?;
+
     // create preimage
     let preimage_bytes: Vec<u8>  = rand::thread_rng()
         .sample_iter(&Alphanumeric)
@@ -28,35 +64,33 @@ pub async fn add_invoice_endpoint(user: User, pool: &State<Pool<Postgres>>, invo
         .collect();
 
     let preimage =  base64::encode(&preimage_bytes);
-    // create memo
-    let memo = format!("funding account {} on lightningchess.io", &user.username);
 
     // create invoice
-    let add_invoice_response_option = add_invoice(invoice_request.sats, &memo, preimage_bytes).await;
+    let add_invoice_response_option = add_invoice(&app_config.lnd_url, value_msat, memo, description_hash, preimage_bytes).await;
     let add_invoice_response = match add_invoice_response_option {
         Some(i) => i,
         None => return Err(Status::InternalServerError)
     };
 
     // save it to db
-    let ttype = "invoice";
-    let state = "OPEN";
+    let ttype = TransactionType::Invoice;
+    let state = TransactionState::Open;
     // TODO: change to return without the preimage
-    let pg_query_result = sqlx::query_as::<_,Transaction>("INSERT INTO lightningchess_transaction (username, ttype, detail, amount, state, preimage, payment_addr, payment_request) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *")
+    let pg_query_result = sqlx::query_as::<_,Transaction>("INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, preimage, payment_addr, payment_request, user_id, lnurl_requester) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING *")
         .bind(&user.username)
         .bind(ttype)
-        .bind(&memo)
+        .bind(memo)
         .bind(0) // default to zero until paid
         .bind(state)
         .bind(&preimage)
         .bind(&add_invoice_response.payment_addr)
         .bind(&add_invoice_response.payment_request)
-        .fetch_one(&**pool).await;
+        .bind(user.user_id)
+        .bind(lnurl_requester)
+        .fetch_one(pool).await;
 
-    return match pg_query_result {
-        Ok(r) => {
-            Ok(serde_json::to_string(&r).unwrap())
-        },
+    match pg_query_result {
+        Ok(r) => Ok(r),
         Err(e) => {
             println!("error: {}", e.as_database_error().unwrap().message());
             Err(Status::InternalServerError)
@@ -83,7 +117,7 @@ pub async fn lookup_transaction(user: User, pool: &State<Pool<Postgres>>, transa
         }
     };
 
-    if transaction.username != user.username {
+    if transaction.user_id != user.user_id {
         return Err(Status::Unauthorized)
     }
 
@@ -91,23 +125,106 @@ pub async fn lookup_transaction(user: User, pool: &State<Pool<Postgres>>, transa
         .bind(transaction_id_int)
         .fetch_one(&**pool).await;
 
-    return match transaction_result2 {
+    match transaction_result2 {
         Ok(t2) => Ok(serde_json::to_string(&t2).unwrap()),
         Err(e) => {
             println!("error getting t2: {}", e);
-            return Err(Status::InternalServerError)
+            Err(Status::InternalServerError)
         }
     }
 }
 
-#[get("/api/transactions")]
-pub async fn transactions(user: User, pool: &State<Pool<Postgres>>) -> Result<String, Status> {
-    let transactions = sqlx::query_as::<_,Transaction>( "SELECT * FROM lightningchess_transaction WHERE username=$1 ORDER BY transaction_id DESC LIMIT 100")
-        .bind(&user.username)
+// newest first; the cursor is the transaction_id of the last row on the previous page
+#[allow(clippy::too_many_arguments)]
+#[get("/api/transactions?<cursor>&<limit>&<ttype>&<state>&<from>&<to>&<challenge_id>")]
+pub async fn transactions(user: User, pool: &State<Pool<Postgres>>, cursor: Option<String>, limit: Option<String>, ttype: Option<String>, state: Option<String>, from: Option<String>, to: Option<String>, challenge_id: Option<String>) -> Result<String, Status> {
+    let cursor = parse_id("cursor", cursor)?;
+    let limit = page_size(limit)?;
+    let ttype: Option<TransactionType> = parse_enum("ttype", ttype)?;
+    let state: Option<TransactionState> = parse_enum("state", state)?;
+    let from = parse_time("from", from)?;
+    let to = parse_time("to", to)?;
+    let challenge_id = parse_id("challenge_id", challenge_id)?;
+
+    // one extra row tells us whether there is another page
+    let transactions = sqlx::query_as::<_,Transaction>( "SELECT * FROM lightningchess_transaction WHERE user_id=$1 \
+        AND ($2::INT IS NULL OR transaction_id < $2) \
+        AND ($3::transaction_type IS NULL OR ttype=$3) \
+        AND ($4::transaction_state IS NULL OR state=$4) \
+        AND ($5::TIMESTAMPTZ IS NULL OR created_at >= $5) \
+        AND ($6::TIMESTAMPTZ IS NULL OR created_at < $6) \
+        AND ($7::INT IS NULL OR challenge_id=$7) \
+        ORDER BY transaction_id DESC LIMIT $8")
+        .bind(user.user_id)
+        .bind(cursor)
+        .bind(ttype)
+        .bind(state)
+        .bind(from)
+        .bind(to)
+        .bind(challenge_id)
+        .bind(limit + 1)
         .fetch_all(&**pool).await;
 
     match transactions {
-        Ok(t) => Ok(serde_json::to_string(&t).unwrap()),
+        Ok(mut t) => {
+            let next_cursor = if t.len() as i64 > limit {
+                t.truncate(limit as usize);
+                t.last().map(|last| last.transaction_id)
+            } else {
+                None
+            };
+            Ok(serde_json::to_string(&TransactionPage { transactions: t, next_cursor }).unwrap())
+        },
+        Err(e) => {
+            println!("error: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// period is [from, to) by when each transaction was recorded, unbounded if left out
+#[get("/api/transactions/summary?<from>&<to>")]
+pub async fn transactions_summary(user: User, pool: &State<Pool<Postgres>>, from: Option<String>, to: Option<String>) -> Result<String, Status> {
+    let from = parse_time("from", from)?;
+    let to = parse_time("to", to)?;
+
+    // won is what payouts came to above the stake or buy-in they paid back, lost is the stakes and buy-ins that didn't come back
+    // fee rows belong to the admin, they are matched to the user's payout by challenge, match or tournament id;
+    // a tournament's fee is shared between its prizes in proportion to them
+    let summary = sqlx::query_as::<_,TransactionSummary>( "SELECT \
+        COALESCE(SUM(t.amount_msat) FILTER (WHERE t.ttype='invoice' AND t.state='SETTLED'), 0)::BIGINT AS deposited, \
+        COALESCE(-SUM(t.amount_msat) FILTER (WHERE t.ttype='withdrawal' AND t.state='SETTLED'), 0)::BIGINT AS withdrawn, \
+        COALESCE(SUM(GREATEST(t.amount_msat - s.stake_msat, 0)) FILTER (WHERE t.ttype IN ('winnings', 'tournament prize') AND t.state='SETTLED'), 0)::BIGINT AS won, \
+        (COALESCE(SUM(GREATEST(s.stake_msat - t.amount_msat, 0)) FILTER (WHERE t.ttype IN ('winnings', 'tournament prize') AND t.state='SETTLED'), 0) \
+        + COALESCE(-SUM(t.amount_msat) FILTER (WHERE (t.ttype IN ('create challenge', 'accept challenge') AND c.status='COMPLETED' \
+            AND NOT EXISTS (SELECT 1 FROM lightningchess_transaction p WHERE p.challenge_id=t.challenge_id AND p.user_id=t.user_id AND p.ttype IN ('winnings', 'draw'))) \
+            OR (t.ttype IN ('create match', 'accept match') AND m.status='COMPLETED' \
+            AND NOT EXISTS (SELECT 1 FROM lightningchess_transaction p WHERE p.match_id=t.match_id AND p.user_id=t.user_id AND p.ttype IN ('winnings', 'draw'))) \
+            OR (t.ttype='tournament entry' AND tn.status='completed' \
+            AND NOT EXISTS (SELECT 1 FROM lightningchess_transaction p WHERE p.tournament_id=t.tournament_id AND p.user_id=t.user_id AND p.ttype='tournament prize'))), 0))::BIGINT AS lost, \
+        (COALESCE(SUM(f.amount_msat) FILTER (WHERE t.ttype='winnings'), 0) \
+        + COALESCE(SUM(s.stake_msat - t.amount_msat) FILTER (WHERE t.ttype='draw'), 0) \
+        + COALESCE(SUM(f.amount_msat::NUMERIC * t.amount_msat / s.prizes_msat) FILTER (WHERE t.ttype='tournament prize'), 0))::BIGINT AS fees \
+        FROM lightningchess_transaction t \
+        LEFT JOIN challenge c ON c.id=t.challenge_id \
+        LEFT JOIN match_series m ON m.id=t.match_id \
+        LEFT JOIN tournament tn ON tn.id=t.tournament_id \
+        CROSS JOIN LATERAL (SELECT \
+            CASE WHEN tn.id IS NOT NULL THEN tn.buy_in WHEN c.user_id=t.user_id THEN c.sats WHEN c.id IS NOT NULL THEN c.opponent_sats \
+            WHEN m.user_id=t.user_id THEN m.sats ELSE m.opponent_sats END * 1000 AS stake_msat, \
+            (SELECT NULLIF(SUM(e.prize_msat), 0) FROM tournament_entry e WHERE e.tournament_id=t.tournament_id) AS prizes_msat) s \
+        LEFT JOIN lightningchess_transaction f ON (f.challenge_id=t.challenge_id OR f.match_id=t.match_id OR f.tournament_id=t.tournament_id) \
+            AND f.ttype='fee' AND t.ttype IN ('winnings', 'draw', 'tournament prize') \
+        WHERE t.user_id=$1 \
+        AND ($2::TIMESTAMPTZ IS NULL OR t.created_at >= $2) \
+        AND ($3::TIMESTAMPTZ IS NULL OR t.created_at < $3)")
+        .bind(user.user_id)
+        .bind(from)
+        .bind(to)
+        .fetch_one(&**pool).await;
+
+    match summary {
+        Ok(s) => Ok(serde_json::to_string(&s).unwrap()),
         Err(e) => {
             println!("error: {}", e);
             Err(Status::InternalServerError)
@@ -116,13 +233,17 @@ pub async fn transactions(user: User, pool: &State<Pool<Postgres>>) -> Result<St
 }
 
 #[get("/api/balance")]
-pub async fn balance(user: User, pool: &State<Pool<Postgres>>) -> Result<String, Status> {
+pub async fn balance(user: User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>) -> Result<String, Status> {
 
-    check_pending_invoices_and_update(&user, pool, None).await;
-    check_pending_challenges_and_update(&user, pool).await;
+    check_pending_invoices_and_update(&user, pool, app_config, None).await;
+    check_pending_challenges_and_update(&user, pool, app_config).await;
+    check_pending_matches_and_update(&user, pool, app_config).await;
+    check_pending_tournaments_and_update(&user, pool, app_config).await;
+    check_expired_withdraw_links_and_update(&user, pool).await;
+    check_pending_withdrawals_and_update(&user, pool, app_config).await;
 
-    let balance_result = sqlx::query_as::<_,Balance>( "SELECT * FROM lightningchess_balance WHERE username=$1")
-        .bind(&user.username)
+    let balance_result = sqlx::query_as::<_,Balance>( "SELECT * FROM lightningchess_balance WHERE user_id=$1")
+        .bind(user.user_id)
         .fetch_optional(&**pool).await;
     match balance_result {
         Ok(balance_option) => {
@@ -131,7 +252,8 @@ pub async fn balance(user: User, pool: &State<Pool<Postgres>>) -> Result<String,
                 None => Ok(serde_json::to_string(&Balance {
                     balance_id: 0,
                     username: user.username,
-                    balance: 0
+                    user_id: user.user_id,
+                    balance_msat: 0
                 }).unwrap())
             }
         },
@@ -142,7 +264,7 @@ pub async fn balance(user: User, pool: &State<Pool<Postgres>>) -> Result<String,
     }
 }
 
-async fn check_pending_invoices_and_update(user: &User, pool: &State<Pool<Postgres>>, transaction_id: Option<i32>) -> () {
+async fn check_pending_invoices_and_update(user: &User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, transaction_id: Option<i32>) -> () {
     // 1. look up all transactions in db for a user where the type is invoice and status is OPEN
     let transactions_result = match transaction_id {
         Some(tid) => {
@@ -151,8 +273,11 @@ async fn check_pending_invoices_and_update(user: &User, pool: &State<Pool<Postgr
                 .fetch_all(&**pool).await
         },
         None => {
-            sqlx::query_as::<_, Transaction>("SELECT * FROM lightningchess_transaction WHERE username=$1 AND state='OPEN' AND ttype='invoice' ORDER BY transaction_id DESC LIMIT 100")
-                .bind(&user.username)
+            // the user's own invoices apart from lightning address ones, which anyone can have made;
+            // those go oldest first, so expired ones are canceled and a pile of them clears a few at a time
+            sqlx::query_as::<_, Transaction>("(SELECT * FROM lightningchess_transaction WHERE user_id=$1 AND state='OPEN' AND ttype='invoice' AND lnurl_requester IS NULL ORDER BY transaction_id DESC LIMIT 100) \
+                UNION ALL (SELECT * FROM lightningchess_transaction WHERE user_id=$1 AND state='OPEN' AND ttype='invoice' AND lnurl_requester IS NOT NULL ORDER BY transaction_id LIMIT 20)")
+                .bind(user.user_id)
                 .fetch_all(&**pool).await
         }
     };
@@ -165,12 +290,18 @@ async fn check_pending_invoices_and_update(user: &User, pool: &State<Pool<Postgr
     // 2. TODO: parallelize this
     for transaction in transactions.iter() {
         println!("processing transaction {}", transaction.transaction_id);
-        let invoice_option = crate::lightning::hodl_invoices::lookup_hodl_invoice(transaction.payment_addr.as_ref().unwrap()).await;
+        let invoice_option = crate::lightning::hodl_invoices::lookup_hodl_invoice(&app_config.lnd_url, transaction.payment_addr.as_ref().unwrap()).await;
         match invoice_option {
             Some(i) => {
-                let new_state = i.state;
+                let new_state = match TransactionState::from_lnd(&i.state) {
+                    Some(s) => s,
+                    None => {
+                        println!("unknown invoice state {} for tx id : {}", i.state, transaction.transaction_id);
+                        continue;
+                    }
+                };
                 // 3. update if necessary in postgres
-                if new_state != "OPEN" {
+                if transaction.state.can_become(new_state) {
                     let tx_result = pool.begin().await;
                     let mut tx = match tx_result {
                         Ok(t) => t,
@@ -180,9 +311,9 @@ async fn check_pending_invoices_and_update(user: &User, pool: &State<Pool<Postgr
                         }
                     };
                     // update transaction table
-                    let amount = i.amt_paid_sat.parse::<i64>().unwrap();
-                    let updated_transaction = sqlx::query( "UPDATE lightningchess_transaction SET state=$1, amount=$2 WHERE transaction_id=$3")
-                        .bind(&new_state)
+                    let amount = i.amt_paid_msat.parse::<i64>().unwrap();
+                    let updated_transaction = sqlx::query( "UPDATE lightningchess_transaction SET state=$1, amount_msat=$2 WHERE transaction_id=$3")
+                        .bind(new_state)
                         .bind(amount)
                         .bind(transaction.transaction_id)
                         .execute(&mut tx).await;
@@ -196,13 +327,10 @@ async fn check_pending_invoices_and_update(user: &User, pool: &State<Pool<Postgr
                     }
 
                     // update balance table
-                    if new_state == "SETTLED" {
-                        //"INSERT INTO lightningchess_balance (username, balance) VALUES ($1, $2) ON CONFLICT DO UPDATE lightningchess_balance SET balance=(balance + $3) WHERE username=$4"
-                        let updated_balance = sqlx::query( "INSERT INTO lightningchess_balance (username, balance) VALUES ($1, $2) ON CONFLICT (username) DO UPDATE SET balance=lightningchess_balance.balance + $3 WHERE lightningchess_balance.username=$4")
-                            .bind(&user.username)
-                            .bind(amount)
+                    if new_state == TransactionState::Settled {
+                        let updated_balance = sqlx::query( "UPDATE lightningchess_balance SET balance_msat=balance_msat + $1 WHERE user_id=$2")
                             .bind(amount)
-                            .bind(&user.username)
+                            .bind(user.user_id)
                             .execute(&mut tx).await;
 
                         match updated_balance {
@@ -230,10 +358,41 @@ async fn check_pending_invoices_and_update(user: &User, pool: &State<Pool<Postgr
     }
 }
 
-async fn check_pending_challenges_and_update(user: &User, pool: &State<Pool<Postgres>>) -> () {
+// withdrawals left OPEN because their payment wasn't confirmed when it was made, looked up in lnd;
+// a minute old so one still being paid isn't raced
+async fn check_pending_withdrawals_and_update(user: &User, pool: &Pool<Postgres>, app_config: &AppConfig) {
+    let withdrawals = sqlx::query_as::<_, Transaction>("SELECT * FROM lightningchess_transaction WHERE user_id=$1 AND ttype='withdrawal' AND state='OPEN' \
+        AND payment_hash IS NOT NULL AND updated_at < now() - INTERVAL '1 minute' ORDER BY transaction_id LIMIT 20")
+        .bind(user.user_id)
+        .fetch_all(pool).await;
+    let withdrawals = match withdrawals {
+        Ok(ws) => ws,
+        Err(e) => return println!("unable to fetch withdrawals: {}", e)
+    };
+
+    for withdrawal in withdrawals.iter() {
+        let new_state = match track_payment(&app_config.lnd_url, withdrawal.payment_hash.as_ref().unwrap()).await {
+            Some(PaymentStatus::Succeeded) => TransactionState::Settled,
+            // lnd never started it, so nothing can still leave the node
+            Some(PaymentStatus::Failed) | Some(PaymentStatus::NotFound) => TransactionState::Failed,
+            Some(PaymentStatus::InFlight) => continue,
+            None => {
+                println!("track_payment error for tx id : {}", withdrawal.transaction_id);
+                continue;
+            }
+        };
+        // amounts of withdrawals are stored negative
+        match record_withdrawal(pool, user.user_id, withdrawal.transaction_id, -withdrawal.amount_msat, new_state).await {
+            Ok(_) => println!("reconciled withdrawal {} as {:?}", withdrawal.transaction_id, new_state),
+            Err(_) => println!("error reconciling withdrawal {}", withdrawal.transaction_id)
+        }
+    }
+}
+
+async fn check_pending_challenges_and_update(user: &User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>) -> () {
     // 1. look up all the challenges in ACCEPTED status
-    let challenges_result = sqlx::query_as::<_,Challenge>( "SELECT * FROM challenge WHERE (username=$1 OR opp_username=$1) AND STATUS='ACCEPTED' ORDER BY created_on DESC LIMIT 100")
-        .bind(&user.username)
+    let challenges_result = sqlx::query_as::<_,Challenge>( "SELECT * FROM challenge WHERE (user_id=$1 OR opp_user_id=$1) AND STATUS='ACCEPTED' ORDER BY created_at DESC LIMIT 100")
+        .bind(user.user_id)
         .fetch_all(&**pool).await;
     let challenges = match challenges_result {
         Ok(cs) => cs,
@@ -248,7 +407,7 @@ async fn check_pending_challenges_and_update(user: &User, pool: &State<Pool<Post
     for challenge in challenges.iter() {
         println!("processing challenge {}", challenge.id);
 
-        let url = format!("https://lichess.org/game/export/{}", &challenge.lichess_challenge_id.as_ref().unwrap());
+        let url = format!("{}/game/export/{}", &app_config.lichess_url, &challenge.lichess_challenge_id.as_ref().unwrap());
         let access_token = &user.access_token;
         let bearer = format!("Bearer {access_token}");
         let resp = Client::new()
@@ -280,17 +439,21 @@ async fn check_pending_challenges_and_update(user: &User, pool: &State<Pool<Post
             }
         };
 
+        let creator = creator_color(challenge, &lichess_export_game_response);
         let challenge_lichess_result = lichess_export_game_response.status;
         if challenge_lichess_result == "created" || challenge_lichess_result == "started" {
             println!("challenge not over yet {}", &challenge.lichess_challenge_id.as_ref().unwrap());
+            give_time_odds(user, pool, app_config, challenge).await;
+            continue;
+        }
+        if !challenge.time_odds_applied && time_odds(challenge).is_some() {
+            void_challenge(pool, challenge).await;
             continue;
         }
 
-        // determine fee
-        let initial_fee: f64 = (challenge.sats.unwrap() as f64) * 0.02;
-        let rounded_down = initial_fee.floor() as i64;
-        // make even
-        let fee = rounded_down - rounded_down % 2;
+        // fixed when the challenge was created
+        let fee = challenge.fee_msat;
+        let net = msat(challenge.pot()) - fee;
         let admin_result = env::var("ADMIN_ACCOUNT");
         let admin = match admin_result {
             Ok(a) => a,
@@ -309,54 +472,73 @@ async fn check_pending_challenges_and_update(user: &User, pool: &State<Pool<Post
             }
         };
 
-        // pay admin
-        let admin_ttype = "fee";
-        let admin_detail = format!("fee from challenge {}", challenge.id);
-        let admin_state = "SETTLED";
-        let admin_transaction_result = sqlx::query( "INSERT INTO lightningchess_transaction (username, ttype, detail, amount, state, lichess_challenge_id) VALUES ($1, $2, $3, $4, $5, $6)")
-            .bind(&admin)
-            .bind(admin_ttype)
-            .bind(admin_detail)
-            .bind(fee)
-            .bind(admin_state)
-            .bind(&challenge.lichess_challenge_id.as_ref().unwrap())
-            .execute(&mut tx).await;
-
-        match admin_transaction_result {
-            Ok(_) => println!("insert transaction successfully"),
+        let admin_user_id = match ensure_user(&mut tx, &admin).await {
+            Ok(id) => id,
             Err(e) => {
-                println!("insert transaction failed {}", e);
+                println!("error adding admin user {}", e);
                 return;
             }
-        }
+        };
 
-        let admin_balance = sqlx::query( "UPDATE lightningchess_balance set balance=balance + $1 WHERE username=$2")
-            .bind(fee)
-            .bind(&admin)
-            .execute(&mut tx).await;
+        // pay admin, nothing to record for a fee-free challenge
+        if fee > 0 {
+            let admin_ttype = TransactionType::Fee;
+            let admin_detail = format!("fee from challenge {}", challenge.id);
+            let admin_state = TransactionState::Settled;
+            let admin_transaction_result = sqlx::query( "INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, lichess_challenge_id, challenge_id, user_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)")
+                .bind(&admin)
+                .bind(admin_ttype)
+                .bind(admin_detail)
+                .bind(fee)
+                .bind(admin_state)
+                .bind(challenge.lichess_challenge_id.as_ref().unwrap())
+                .bind(challenge.id)
+                .bind(admin_user_id)
+                .execute(&mut tx).await;
 
-        match admin_balance {
-            Ok(_) => println!("successfully payed admin"),
-            Err(e) => {
-                println!("error paying admin admin_transaction transaction{}", e);
-                return;
+            match admin_transaction_result {
+                Ok(_) => println!("insert transaction successfully"),
+                Err(e) => {
+                    println!("insert transaction failed {}", e);
+                    return;
+                }
+            }
+
+            let admin_balance = sqlx::query( "UPDATE lightningchess_balance set balance_msat=balance_msat + $1 WHERE user_id=$2")
+                .bind(fee)
+                .bind(admin_user_id)
+                .execute(&mut tx).await;
+
+            match admin_balance {
+                Ok(_) => println!("successfully payed admin"),
+                Err(e) => {
+                    println!("error paying admin admin_transaction transaction{}", e);
+                    return;
+                }
             }
         }
 
         let winner = lichess_export_game_response.winner.get_or_insert("".to_string());
         if winner == "black" || winner == "white" {
             // pay money to winner
-            let winner_username = if challenge.color.as_ref().unwrap() == "black" { &challenge.username } else { &challenge.opp_username };
-            let winner_ttype = "winnings";
+            let (winner_username, winner_user_id) = if creator.as_str() == winner {
+                (&challenge.username, challenge.user_id)
+            } else {
+                // accepted challenges always have an opponent
+                (challenge.opp_username.as_ref().unwrap(), challenge.opp_user_id.unwrap())
+            };
+            let winner_ttype = TransactionType::Winnings;
             let winner_detail = "";
-            let winning_amt = (&challenge.sats.unwrap() * 2) - fee;
-            let winner_state = "SETTLED";
-            let winner_transaction_result = sqlx::query( "INSERT INTO lightningchess_transaction (username, ttype, detail, amount, state) VALUES ($1, $2, $3, $4, $5)")
+            let winning_amt = net;
+            let winner_state = TransactionState::Settled;
+            let winner_transaction_result = sqlx::query( "INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, challenge_id, user_id) VALUES ($1, $2, $3, $4, $5, $6, $7)")
                 .bind(winner_username)
                 .bind(winner_ttype)
                 .bind(winner_detail)
                 .bind(winning_amt)
                 .bind(winner_state)
+                .bind(challenge.id)
+                .bind(winner_user_id)
                 .execute(&mut tx).await;
 
             match winner_transaction_result {
@@ -367,9 +549,9 @@ async fn check_pending_challenges_and_update(user: &User, pool: &State<Pool<Post
                 }
             }
 
-            let winner_balance = sqlx::query( "UPDATE lightningchess_balance set balance=balance + $1 WHERE username=$2")
+            let winner_balance = sqlx::query( "UPDATE lightningchess_balance set balance_msat=balance_msat + $1 WHERE user_id=$2")
                 .bind(winning_amt)
-                .bind(winner_username)
+                .bind(winner_user_id)
                 .execute(&mut tx).await;
 
             match winner_balance {
@@ -381,16 +563,20 @@ async fn check_pending_challenges_and_update(user: &User, pool: &State<Pool<Post
             }
         } else {
             // no winner so return money to both people
-            let draw_ttype = "draw";
-            let draw_detail = "initial sats amount minus 2% fee";
-            let draw_amt = &challenge.sats.unwrap() - (fee / 2);
-            let draw_state = "SETTLED";
-            let draw_transaction_result = sqlx::query( "INSERT INTO lightningchess_transaction (username, ttype, detail, amount, state) VALUES ($1, $2, $3, $4, $5)")
+            let draw_ttype = TransactionType::Draw;
+            let draw_detail = "initial sats amount minus fee";
+            // with stake odds each side gets back in proportion to its stake, rounded down
+            let (shares, rounding) = split(net, &[challenge.sats, challenge.opponent_stake()]);
+            let (draw_amt, opp_draw_amt) = (shares[0], shares[1]);
+            let draw_state = TransactionState::Settled;
+            let draw_transaction_result = sqlx::query( "INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, challenge_id, user_id) VALUES ($1, $2, $3, $4, $5, $6, $7)")
                 .bind(&challenge.username)
                 .bind(draw_ttype)
                 .bind(draw_detail)
                 .bind(draw_amt)
                 .bind(draw_state)
+                .bind(challenge.id)
+                .bind(challenge.user_id)
                 .execute(&mut tx).await;
 
             match draw_transaction_result {
@@ -401,9 +587,9 @@ async fn check_pending_challenges_and_update(user: &User, pool: &State<Pool<Post
                 }
             }
 
-            let draw_balance = sqlx::query( "UPDATE lightningchess_balance set balance=balance + $1 WHERE username=$2")
+            let draw_balance = sqlx::query( "UPDATE lightningchess_balance set balance_msat=balance_msat + $1 WHERE user_id=$2")
                 .bind(draw_amt)
-                .bind(&challenge.username)
+                .bind(challenge.user_id)
                 .execute(&mut tx).await;
 
             match draw_balance {
@@ -414,12 +600,14 @@ async fn check_pending_challenges_and_update(user: &User, pool: &State<Pool<Post
                 }
             }
 
-            let draw_transaction_result2 = sqlx::query( "INSERT INTO lightningchess_transaction (username, ttype, detail, amount, state) VALUES ($1, $2, $3, $4, $5)")
+            let draw_transaction_result2 = sqlx::query( "INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, challenge_id, user_id) VALUES ($1, $2, $3, $4, $5, $6, $7)")
                 .bind(&challenge.opp_username)
                 .bind(draw_ttype)
                 .bind(draw_detail)
-                .bind(draw_amt)
+                .bind(opp_draw_amt)
                 .bind(draw_state)
+                .bind(challenge.id)
+                .bind(challenge.opp_user_id)
                 .execute(&mut tx).await;
 
             match draw_transaction_result2 {
@@ -430,9 +618,9 @@ async fn check_pending_challenges_and_update(user: &User, pool: &State<Pool<Post
                 }
             }
 
-            let draw_balance2 = sqlx::query( "UPDATE lightningchess_balance set balance=balance + $1 WHERE username=$2")
-                .bind(draw_amt)
-                .bind(&challenge.opp_username)
+            let draw_balance2 = sqlx::query( "UPDATE lightningchess_balance set balance_msat=balance_msat + $1 WHERE user_id=$2")
+                .bind(opp_draw_amt)
+                .bind(challenge.opp_user_id)
                 .execute(&mut tx).await;
 
             match draw_balance2 {
@@ -442,14 +630,42 @@ async fn check_pending_challenges_and_update(user: &User, pool: &State<Pool<Post
                     return;
                 }
             }
+
+            // what the shares leave over goes to the house
+            if rounding > 0 {
+                let rounding_transaction_result = sqlx::query( "INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, challenge_id, user_id) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+                    .bind(&admin)
+                    .bind(TransactionType::Rounding)
+                    .bind(format!("rounding from challenge {}", challenge.id))
+                    .bind(rounding)
+                    .bind(draw_state)
+                    .bind(challenge.id)
+                    .bind(admin_user_id)
+                    .execute(&mut tx).await;
+
+                if let Err(e) = rounding_transaction_result {
+                    println!("insert transaction failed {}", e);
+                    return;
+                }
+
+                let rounding_balance = sqlx::query( "UPDATE lightningchess_balance set balance_msat=balance_msat + $1 WHERE user_id=$2")
+                    .bind(rounding)
+                    .bind(admin_user_id)
+                    .execute(&mut tx).await;
+
+                if let Err(e) = rounding_balance {
+                    println!("error paying rounding balance transaction{}", e);
+                    return;
+                }
+            }
         }
 
         // mark challenge as completed
         // update challenge in db
-        let status = "COMPLETED";
-        let pg_query_result = sqlx::query_as::<_,Challenge>("UPDATE challenge SET status=$1 WHERE id=$2 RETURNING *")
+        let status = ChallengeStatus::Completed;
+        let pg_query_result = sqlx::query_as::<_,Challenge>("UPDATE challenge SET status=$1, time_odds_token=NULL WHERE id=$2 RETURNING *")
             .bind(status)
-            .bind(&challenge.id)
+            .bind(challenge.id)
             .fetch_one(&mut tx).await;
 
         match pg_query_result {
@@ -469,63 +685,182 @@ async fn check_pending_challenges_and_update(user: &User, pool: &State<Pool<Post
     }
 }
 
+// challenge.color is the creator's colour, challenges accepted before colours were
+// resolved on accept may still be random and lichess knows who got white
+fn creator_color(challenge: &Challenge, game: &LichessExportGameResponse) -> Color {
+    if challenge.color != Color::Random {
+        return challenge.color;
+    }
+    let white_id = game.players.as_ref()
+        .and_then(|players| players.white.user.as_ref())
+        .map(|user| user.id.as_str());
+    if white_id == Some(challenge.username.to_lowercase().as_str()) { Color::White } else { Color::Black }
+}
+
+// the player on the shorter clock adds the difference to the other side once the game exists on lichess,
+// with their own token when they poll and with the one kept from their create or accept when their opponent does
+async fn give_time_odds(user: &User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, challenge: &Challenge) {
+    if challenge.time_odds_applied {
+        return;
+    }
+    let (creator_gives, seconds) = match time_odds(challenge) {
+        Some(odds) => odds,
+        None => return
+    };
+    let giver = if creator_gives { challenge.user_id } else { challenge.opp_user_id.unwrap() };
+    let access_token = if giver == user.user_id {
+        user.access_token.clone()
+    } else {
+        let kept = sqlx::query_scalar::<_,Option<String>>("SELECT time_odds_token FROM challenge WHERE id=$1")
+            .bind(challenge.id)
+            .fetch_one(&**pool).await;
+        match kept {
+            Ok(Some(token)) => token,
+            Ok(None) => {
+                println!("no token to add time odds to challenge {} with", challenge.id);
+                return;
+            },
+            Err(e) => {
+                println!("error getting time odds token for challenge {}: {}", challenge.id, e);
+                return;
+            }
+        }
+    };
+
+    let game_id = challenge.lichess_challenge_id.as_ref().unwrap();
+    if !add_time(&app_config.lichess_url, &access_token, game_id, seconds).await {
+        println!("could not add time odds to game {}, retrying later", game_id);
+        return;
+    }
+    let updated = sqlx::query("UPDATE challenge SET time_odds_applied=true, time_odds_token=NULL WHERE id=$1")
+        .bind(challenge.id)
+        .execute(&**pool).await;
+    match updated {
+        Ok(_) => println!("added {}s time odds to game {}", seconds, game_id),
+        Err(e) => println!("error recording time odds for challenge {}: {}", challenge.id, e)
+    }
+}
+
+// a game that ended before its time odds went on wasn't the game that was staked on,
+// both players get their stake back and the house takes nothing
+async fn void_challenge(pool: &State<Pool<Postgres>>, challenge: &Challenge) {
+    let mut tx = match pool.begin().await {
+        Ok(t) => t,
+        Err(e) => {
+            println!("error creating tx: {}", e);
+            return;
+        }
+    };
+
+    // accepted challenges always have an opponent
+    let refunds = [
+        (&challenge.username, challenge.user_id, challenge.sats),
+        (challenge.opp_username.as_ref().unwrap(), challenge.opp_user_id.unwrap(), challenge.opponent_stake())
+    ];
+    for (username, user_id, stake) in refunds {
+        let refund_transaction_result = sqlx::query( "INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, challenge_id, user_id) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(username)
+            .bind(TransactionType::ChallengeRefund)
+            .bind("time odds never applied")
+            .bind(msat(stake))
+            .bind(TransactionState::Settled)
+            .bind(challenge.id)
+            .bind(user_id)
+            .execute(&mut tx).await;
+        if let Err(e) = refund_transaction_result {
+            println!("insert transaction failed {}", e);
+            return;
+        }
+
+        let refund_balance = sqlx::query( "UPDATE lightningchess_balance set balance_msat=balance_msat + $1 WHERE user_id=$2")
+            .bind(msat(stake))
+            .bind(user_id)
+            .execute(&mut tx).await;
+        if let Err(e) = refund_balance {
+            println!("error refunding {}: {}", username, e);
+            return;
+        }
+    }
+
+    let completed = sqlx::query("UPDATE challenge SET status=$1, time_odds_token=NULL WHERE id=$2")
+        .bind(ChallengeStatus::Completed)
+        .bind(challenge.id)
+        .execute(&mut tx).await;
+    if let Err(e) = completed {
+        println!("update challenge failed: {}", e);
+        return;
+    }
+
+    match tx.commit().await {
+        Ok(_) => println!("voided challenge {}, time odds never applied", challenge.id),
+        Err(e) => println!("error committing: {}", e)
+    }
+}
+
 #[post("/api/send-payment", data = "<send_payment_request_str>")]
-pub async fn send_payment_endpoint(user: User, pool: &State<Pool<Postgres>>, send_payment_request_str: String) -> Result<String, Status> {
+pub async fn send_payment_endpoint(user: User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, send_payment_request_str: String) -> Result<String, PaymentError> {
     println!("send_payment_request_str: {}", send_payment_request_str);
     let send_payment_result: Result<SendPaymentRequest, serde_json::Error> = serde_json::from_str(&send_payment_request_str);
     let send_payment = match send_payment_result {
         Ok(sp) => sp,
         Err(e) => {
             println!("error: {}", e);
-            return Err(Status::BadRequest)
+            return Err(Status::BadRequest.into())
         }
     };
 
-    // decode
-    let decoded_option = decode_payment(&send_payment.payment_request).await;
-    let decoded_payment = match decoded_option {
-        Some(dp) => dp,
-        None => return Err(Status::BadRequest)
+    // a bolt11 invoice, with an amount only if it's a zero-amount one, or one fetched from an lnurl for the amount given
+    let explicit_msat = match (send_payment.sats, send_payment.msat) {
+        (Some(sats), None) => Some(msat(sats)),
+        (None, Some(value_msat)) => Some(value_msat),
+        (None, None) => None,
+        _ => return Err(Status::BadRequest.into())
+    };
+    let (payment_request, decoded_payment, withdrawal_amt) = match (send_payment.payment_request, send_payment.lnurl, explicit_msat) {
+        (Some(payment_request), None, explicit_msat) => {
+            // checked here so nothing reaches lnd for the wrong network, expired or badly signed
+            let decoded = bolt11::decode(&payment_request, app_config.network, Utc::now())?;
+            let amount_msat = bolt11::amount_to_pay(&decoded, explicit_msat)?;
+            (payment_request, decoded, amount_msat)
+        },
+        (None, Some(lnurl), Some(amount_msat)) if amount_msat > 0 => match fetch_invoice(app_config.network, app_config.allow_loopback_lnurl, &lnurl, amount_msat).await {
+            Some((payment_request, decoded)) => (payment_request, decoded, amount_msat),
+            None => return Err(Status::BadRequest.into())
+        },
+        _ => return Err(Status::BadRequest.into())
+    };
+    // lnd takes an amount only for a zero-amount invoice
+    let amt_msat = match decoded_payment.amount_msat {
+        Some(_) => None,
+        None => Some(withdrawal_amt)
     };
-    let withdrawal_amt = decoded_payment.num_satoshis.parse::<i64>().unwrap();
 
-    // not sure if this is possible
-    if withdrawal_amt < 0 {
-        return Err(Status::BadRequest);
-    }
+    let withdrawal_amt_neg = -withdrawal_amt;
 
-    let balance_result = sqlx::query_as::<_,Balance>( "SELECT * FROM lightningchess_balance WHERE username=$1")
-        .bind(&user.username)
-        .fetch_one(&**pool).await;
-    let balance = match balance_result {
-        Ok(b) => b,
+    let tx_result = pool.begin().await;
+    let mut tx = match tx_result {
+        Ok(t) => t,
         Err(e) => {
-            println!("error: {}", e);
-            return Err(Status::InternalServerError);
+            println!("error creating tx: {}", e);
+            return Err(Status::InternalServerError.into());
         }
     };
+    check_withdrawal(&mut tx, app_config, user.user_id, withdrawal_amt).await?;
 
-    // only send if they have enough money
-    if balance.balance <= withdrawal_amt {
-        return Err(Status::BadRequest);
-    }
-    // if there are any existing open payments, pay them and return fail for this
-
-    let withdrawal_amt_neg = withdrawal_amt * -1;
-
-    // insert payment into transactions table with status == 0PEN, commit
+    // insert payment into transactions table with status == OPEN and reserve the funds, commit
     // if we don't do this, we never have a way to retry if the update the db fails after the payment is made
-    let withdrawal_ttype = "withdrawal";
+    let withdrawal_ttype = TransactionType::Withdrawal;
     let withdrawal_detail = "";
-    let withdrawal_state = "OPEN";
-    let withdrawal_transaction_result = sqlx::query_as::<_, Transaction>( "INSERT INTO lightningchess_transaction (username, ttype, detail, amount, state, payment_hash) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *")
+    let withdrawal_state = TransactionState::Open;
+    let withdrawal_transaction_result = sqlx::query_as::<_, Transaction>( "INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, payment_hash, user_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *")
         .bind(&user.username)
         .bind(withdrawal_ttype)
         .bind(withdrawal_detail)
-        .bind(&withdrawal_amt_neg)
+        .bind(withdrawal_amt_neg)
         .bind(withdrawal_state)
         .bind(&decoded_payment.payment_hash)
-        .fetch_one(&**pool).await;
+        .bind(user.user_id)
+        .fetch_one(&mut tx).await;
 
     let withdrawal_transaction = match withdrawal_transaction_result {
         Ok(t) => {
@@ -534,59 +869,108 @@ pub async fn send_payment_endpoint(user: User, pool: &State<Pool<Postgres>>, sen
         },
         Err(e) => {
             println!("insert transaction failed {}", e);
-            return Err(Status::BadRequest);
+            return Err(Status::BadRequest.into());
         }
     };
 
+    // only send if they have enough money, balance_not_negative rejects overdrafts
+    let reserved_balance = sqlx::query( "UPDATE lightningchess_balance set balance_msat=balance_msat + $1 WHERE user_id=$2")
+        .bind(withdrawal_amt_neg)
+        .bind(user.user_id)
+        .execute(&mut tx).await;
+
+    match reserved_balance {
+        Ok(r) if r.rows_affected() == 1 => println!("reserved withdrawal"),
+        Ok(_) => return Err(Status::PaymentRequired.into()),
+        Err(e) if is_insufficient_funds(&e) => return Err(Status::PaymentRequired.into()),
+        Err(e) => {
+            println!("error reserving withdrawal {}", e);
+            return Err(Status::InternalServerError.into());
+        }
+    }
+
+    if let Err(e) = tx.commit().await {
+        println!("error committing: {}", e);
+        return Err(Status::InternalServerError.into());
+    }
+
+    match finish_withdrawal(pool, &app_config.lnd_url, user.user_id, withdrawal_transaction.transaction_id, withdrawal_amt, &payment_request, amt_msat).await {
+        Ok(TransactionState::Settled) => {
+            let send_payment_response = SendPaymentResponse {
+                complete: true
+            };
+            Ok(serde_json::to_string(&send_payment_response).unwrap())
+        },
+        Ok(_) => Err(Status::InternalServerError.into()),
+        Err(status) => Err(status.into())
+    }
+}
+
+// pays a withdrawal whose funds are already reserved, then settles it or fails it and gives the funds back.
+// amt_msat is what to send for a zero-amount invoice, None for one with an amount
+pub async fn finish_withdrawal(pool: &Pool<Postgres>, lnd_url: &str, user_id: i32, transaction_id: i32, amount_msat: i64, payment_request: &str, amt_msat: Option<i64>) -> Result<TransactionState, Status> {
     // send payment to lightning node
-    make_payment(&send_payment.payment_request).await;
+    let new_state = match make_payment(lnd_url, payment_request, amt_msat).await {
+        Some(true) => TransactionState::Settled,
+        Some(false) => TransactionState::Failed,
+        None => {
+            // payment may still be in flight, leave the withdrawal OPEN and the funds reserved
+            println!("unknown payment state for transaction id {}", transaction_id);
+            return Err(Status::InternalServerError);
+        }
+    };
 
+    record_withdrawal(pool, user_id, transaction_id, amount_msat, new_state).await?;
+    Ok(new_state)
+}
+
+// settles or fails an OPEN withdrawal, giving the reserved funds back if it failed;
+// whoever gets to it second, the payment or a reconciliation pass, finds it no longer OPEN and leaves it
+async fn record_withdrawal(pool: &Pool<Postgres>, user_id: i32, transaction_id: i32, amount_msat: i64, new_state: TransactionState) -> Result<(), Status> {
     let tx_result = pool.begin().await;
     let mut tx = match tx_result {
         Ok(t) => t,
         Err(e) => {
             println!("error creating tx: {}", e);
-            return Err(Status::BadRequest);
+            return Err(Status::InternalServerError);
         }
     };
 
-    let new_state = "SETTLED";
-    let updated_transaction = sqlx::query( "UPDATE lightningchess_transaction SET state=$1, amount=$2 WHERE transaction_id=$3")
+    let updated_transaction = sqlx::query( "UPDATE lightningchess_transaction SET state=$1 WHERE transaction_id=$2 AND state='OPEN'")
         .bind(new_state)
-        .bind(&withdrawal_amt_neg)
-        .bind(withdrawal_transaction.transaction_id)
+        .bind(transaction_id)
         .execute(&mut tx).await;
 
     match updated_transaction {
-        Ok(_) => println!("successfully updated_transaction transaction id"),
+        Ok(r) if r.rows_affected() == 1 => println!("successfully updated_transaction transaction id {}", transaction_id),
+        Ok(_) => {
+            println!("withdrawal {} already recorded", transaction_id);
+            return Ok(());
+        },
         Err(e) => {
             println!("error updated_transaction transaction id : {}", e);
             return Err(Status::InternalServerError);
         }
     }
 
-    let winner_balance = sqlx::query( "UPDATE lightningchess_balance set balance=balance + $1 WHERE username=$2")
-        .bind(&withdrawal_amt_neg)
-        .bind(&user.username)
-        .execute(&mut tx).await;
+    if new_state == TransactionState::Failed {
+        // nothing left the node, so give the reserved funds back
+        let refunded_balance = sqlx::query( "UPDATE lightningchess_balance set balance_msat=balance_msat + $1 WHERE user_id=$2")
+            .bind(amount_msat)
+            .bind(user_id)
+            .execute(&mut tx).await;
 
-    match winner_balance {
-        Ok(_) => println!("successfully payed admin"),
-        Err(e) => {
-            println!("error paying admin admin_transaction transaction{}", e);
+        if let Err(e) = refunded_balance {
+            println!("error refunding failed withdrawal {}", e);
             return Err(Status::InternalServerError);
         }
     }
 
     // commit transaction
-    let commit_result = tx.commit().await;
-    return match commit_result {
+    match tx.commit().await {
         Ok(_) => {
             println!("successfully committed");
-            let send_payment_response = SendPaymentResponse {
-                complete: true
-            };
-            Ok(serde_json::to_string(&send_payment_response).unwrap())
+            Ok(())
         },
         Err(e) => {
             println!("error committing: {}", e);
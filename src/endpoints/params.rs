@@ -0,0 +1,55 @@
+use rocket::http::Status;
+use serde::de::DeserializeOwned;
+use sqlx::types::chrono::{DateTime, Utc};
+
+pub const DEFAULT_PAGE_SIZE: i64 = 100;
+
+// query strings are parsed by hand so a bad filter is a 400 rather than silently ignored
+
+pub fn parse_time(name: &str, value: Option<String>) -> Result<Option<DateTime<Utc>>, Status> {
+    match value {
+        Some(v) => match DateTime::parse_from_rfc3339(&v) {
+            Ok(t) => Ok(Some(t.with_timezone(&Utc))),
+            Err(e) => {
+                println!("bad {} {}: {}", name, v, e);
+                Err(Status::BadRequest)
+            }
+        },
+        None => Ok(None)
+    }
+}
+
+// enums use the same names as in the json responses, e.g. ttype=create%20challenge
+pub fn parse_enum<T: DeserializeOwned>(name: &str, value: Option<String>) -> Result<Option<T>, Status> {
+    match value {
+        Some(v) => match serde_json::from_value(serde_json::Value::String(v.clone())) {
+            Ok(t) => Ok(Some(t)),
+            Err(e) => {
+                println!("bad {} {}: {}", name, v, e);
+                Err(Status::BadRequest)
+            }
+        },
+        None => Ok(None)
+    }
+}
+
+pub fn parse_id(name: &str, value: Option<String>) -> Result<Option<i32>, Status> {
+    match value {
+        Some(v) => match v.parse::<i32>() {
+            Ok(id) => Ok(Some(id)),
+            Err(e) => {
+                println!("bad {} {}: {}", name, v, e);
+                Err(Status::BadRequest)
+            }
+        },
+        None => Ok(None)
+    }
+}
+
+pub fn page_size(value: Option<String>) -> Result<i64, Status> {
+    match parse_id("limit", value)? {
+        Some(limit) if limit > 0 => Ok(DEFAULT_PAGE_SIZE.min(limit as i64)),
+        Some(_) => Err(Status::BadRequest),
+        None => Ok(DEFAULT_PAGE_SIZE)
+    }
+}
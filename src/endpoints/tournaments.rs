@@ -0,0 +1,889 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use rocket::http::Status;
+use rocket::State;
+use sqlx::{Pool, Postgres};
+use chrono::Duration;
+use sqlx::types::chrono::Utc;
+use crate::db::{ensure_user, is_insufficient_funds, is_violation};
+use crate::endpoints::limits::check_stake;
+use crate::endpoints::params::{page_size, parse_enum, parse_id};
+use crate::ledger::{msat, split};
+use crate::lichess::clock::{is_valid_clock, DEFAULT_TIME_LIMIT};
+use crate::lichess::games::{create_challenge, export_game};
+use crate::lichess::tournaments::{fetch_results, fetch_tournament, is_finished};
+use crate::models::{AppConfig, Balance, LichessChallenge, LichessChallengeClock, PairingResult, Tournament, TournamentDetail, TournamentEntry, TournamentKind, TournamentPage, TournamentPairing, TournamentStatus, TransactionState, TransactionType, User};
+
+// swiss needs fewer rounds than players, this also keeps a tournament to a sitting
+pub const MAX_TOURNAMENT_ROUNDS: i32 = 11;
+
+// a swiss tournament we pair, or a lichess arena or swiss linked by its id
+// linked tournaments take their name, clock and start time from lichess
+#[post("/api/tournament", data = "<tournament_request>")]
+pub async fn create_tournament(user: User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, tournament_request: String) -> Result<String, Status> {
+    println!("tournament request!: {}", tournament_request);
+    let mut tournament: Tournament = match serde_json::from_str(&tournament_request) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("error: {}", e);
+            return Err(Status::BadRequest)
+        }
+    };
+
+    match (tournament.kind, &tournament.lichess_tournament_id) {
+        (TournamentKind::Swiss, None) => {
+            if !tournament.rounds.is_some_and(|rounds| (1..=MAX_TOURNAMENT_ROUNDS).contains(&rounds)) {
+                return Err(Status::BadRequest)
+            }
+            let time_limit = tournament.time_limit.unwrap_or(DEFAULT_TIME_LIMIT);
+            let increment = tournament.increment.unwrap_or(0);
+            if !is_valid_clock(tournament.variant, time_limit, increment) {
+                println!("clock not allowed for {}", tournament.variant.as_str());
+                return Err(Status::BadRequest)
+            }
+            tournament.time_limit = Some(time_limit);
+            tournament.increment = Some(increment);
+        },
+        (TournamentKind::Swiss, Some(_)) | (_, None) => return Err(Status::BadRequest),
+        (kind, Some(lichess_id)) => {
+            if tournament.rounds.is_some() {
+                return Err(Status::BadRequest)
+            }
+            let linked = match fetch_tournament(&app_config.lichess_url, kind, lichess_id).await {
+                Some(t) => t,
+                None => {
+                    println!("no lichess {:?} {}", kind, lichess_id);
+                    return Err(Status::BadRequest)
+                }
+            };
+            if tournament.name.trim().is_empty() {
+                tournament.name = linked.name;
+            }
+            tournament.starts_at = linked.starts_at;
+            tournament.time_limit = Some(linked.clock.limit);
+            tournament.increment = Some(linked.clock.increment);
+        }
+    }
+
+    if tournament.name.trim().is_empty() || tournament.buy_in <= 0 || tournament.min_players < 2 {
+        return Err(Status::BadRequest)
+    }
+    if tournament.max_players.is_some_and(|max| max < tournament.min_players) {
+        return Err(Status::BadRequest)
+    }
+    // entries close when it starts
+    if tournament.starts_at <= Utc::now() {
+        return Err(Status::BadRequest)
+    }
+    // every paid place has to be filled, so there can't be more of them than min_players
+    let prizes = &tournament.prizes;
+    if prizes.is_empty() || prizes.len() > tournament.min_players as usize || prizes.iter().any(|p| *p < 0) || prizes.iter().sum::<i32>() != 100 {
+        return Err(Status::BadRequest)
+    }
+
+    // the pool isn't known yet, so the rule is kept and applied when it's settled
+    let fee = app_config.fees.rule_for_tournament(Utc::now());
+    let created = sqlx::query_as::<_,Tournament>("INSERT INTO tournament (user_id, username, name, buy_in, min_players, max_players, rounds, time_limit, increment, variant, rated, prizes, starts_at, kind, lichess_tournament_id, fee_rate_bps, fee_flat, fee_min, fee_max) \
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19) RETURNING *")
+        .bind(user.user_id)
+        .bind(&user.username)
+        .bind(tournament.name.trim())
+        .bind(tournament.buy_in)
+        .bind(tournament.min_players)
+        .bind(tournament.max_players)
+        .bind(tournament.rounds)
+        .bind(tournament.time_limit)
+        .bind(tournament.increment)
+        .bind(tournament.variant)
+        .bind(tournament.rated)
+        .bind(&tournament.prizes)
+        .bind(tournament.starts_at)
+        .bind(tournament.kind)
+        .bind(&tournament.lichess_tournament_id)
+        .bind(fee.rate_bps as i32)
+        .bind(fee.flat)
+        .bind(fee.min)
+        .bind(fee.max)
+        .fetch_one(&**pool).await;
+
+    match created {
+        Ok(t) => Ok(serde_json::to_string(&t).unwrap()),
+        Err(e) if is_violation(&e, "tournament_lichess_id_key") => Err(Status::Conflict),
+        Err(e) => {
+            println!("insert tournament error: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// pays the buy-in into the pool, entries close at starts_at or when it's full
+#[post("/api/tournament/<tournament_id>/join")]
+pub async fn join_tournament(user: User, pool: &State<Pool<Postgres>>, app_config: &State<AppConfig>, tournament_id: String) -> Result<String, Status> {
+    let tournament_id_int = match tournament_id.parse::<i32>() {
+        Ok(i) => i,
+        Err(_) => return Err(Status::BadRequest)
+    };
+    let mut tx = match pool.begin().await {
+        Ok(t) => t,
+        Err(e) => {
+            println!("error creating tx: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+
+    // locked so the last seat can't be taken twice
+    let tournament = sqlx::query_as::<_,Tournament>("SELECT * FROM tournament WHERE id=$1 FOR UPDATE")
+        .bind(tournament_id_int)
+        .fetch_optional(&mut tx).await;
+    let tournament = match tournament {
+        Ok(Some(t)) => t,
+        Ok(None) => return Err(Status::NotFound),
+        Err(e) => {
+            println!("error getting tournament: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+    if tournament.status != TournamentStatus::Open || tournament.starts_at <= Utc::now() {
+        return Err(Status::BadRequest)
+    }
+    if let Some(max) = tournament.max_players {
+        let entries = sqlx::query_scalar::<_,i64>("SELECT COUNT(*) FROM tournament_entry WHERE tournament_id=$1")
+            .bind(tournament.id)
+            .fetch_one(&mut tx).await;
+        match entries {
+            Ok(n) if n >= max as i64 => return Err(Status::Conflict),
+            Ok(_) => (),
+            Err(e) => {
+                println!("error counting entries: {}", e);
+                return Err(Status::InternalServerError)
+            }
+        }
+    }
+    check_stake(&mut tx, app_config, user.user_id, tournament.buy_in).await?;
+
+    let entry = sqlx::query_as::<_,TournamentEntry>("INSERT INTO tournament_entry (tournament_id, user_id, username) VALUES ($1, $2, $3) RETURNING *")
+        .bind(tournament.id)
+        .bind(user.user_id)
+        .bind(&user.username)
+        .fetch_one(&mut tx).await;
+    let entry = match entry {
+        Ok(e) => e,
+        Err(e) if is_violation(&e, "tournament_entry_once") => return Err(Status::Conflict),
+        Err(e) => {
+            println!("error adding entry: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+
+    // deduct from balance, balance_not_negative rejects overdrafts
+    let balance_result = sqlx::query_as::<_,Balance>( "UPDATE lightningchess_balance SET balance_msat=balance_msat - $1 WHERE user_id=$2 RETURNING *")
+        .bind(msat(tournament.buy_in))
+        .bind(user.user_id)
+        .fetch_one(&mut tx).await;
+    match balance_result {
+        Ok(_) => println!("updated balance"),
+        Err(e) if is_insufficient_funds(&e) => return Err(Status::PaymentRequired),
+        Err(e) => {
+            println!("error updating balance: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    }
+
+    let detail = format!("entry to {}", tournament.name);
+    if let Err(e) = insert_transaction(&mut tx, user.user_id, &user.username, TransactionType::TournamentEntry, &detail, -msat(tournament.buy_in), tournament.id).await {
+        println!("error inserting transaction: {}", e);
+        return Err(Status::InternalServerError)
+    }
+
+    match tx.commit().await {
+        Ok(_) => Ok(serde_json::to_string(&entry).unwrap()),
+        Err(e) => {
+            println!("error committing: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// refunds the buy-in, only while entries are open
+#[post("/api/tournament/<tournament_id>/leave")]
+pub async fn leave_tournament(user: User, pool: &State<Pool<Postgres>>, tournament_id: String) -> Result<String, Status> {
+    let tournament_id_int = match tournament_id.parse::<i32>() {
+        Ok(i) => i,
+        Err(_) => return Err(Status::BadRequest)
+    };
+    let mut tx = match pool.begin().await {
+        Ok(t) => t,
+        Err(e) => {
+            println!("error creating tx: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+
+    let tournament = sqlx::query_as::<_,Tournament>("SELECT * FROM tournament WHERE id=$1 FOR UPDATE")
+        .bind(tournament_id_int)
+        .fetch_optional(&mut tx).await;
+    let tournament = match tournament {
+        Ok(Some(t)) => t,
+        Ok(None) => return Err(Status::NotFound),
+        Err(e) => {
+            println!("error getting tournament: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+    if tournament.status != TournamentStatus::Open || tournament.starts_at <= Utc::now() {
+        return Err(Status::BadRequest)
+    }
+
+    let removed = sqlx::query("DELETE FROM tournament_entry WHERE tournament_id=$1 AND user_id=$2")
+        .bind(tournament.id)
+        .bind(user.user_id)
+        .execute(&mut tx).await;
+    match removed {
+        Ok(r) if r.rows_affected() == 1 => println!("{} left tournament {}", user.username, tournament.id),
+        Ok(_) => return Err(Status::NotFound),
+        Err(e) => {
+            println!("error removing entry: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    }
+
+    if let Err(e) = credit(&mut tx, user.user_id, &user.username, TransactionType::TournamentRefund, &format!("left {}", tournament.name), msat(tournament.buy_in), tournament.id).await {
+        println!("error refunding entry: {}", e);
+        return Err(Status::InternalServerError)
+    }
+
+    match tx.commit().await {
+        Ok(_) => Ok(serde_json::to_string(&tournament).unwrap()),
+        Err(e) => {
+            println!("error committing: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// newest first, no login needed; status defaults to open
+#[get("/api/tournaments?<status>&<cursor>&<limit>")]
+pub async fn tournaments(pool: &State<Pool<Postgres>>, status: Option<String>, cursor: Option<String>, limit: Option<String>) -> Result<String, Status> {
+    let status: TournamentStatus = parse_enum("status", status)?.unwrap_or_default();
+    let cursor = parse_id("cursor", cursor)?;
+    let limit = page_size(limit)?;
+
+    // one extra row tells us whether there is another page
+    let tournaments = sqlx::query_as::<_,Tournament>("SELECT * FROM tournament WHERE status=$1 \
+        AND ($2::INT IS NULL OR id < $2) \
+        ORDER BY id DESC LIMIT $3")
+        .bind(status)
+        .bind(cursor)
+        .bind(limit + 1)
+        .fetch_all(&**pool).await;
+    match tournaments {
+        Ok(mut tournaments) => {
+            let next_cursor = if tournaments.len() as i64 > limit {
+                tournaments.truncate(limit as usize);
+                tournaments.last().map(|last| last.id)
+            } else {
+                None
+            };
+            Ok(serde_json::to_string(&TournamentPage { tournaments, next_cursor }).unwrap())
+        },
+        Err(e) => {
+            println!("error: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// public, with standings and every round's pairings
+#[get("/api/tournament/<tournament_id>")]
+pub async fn lookup_tournament(pool: &State<Pool<Postgres>>, tournament_id: String) -> Result<String, Status> {
+    let tournament_id_int = match tournament_id.parse::<i32>() {
+        Ok(i) => i,
+        Err(_) => return Err(Status::BadRequest)
+    };
+    let tournament = sqlx::query_as::<_,Tournament>("SELECT * FROM tournament WHERE id=$1")
+        .bind(tournament_id_int)
+        .fetch_optional(&**pool).await;
+    let tournament = match tournament {
+        Ok(Some(t)) => t,
+        Ok(None) => return Err(Status::NotFound),
+        Err(e) => {
+            println!("error getting tournament: {}", e);
+            return Err(Status::InternalServerError)
+        }
+    };
+    let standings = sqlx::query_as::<_,TournamentEntry>("SELECT * FROM tournament_entry WHERE tournament_id=$1 ORDER BY place NULLS LAST, half_points DESC, id")
+        .bind(tournament.id)
+        .fetch_all(&**pool).await;
+    let pairings = sqlx::query_as::<_,TournamentPairing>("SELECT * FROM tournament_pairing WHERE tournament_id=$1 ORDER BY round, id")
+        .bind(tournament.id)
+        .fetch_all(&**pool).await;
+    match (standings, pairings) {
+        (Ok(standings), Ok(pairings)) => Ok(serde_json::to_string(&TournamentDetail { tournament, standings, pairings }).unwrap()),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("error: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// starts or refunds tournaments whose start time has passed, records results and pairs the next round
+// sending a pairing's challenge needs one of its players' tokens, so each player moves their own games on
+// linked tournaments are settled once lichess has finished them
+pub async fn check_pending_tournaments_and_update(user: &User, pool: &Pool<Postgres>, app_config: &AppConfig) {
+    let tournaments = sqlx::query_scalar::<_,i32>("SELECT id FROM tournament t \
+        WHERE (t.status='running' OR (t.status='open' AND t.starts_at <= now())) \
+        AND (t.user_id=$1 OR EXISTS (SELECT 1 FROM tournament_entry e WHERE e.tournament_id=t.id AND e.user_id=$1)) \
+        ORDER BY id DESC LIMIT 100")
+        .bind(user.user_id)
+        .fetch_all(pool).await;
+    let tournaments = match tournaments {
+        Ok(ids) => ids,
+        Err(e) => {
+            println!("error getting tournaments: {}", e);
+            return
+        }
+    };
+
+    // TODO: parallelize this
+    for tournament_id in tournaments {
+        println!("processing tournament {}", tournament_id);
+        update_tournament(user, pool, app_config, tournament_id).await;
+    }
+}
+
+async fn update_tournament(user: &User, pool: &Pool<Postgres>, app_config: &AppConfig, tournament_id: i32) {
+    let mut tx = match pool.begin().await {
+        Ok(t) => t,
+        Err(e) => {
+            println!("error creating tx: {}", e);
+            return
+        }
+    };
+
+    // locked so two players polling at once don't both pair the next round
+    let mut tournament = match lock_tournament(&mut tx, tournament_id).await {
+        Some(t) => t,
+        None => return
+    };
+
+    if tournament.status == TournamentStatus::Open && tournament.starts_at <= Utc::now() {
+        if !start_tournament(&mut tx, &tournament).await {
+            return
+        }
+        tournament = match lock_tournament(&mut tx, tournament_id).await {
+            Some(t) => t,
+            None => return
+        };
+    }
+    // a newly paired round's games are sent in the same poll
+    while tournament.status == TournamentStatus::Running {
+        let round = tournament.current_round;
+        let played = match tournament.kind {
+            TournamentKind::Swiss => play_round(&mut tx, user, app_config, &tournament).await,
+            _ => settle_linked(&mut tx, app_config, &tournament).await
+        };
+        if !played {
+            return
+        }
+        tournament = match lock_tournament(&mut tx, tournament_id).await {
+            Some(t) => t,
+            None => return
+        };
+        if tournament.current_round == round {
+            break
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_) => println!("successfully committed tournament {}", tournament.id),
+        Err(e) => println!("error committing tournament {}: {}", tournament.id, e)
+    }
+}
+
+async fn lock_tournament(tx: &mut sqlx::Transaction<'_, Postgres>, tournament_id: i32) -> Option<Tournament> {
+    let tournament = sqlx::query_as::<_,Tournament>("SELECT * FROM tournament WHERE id=$1 FOR UPDATE")
+        .bind(tournament_id)
+        .fetch_one(&mut *tx).await;
+    match tournament {
+        Ok(t) => Some(t),
+        Err(e) => {
+            println!("error locking tournament {}: {}", tournament_id, e);
+            None
+        }
+    }
+}
+
+async fn fetch_standings(tx: &mut sqlx::Transaction<'_, Postgres>, tournament_id: i32) -> Result<Vec<TournamentEntry>, sqlx::Error> {
+    sqlx::query_as::<_,TournamentEntry>("SELECT * FROM tournament_entry WHERE tournament_id=$1 ORDER BY place NULLS LAST, half_points DESC, id")
+        .bind(tournament_id)
+        .fetch_all(&mut *tx).await
+}
+
+async fn fetch_pairings(tx: &mut sqlx::Transaction<'_, Postgres>, tournament_id: i32) -> Result<Vec<TournamentPairing>, sqlx::Error> {
+    sqlx::query_as::<_,TournamentPairing>("SELECT * FROM tournament_pairing WHERE tournament_id=$1 ORDER BY round, id")
+        .bind(tournament_id)
+        .fetch_all(&mut *tx).await
+}
+
+// unfilled tournaments are canceled and every entry refunded, otherwise round 1 is paired
+// linked tournaments are paired by lichess
+async fn start_tournament(tx: &mut sqlx::Transaction<'_, Postgres>, tournament: &Tournament) -> bool {
+    let entries = match fetch_standings(tx, tournament.id).await {
+        Ok(e) => e,
+        Err(e) => {
+            println!("error getting entries for tournament {}: {}", tournament.id, e);
+            return false
+        }
+    };
+
+    if entries.len() < tournament.min_players as usize {
+        println!("tournament {} has {} of {} players, refunding", tournament.id, entries.len(), tournament.min_players);
+        let detail = format!("{} canceled", tournament.name);
+        for entry in entries.iter() {
+            if let Err(e) = credit(tx, entry.user_id, &entry.username, TransactionType::TournamentRefund, &detail, msat(tournament.buy_in), tournament.id).await {
+                println!("error refunding {}: {}", entry.username, e);
+                return false
+            }
+        }
+        return set_status(tx, tournament.id, TournamentStatus::Canceled).await
+    }
+
+    set_status(tx, tournament.id, TournamentStatus::Running).await
+        && (tournament.kind != TournamentKind::Swiss || pair_round(tx, tournament, 1, &entries, &[]).await)
+}
+
+async fn set_status(tx: &mut sqlx::Transaction<'_, Postgres>, tournament_id: i32, status: TournamentStatus) -> bool {
+    let updated = sqlx::query("UPDATE tournament SET status=$1 WHERE id=$2")
+        .bind(status)
+        .bind(tournament_id)
+        .execute(&mut *tx).await;
+    match updated {
+        Ok(_) => true,
+        Err(e) => {
+            println!("error updating tournament {}: {}", tournament_id, e);
+            false
+        }
+    }
+}
+
+// records finished games of the current round and sends the poller's own challenge,
+// then pairs the next round or settles once every game in the round is over
+async fn play_round(tx: &mut sqlx::Transaction<'_, Postgres>, user: &User, app_config: &AppConfig, tournament: &Tournament) -> bool {
+    let pairings = match fetch_pairings(tx, tournament.id).await {
+        Ok(p) => p,
+        Err(e) => {
+            println!("error getting pairings for tournament {}: {}", tournament.id, e);
+            return false
+        }
+    };
+
+    let mut round_over = true;
+    for pairing in pairings.iter().filter(|p| p.round == tournament.current_round && p.result.is_none()) {
+        let deadline_passed = pairing.waiting_since.unwrap_or(pairing.created_at) + Duration::seconds(app_config.start_deadline) <= Utc::now();
+        let game_id = match &pairing.lichess_game_id {
+            Some(id) => id,
+            None => {
+                let plays_white = pairing.white_user_id == user.user_id;
+                let plays = plays_white || pairing.black_user_id == Some(user.user_id);
+                // neither player has polled since the pairing was made
+                if !plays && deadline_passed {
+                    println!("pairing {} of tournament {} never sent", pairing.id, tournament.id);
+                    if !record_result(tx, pairing, PairingResult::Forfeit).await {
+                        return false
+                    }
+                    continue
+                }
+                round_over = false;
+                if plays && !send_pairing(tx, user, app_config, tournament, pairing, plays_white).await {
+                    return false
+                }
+                continue
+            }
+        };
+
+        // lichess has no game for a challenge that was declined or never accepted,
+        // one it couldn't be asked about is left for the next poll
+        let game = match export_game(&app_config.lichess_url, &user.access_token, game_id).await {
+            Ok(g) => g,
+            Err(_) => {
+                round_over = false;
+                continue
+            }
+        };
+        let result = match game.as_ref().map(|g| (g.status.as_str(), g.winner.as_deref())) {
+            Some(("started", _)) => {
+                round_over = false;
+                continue
+            },
+            None | Some(("created", _)) if deadline_passed => match no_show_result(pairing) {
+                Some(result) => {
+                    println!("pairing {} game {} never started", pairing.id, game_id);
+                    result
+                },
+                None => {
+                    // sent before the challenged player was recorded, send it again
+                    round_over = false;
+                    if !clear_game(tx, pairing).await {
+                        return false
+                    }
+                    continue
+                }
+            },
+            None | Some(("created", _)) => {
+                round_over = false;
+                continue
+            },
+            // the next poll sends it again
+            Some(("aborted" | "noStart", _)) => {
+                round_over = false;
+                if !clear_game(tx, pairing).await {
+                    return false
+                }
+                continue
+            },
+            Some((_, Some("white"))) => PairingResult::White,
+            Some((_, Some("black"))) => PairingResult::Black,
+            _ => PairingResult::Draw
+        };
+        if !record_result(tx, pairing, result).await {
+            return false
+        }
+    }
+
+    if !round_over {
+        return true
+    }
+    let entries = match fetch_standings(tx, tournament.id).await {
+        Ok(e) => e,
+        Err(e) => {
+            println!("error getting standings for tournament {}: {}", tournament.id, e);
+            return false
+        }
+    };
+    if tournament.current_round >= tournament.rounds.unwrap_or(0) {
+        // players on the same points share a place
+        let mut places: Vec<Vec<&TournamentEntry>> = Vec::new();
+        for entry in entries.iter() {
+            match places.last_mut() {
+                Some(place) if place[0].half_points == entry.half_points => place.push(entry),
+                _ => places.push(vec![entry])
+            }
+        }
+        return settle_tournament(tx, tournament, &places).await
+    }
+    pair_round(tx, tournament, tournament.current_round + 1, &entries, &pairings).await
+}
+
+// the challenged player loses a game that never started, None if we don't know who that was
+fn no_show_result(pairing: &TournamentPairing) -> Option<PairingResult> {
+    match pairing.challenged_user_id {
+        Some(id) if id == pairing.white_user_id => Some(PairingResult::Black),
+        Some(_) => Some(PairingResult::White),
+        None => None
+    }
+}
+
+// forgets the pairing's game so either player's next poll sends a new one, the deadline starts again
+async fn clear_game(tx: &mut sqlx::Transaction<'_, Postgres>, pairing: &TournamentPairing) -> bool {
+    let cleared = sqlx::query("UPDATE tournament_pairing SET lichess_game_id=NULL, challenged_user_id=NULL, waiting_since=now() WHERE id=$1")
+        .bind(pairing.id)
+        .execute(&mut *tx).await;
+    match cleared {
+        Ok(_) => true,
+        Err(e) => {
+            println!("error clearing game of pairing {}: {}", pairing.id, e);
+            false
+        }
+    }
+}
+
+async fn send_pairing(tx: &mut sqlx::Transaction<'_, Postgres>, user: &User, app_config: &AppConfig, tournament: &Tournament, pairing: &TournamentPairing, plays_white: bool) -> bool {
+    let (dest_user, dest_user_id, color) = if plays_white {
+        (pairing.black_username.as_deref().unwrap_or_default(), pairing.black_user_id, "white")
+    } else {
+        (pairing.white_username.as_str(), Some(pairing.white_user_id), "black")
+    };
+    let challenge = LichessChallenge {
+        rated: tournament.rated,
+        clock: LichessChallengeClock {
+            limit: tournament.time_limit.unwrap_or(DEFAULT_TIME_LIMIT).to_string(),
+            increment: tournament.increment.unwrap_or(0).to_string(),
+        },
+        color: color.to_string(),
+        variant: tournament.variant.as_str().to_string(),
+        rules: "noClaimWin".to_string(),
+    };
+    let game_id = match create_challenge(&app_config.lichess_url, &user.access_token, dest_user, &challenge).await {
+        Some(id) => id,
+        None => return record_send_failure(tx, pairing).await
+    };
+    let saved = sqlx::query("UPDATE tournament_pairing SET lichess_game_id=$1, challenged_user_id=$2, waiting_since=now(), send_failed_at=NULL WHERE id=$3")
+        .bind(&game_id)
+        .bind(dest_user_id)
+        .bind(pairing.id)
+        .execute(&mut *tx).await;
+    match saved {
+        Ok(_) => {
+            println!("sent round {} game of tournament {} as {}", pairing.round, tournament.id, game_id);
+            true
+        },
+        Err(e) => {
+            println!("error saving game for pairing {}: {}", pairing.id, e);
+            false
+        }
+    }
+}
+
+// only this pairing waits for the next try, false is left for database errors that should roll the poll back
+async fn record_send_failure(tx: &mut sqlx::Transaction<'_, Postgres>, pairing: &TournamentPairing) -> bool {
+    println!("lichess didn't create the challenge for pairing {}", pairing.id);
+    let recorded = sqlx::query("UPDATE tournament_pairing SET send_failed_at=now() WHERE id=$1")
+        .bind(pairing.id)
+        .execute(&mut *tx).await;
+    match recorded {
+        Ok(_) => true,
+        Err(e) => {
+            println!("error recording send failure of pairing {}: {}", pairing.id, e);
+            false
+        }
+    }
+}
+
+async fn record_result(tx: &mut sqlx::Transaction<'_, Postgres>, pairing: &TournamentPairing, result: PairingResult) -> bool {
+    let (white, black) = match result {
+        PairingResult::White | PairingResult::Bye => (2, 0),
+        PairingResult::Black => (0, 2),
+        PairingResult::Draw => (1, 1),
+        PairingResult::Forfeit => (0, 0)
+    };
+    let recorded = sqlx::query("UPDATE tournament_pairing SET result=$1 WHERE id=$2")
+        .bind(result)
+        .bind(pairing.id)
+        .execute(&mut *tx).await;
+    if let Err(e) = recorded {
+        println!("error recording result of pairing {}: {}", pairing.id, e);
+        return false
+    }
+    let scores = sqlx::query("UPDATE tournament_entry SET half_points=half_points + CASE WHEN user_id=$2 THEN $3 ELSE $4 END \
+        WHERE tournament_id=$1 AND user_id IN ($2, $5)")
+        .bind(pairing.tournament_id)
+        .bind(pairing.white_user_id)
+        .bind(white)
+        .bind(black)
+        .bind(pairing.black_user_id)
+        .execute(&mut *tx).await;
+    match scores {
+        Ok(_) => true,
+        Err(e) => {
+            println!("error scoring pairing {}: {}", pairing.id, e);
+            false
+        }
+    }
+}
+
+async fn pair_round(tx: &mut sqlx::Transaction<'_, Postgres>, tournament: &Tournament, round: i32, standings: &[TournamentEntry], previous: &[TournamentPairing]) -> bool {
+    for (white, black) in swiss_pairings(round, standings, previous) {
+        let pairing = sqlx::query_as::<_,TournamentPairing>("INSERT INTO tournament_pairing (tournament_id, round, white_user_id, white_username, black_user_id, black_username) \
+            VALUES ($1, $2, $3, $4, $5, $6) RETURNING *")
+            .bind(tournament.id)
+            .bind(round)
+            .bind(white.user_id)
+            .bind(&white.username)
+            .bind(black.map(|b| b.user_id))
+            .bind(black.map(|b| &b.username))
+            .fetch_one(&mut *tx).await;
+        let pairing = match pairing {
+            Ok(p) => p,
+            Err(e) => {
+                println!("error pairing round {} of tournament {}: {}", round, tournament.id, e);
+                return false
+            }
+        };
+        if black.is_none() && !record_result(tx, &pairing, PairingResult::Bye).await {
+            return false
+        }
+    }
+
+    let updated = sqlx::query("UPDATE tournament SET current_round=$1 WHERE id=$2")
+        .bind(round)
+        .bind(tournament.id)
+        .execute(&mut *tx).await;
+    match updated {
+        Ok(_) => {
+            println!("paired round {} of tournament {}", round, tournament.id);
+            true
+        },
+        Err(e) => {
+            println!("error starting round {} of tournament {}: {}", round, tournament.id, e);
+            false
+        }
+    }
+}
+
+// greedy swiss: the bye goes to the lowest ranked player without one, then each player from the top
+// meets the highest ranked player they haven't met yet, or the next one down if they've met everyone
+// the player who has had white less often gets it, the higher ranked one in odd rounds if equal
+fn swiss_pairings<'a>(round: i32, standings: &'a [TournamentEntry], previous: &[TournamentPairing]) -> Vec<(&'a TournamentEntry, Option<&'a TournamentEntry>)> {
+    let mut met = HashSet::new();
+    let mut byes = HashSet::new();
+    let mut whites: HashMap<i32, i32> = HashMap::new();
+    for p in previous {
+        *whites.entry(p.white_user_id).or_default() += 1;
+        match p.black_user_id {
+            Some(black) => {
+                met.insert((p.white_user_id, black));
+                met.insert((black, p.white_user_id));
+            },
+            None => {
+                byes.insert(p.white_user_id);
+            }
+        }
+    }
+
+    let mut unpaired: Vec<&TournamentEntry> = standings.iter().collect();
+    let mut pairings = Vec::new();
+    if unpaired.len() % 2 == 1 {
+        let bye = unpaired.iter().rposition(|e| !byes.contains(&e.user_id)).unwrap_or(unpaired.len() - 1);
+        pairings.push((unpaired.remove(bye), None));
+    }
+    while !unpaired.is_empty() {
+        let top = unpaired.remove(0);
+        let next = unpaired.iter().position(|e| !met.contains(&(top.user_id, e.user_id))).unwrap_or(0);
+        let other = unpaired.remove(next);
+        let (top_whites, other_whites) = (whites.get(&top.user_id).copied().unwrap_or(0), whites.get(&other.user_id).copied().unwrap_or(0));
+        let top_white = top_whites < other_whites || (top_whites == other_whites && round % 2 == 1);
+        pairings.push(if top_white { (top, Some(other)) } else { (other, Some(top)) });
+    }
+    pairings
+}
+
+// lichess ranks everyone, entrants who didn't play on lichess share last place
+async fn settle_linked(tx: &mut sqlx::Transaction<'_, Postgres>, app_config: &AppConfig, tournament: &Tournament) -> bool {
+    let lichess_id = tournament.lichess_tournament_id.as_deref().unwrap_or_default();
+    let linked = match fetch_tournament(&app_config.lichess_url, tournament.kind, lichess_id).await {
+        Some(t) => t,
+        None => return false
+    };
+    if !is_finished(tournament.kind, &linked) {
+        println!("lichess tournament {} not over yet", lichess_id);
+        return true
+    }
+    let results = match fetch_results(&app_config.lichess_url, tournament.kind, lichess_id).await {
+        Some(r) => r,
+        None => return false
+    };
+    let entries = match fetch_standings(tx, tournament.id).await {
+        Ok(e) => e,
+        Err(e) => {
+            println!("error getting entries for tournament {}: {}", tournament.id, e);
+            return false
+        }
+    };
+
+    let mut places: Vec<Vec<&TournamentEntry>> = results.iter()
+        .filter_map(|r| entries.iter().find(|e| e.username.eq_ignore_ascii_case(&r.username)))
+        .map(|e| vec![e])
+        .collect();
+    let absent: Vec<&TournamentEntry> = entries.iter()
+        .filter(|e| !results.iter().any(|r| e.username.eq_ignore_ascii_case(&r.username)))
+        .collect();
+    if !absent.is_empty() {
+        places.push(absent);
+    }
+    settle_tournament(tx, tournament, &places).await
+}
+
+// the pool less the fee from the tournament's rule is paid by place, players sharing a place split the prizes for the places they cover
+// shares round down to the msat and what they leave over goes to the house as rounding
+async fn settle_tournament(tx: &mut sqlx::Transaction<'_, Postgres>, tournament: &Tournament, places: &[Vec<&TournamentEntry>]) -> bool {
+    let admin = match env::var("ADMIN_ACCOUNT") {
+        Ok(a) => a,
+        Err(e) => {
+            println!("error getting admin account: {}", e);
+            return false
+        }
+    };
+    let entrants = places.iter().map(|place| place.len()).sum::<usize>();
+    let prize_pool = msat(tournament.buy_in) * entrants as i64;
+    let fee = tournament.fee_rule().fee_msat(prize_pool);
+
+    let mut start = 0;
+    let percents: Vec<i64> = places.iter()
+        .map(|place| {
+            let percent = tournament.prizes.iter().skip(start).take(place.len()).map(|p| *p as i64).sum();
+            start += place.len();
+            percent
+        })
+        .collect();
+    let (place_totals, mut rounding) = split(prize_pool - fee, &percents);
+
+    let mut start = 0;
+    for (place, place_total) in places.iter().zip(place_totals) {
+        let (shares, left_over) = split(place_total, &vec![1; place.len()]);
+        rounding += left_over;
+        for (entry, share) in place.iter().zip(shares) {
+            let placed = sqlx::query("UPDATE tournament_entry SET place=$1, prize_msat=$2 WHERE id=$3")
+                .bind(start as i32 + 1)
+                .bind(share)
+                .bind(entry.id)
+                .execute(&mut *tx).await;
+            if let Err(e) = placed {
+                println!("error placing {}: {}", entry.username, e);
+                return false
+            }
+            if share > 0 {
+                let detail = format!("place {} in {}", start + 1, tournament.name);
+                if let Err(e) = credit(tx, entry.user_id, &entry.username, TransactionType::TournamentPrize, &detail, share, tournament.id).await {
+                    println!("error paying {}: {}", entry.username, e);
+                    return false
+                }
+            }
+        }
+        start += place.len();
+    }
+
+    let admin_user_id = match ensure_user(&mut *tx, &admin).await {
+        Ok(id) => id,
+        Err(e) => {
+            println!("error adding admin user {}", e);
+            return false
+        }
+    };
+    if fee > 0 {
+        if let Err(e) = credit(tx, admin_user_id, &admin, TransactionType::Fee, &format!("fee from tournament {}", tournament.id), fee, tournament.id).await {
+            println!("error paying fee: {}", e);
+            return false
+        }
+    }
+    if rounding > 0 {
+        if let Err(e) = credit(tx, admin_user_id, &admin, TransactionType::Rounding, &format!("rounding from tournament {}", tournament.id), rounding, tournament.id).await {
+            println!("error paying rounding: {}", e);
+            return false
+        }
+    }
+
+    println!("settled tournament {}", tournament.id);
+    set_status(tx, tournament.id, TournamentStatus::Completed).await
+}
+
+async fn credit(tx: &mut sqlx::Transaction<'_, Postgres>, user_id: i32, username: &str, ttype: TransactionType, detail: &str, amount: i64, tournament_id: i32) -> Result<(), sqlx::Error> {
+    insert_transaction(tx, user_id, username, ttype, detail, amount, tournament_id).await?;
+    sqlx::query("UPDATE lightningchess_balance SET balance_msat=balance_msat + $1 WHERE user_id=$2")
+        .bind(amount)
+        .bind(user_id)
+        .execute(&mut *tx).await
+        .map(|_| ())
+}
+
+async fn insert_transaction(tx: &mut sqlx::Transaction<'_, Postgres>, user_id: i32, username: &str, ttype: TransactionType, detail: &str, amount: i64, tournament_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, tournament_id, user_id) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+        .bind(username)
+        .bind(ttype)
+        .bind(detail)
+        .bind(amount)
+        .bind(TransactionState::Settled)
+        .bind(tournament_id)
+        .bind(user_id)
+        .execute(&mut *tx).await
+        .map(|_| ())
+}
@@ -1,41 +1,68 @@
-
 pub mod auth {
     use reqwest::Client;
     use rocket::Request;
+    use rocket::http::Status;
     use rocket::request::{FromRequest, Outcome};
-    use crate::models::{Account, User};
+    use sqlx::{Pool, Postgres};
+    use crate::db::find_user_id;
+    use crate::models::{Account, AppConfig, User};
+
+    // None if lichess doesn't accept the token
+    pub async fn fetch_account(lichess_url: &str, token: &str) -> Option<Account> {
+        let bearer = format!("Bearer {token}");
+        let response = Client::new()
+            .get(format!("{lichess_url}/api/account"))
+            .header("Authorization", bearer)
+            .send().await;
+        match response {
+            Ok(res) => {
+                println!("Status: {}", res.status());
+                println!("Headers:\n{:#?}", res.headers());
+                // lichess rejected the token
+                if !res.status().is_success() {
+                    return None
+                }
+                let text = res.text().await;
+                match text {
+                    Ok(text) => Some(serde_json::from_str(&text).unwrap()),
+                    Err(e) => {
+                        println!("error in text():\n{}", e);
+                        None
+                    }
+                }
+            },
+            Err(e) => {
+                println!("error from api/account:\n{}", e);
+                None
+            }
+        }
+    }
 
     #[rocket::async_trait]
     impl<'r> FromRequest<'r> for User {
         type Error = ();
         async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
             let access_token = request.cookies().get("llchess_access_token").map(|c| c.value());
+            let (app_config, pool) = match (request.rocket().state::<AppConfig>(), request.rocket().state::<Pool<Postgres>>()) {
+                (Some(app_config), Some(pool)) => (app_config, pool),
+                _ => return Outcome::Forward(())
+            };
             match access_token {
                 Some(token) => {
-                    let bearer = format!("Bearer {token}");
-                    let response = Client::new()
-                        .get("https://lichess.org/api/account")
-                        .header("Authorization", bearer)
-                        .send().await;
-                    match response {
-                        Ok(res) => {
-                            println!("Status: {}", res.status());
-                            println!("Headers:\n{:#?}", res.headers());
-                            let text = res.text().await;
-                            match text {
-                                Ok(text) => {
-                                    let account: Account = serde_json::from_str(&text).unwrap();
-                                    Outcome::Success(User { access_token: token.to_string(), username: account.username})
-                                }
-                                Err(e) => {
-                                    println!("error in text():\n{}", e);
-                                    Outcome::Forward(())
-                                }
-                            }
+                    let account = match fetch_account(&app_config.lichess_url, token).await {
+                        Some(account) => account,
+                        None => return Outcome::Forward(())
+                    };
+                    // users are only created by the callback, a token that never went through it logs in again
+                    match find_user_id(pool, &account.username).await {
+                        Ok(Some(user_id)) => Outcome::Success(User { access_token: token.to_string(), username: account.username, user_id }),
+                        Ok(None) => {
+                            println!("{} has never logged in", account.username);
+                            Outcome::Forward(())
                         },
                         Err(e) => {
-                            println!("error from api/account:\n{}", e);
-                            Outcome::Forward(())
+                            println!("error getting user {}: {}", account.username, e);
+                            Outcome::Failure((Status::InternalServerError, ()))
                         }
                     }
                 }
@@ -47,4 +74,4 @@ pub mod auth {
         }
     }
 
-}
\ No newline at end of file
+}
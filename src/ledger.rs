@@ -0,0 +1,27 @@
+// Ledger amounts (balances, transactions and fees) are millisatoshis. Stakes and buy-ins are
+// whole sats and become msat when they are taken from a balance.
+//
+// Rounding:
+// - a fee rounds down to the msat
+// - each share of a pot rounds down to the msat
+// - whatever the shares leave over is paid to the house account as a 'rounding' transaction,
+//   so every pot is paid out exactly
+
+pub const MSAT_PER_SAT: i64 = 1000;
+
+pub fn msat(sats: i64) -> i64 {
+    sats * MSAT_PER_SAT
+}
+
+// amount_msat shared in proportion to weights, each share rounded down, and what's left over
+pub fn split(amount_msat: i64, weights: &[i64]) -> (Vec<i64>, i64) {
+    let total: i64 = weights.iter().sum();
+    if total <= 0 {
+        return (vec![0; weights.len()], amount_msat)
+    }
+    let shares: Vec<i64> = weights.iter()
+        .map(|w| (amount_msat as i128 * *w as i128 / total as i128) as i64)
+        .collect();
+    let remainder = amount_msat - shares.iter().sum::<i64>();
+    (shares, remainder)
+}
@@ -0,0 +1,94 @@
+#[macro_use] extern crate rocket;
+
+use crate::config::parse_config;
+use crate::db::run_migrations;
+use crate::endpoints::callback::callback;
+use crate::endpoints::challenge::{accept_challenge, create_challenge, lookup_challenge, challenges, challenge_counts, lobby, rematch};
+use crate::endpoints::export::export_transactions;
+use crate::endpoints::matches::{accept_match, create_match, lookup_match};
+use crate::endpoints::money::{add_invoice_endpoint, balance, transactions, transactions_summary, lookup_transaction, send_payment_endpoint};
+use crate::endpoints::limits::limits;
+use crate::endpoints::lnurl::{create_withdraw_link, pay_callback, pay_request, withdraw_callback, withdraw_request};
+use crate::endpoints::login::login;
+use crate::endpoints::profile::profile;
+use crate::endpoints::tournaments::{create_tournament, join_tournament, leave_tournament, lookup_tournament, tournaments};
+use crate::models::AppConfig;
+use rocket::fairing::AdHoc;
+use rocket::{Build, Rocket, State};
+use rocket_dyn_templates::Template;
+use std::collections::HashMap;
+use rocket::response::Redirect;
+use sqlx::{Pool, Postgres};
+
+pub mod guard;
+pub mod models;
+pub mod lightning;
+pub mod lichess;
+pub mod endpoints;
+pub mod config;
+pub mod db;
+pub mod ledger;
+
+
+#[get("/")]
+async fn index(app_config: &State<AppConfig>) -> Template {
+    let mut context = HashMap::new();
+    context.insert("fe_url", app_config.fe_url.to_string());
+    Template::render("index", &context)
+}
+
+#[get("/<any_str>", rank = 100)]
+async fn index_catch_all(app_config: &State<AppConfig>, any_str: String) -> Template {
+    println!("index catch all / {}", any_str);
+    let mut context = HashMap::new();
+    context.insert("fe_url", app_config.fe_url.to_string());
+    Template::render("index", &context)
+}
+
+#[get("/api/<_any_str>", rank = 2)]
+async fn api_catch_all(_any_str: String) -> Redirect {
+    Redirect::to("/login")
+}
+
+pub fn rocket(pool: Pool<Postgres>) -> Rocket<Build> {
+    rocket::build()
+        .attach(AdHoc::try_on_ignite("appConfig", parse_config))
+        .attach(AdHoc::try_on_ignite("migrations", run_migrations))
+        .manage(pool)
+        .mount("/", routes![
+            index,
+            index_catch_all,
+            api_catch_all,
+            login,
+            callback,
+            profile,
+            create_challenge,
+            accept_challenge,
+            lookup_challenge,
+            challenges,
+            challenge_counts,
+            lobby,
+            rematch,
+            create_match,
+            accept_match,
+            lookup_match,
+            create_tournament,
+            join_tournament,
+            leave_tournament,
+            tournaments,
+            lookup_tournament,
+            add_invoice_endpoint,
+            balance,
+            transactions,
+            transactions_summary,
+            export_transactions,
+            lookup_transaction,
+            send_payment_endpoint,
+            create_withdraw_link,
+            withdraw_request,
+            withdraw_callback,
+            pay_request,
+            pay_callback,
+            limits])
+        .attach(Template::fairing())
+}
@@ -0,0 +1,60 @@
+use std::cmp::Ordering;
+use crate::models::{Challenge, Variant};
+
+// used when a challenge doesn't set time_limit, in seconds
+pub const DEFAULT_TIME_LIMIT: i32 = 300;
+
+// lichess groups games by estimated duration, limit + 40 * increment
+pub fn estimated_seconds(limit: i32, increment: i32) -> i32 {
+    limit + 40 * increment
+}
+
+pub fn speed(limit: i32, increment: i32) -> &'static str {
+    match estimated_seconds(limit, increment) {
+        e if e < 30 => "ultraBullet",
+        e if e < 180 => "bullet",
+        e if e < 480 => "blitz",
+        e if e < 1500 => "rapid",
+        _ => "classical"
+    }
+}
+
+// the initial times lichess offers: 1/4, 1/2, 3/4 and 1 1/2 minutes, then whole minutes up to 3 hours
+fn is_valid_limit(limit: i32) -> bool {
+    matches!(limit, 15 | 30 | 45 | 90) || (limit % 60 == 0 && (0..=10800).contains(&limit))
+}
+
+fn is_valid_increment(increment: i32) -> bool {
+    (0..=180).contains(&increment)
+}
+
+// a clock lichess will accept for this variant, ultrabullet is standard only
+pub fn is_valid_clock(variant: Variant, limit: i32, increment: i32) -> bool {
+    if !is_valid_limit(limit) || !is_valid_increment(increment) || limit + increment == 0 {
+        return false;
+    }
+    variant == Variant::Standard || speed(limit, increment) != "ultraBullet"
+}
+
+pub fn creator_limit(challenge: &Challenge) -> i32 {
+    challenge.time_limit.unwrap_or(DEFAULT_TIME_LIMIT)
+}
+
+pub fn opponent_limit(challenge: &Challenge) -> i32 {
+    challenge.opponent_time_limit.unwrap_or_else(|| creator_limit(challenge))
+}
+
+// lichess challenges have one clock, so the game starts on the shorter one
+pub fn lichess_limit(challenge: &Challenge) -> i32 {
+    creator_limit(challenge).min(opponent_limit(challenge))
+}
+
+// seconds the player with the shorter clock has to add to the other side, and whether that's the creator
+pub fn time_odds(challenge: &Challenge) -> Option<(bool, i32)> {
+    let (creator, opponent) = (creator_limit(challenge), opponent_limit(challenge));
+    match creator.cmp(&opponent) {
+        Ordering::Less => Some((true, opponent - creator)),
+        Ordering::Greater => Some((false, creator - opponent)),
+        Ordering::Equal => None
+    }
+}
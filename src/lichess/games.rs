@@ -0,0 +1,87 @@
+use reqwest::{Client, StatusCode};
+use crate::models::{LichessChallenge, LichessChallengeResponse, LichessExportGameResponse};
+
+// adds seconds to the opponent's clock, lichess's way of giving time odds
+pub async fn add_time(lichess_url: &str, access_token: &str, game_id: &str, seconds: i32) -> bool {
+    let bearer = format!("Bearer {access_token}");
+    let response = Client::new()
+        .post(format!("{}/api/round/{}/add-time/{}", lichess_url, game_id, seconds))
+        .header("Authorization", bearer)
+        .send().await;
+
+    match response {
+        Ok(res) => {
+            println!("Status: {}", res.status());
+            res.status().is_success()
+        },
+        Err(e) => {
+            println!("error from add-time:\n{}", e);
+            false
+        }
+    }
+}
+
+// challenges dest_user from the token's account, returns the lichess game id
+pub async fn create_challenge(lichess_url: &str, access_token: &str, dest_user: &str, challenge: &LichessChallenge) -> Option<String> {
+    let bearer = format!("Bearer {access_token}");
+    let response = Client::new()
+        .post(format!("{}/api/challenge/{}", lichess_url, dest_user))
+        .json(challenge)
+        .header("Authorization", bearer)
+        .send().await;
+
+    match response {
+        Ok(res) => {
+            println!("Status: {}", res.status());
+            match res.json::<LichessChallengeResponse>().await {
+                Ok(created) => Some(created.challenge.id),
+                Err(e) => {
+                    println!("error parsing lichess challenge:\n{}", e);
+                    None
+                }
+            }
+        },
+        Err(e) => {
+            println!("error creating on lichess:\n{}", e);
+            None
+        }
+    }
+}
+
+// Ok(None) only when lichess says there's no such game, e.g. a declined challenge;
+// a failed request, an error status or a body that doesn't parse is an Err to try again later
+pub async fn export_game(lichess_url: &str, access_token: &str, game_id: &str) -> Result<Option<LichessExportGameResponse>, reqwest::Error> {
+    let bearer = format!("Bearer {access_token}");
+    let response = Client::new()
+        .get(format!("{}/game/export/{}", lichess_url, game_id))
+        .header("Authorization", bearer)
+        .header("Accept", "application/json")
+        .send().await;
+
+    match response {
+        Ok(res) if res.status() == StatusCode::NOT_FOUND => {
+            println!("no game {} on lichess", game_id);
+            Ok(None)
+        },
+        Ok(res) => {
+            println!("Status: {}", res.status());
+            match res.error_for_status() {
+                Ok(res) => match res.json::<LichessExportGameResponse>().await {
+                    Ok(game) => Ok(Some(game)),
+                    Err(e) => {
+                        println!("error parsing game export:\n{}", e);
+                        Err(e)
+                    }
+                },
+                Err(e) => {
+                    println!("error exporting game {}:\n{}", game_id, e);
+                    Err(e)
+                }
+            }
+        },
+        Err(e) => {
+            println!("error getting game on lichess:\n{}", e);
+            Err(e)
+        }
+    }
+}
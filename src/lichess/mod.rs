@@ -0,0 +1,4 @@
+pub mod clock;
+pub mod games;
+pub mod users;
+pub mod tournaments;
@@ -0,0 +1,83 @@
+use reqwest::Client;
+use crate::models::{LichessTournament, LichessTournamentResult, TournamentKind};
+
+fn api_path(kind: TournamentKind) -> &'static str {
+    match kind {
+        TournamentKind::LichessSwiss => "swiss",
+        _ => "tournament"
+    }
+}
+
+// None if lichess doesn't know the tournament
+pub async fn fetch_tournament(lichess_url: &str, kind: TournamentKind, id: &str) -> Option<LichessTournament> {
+    let response = Client::new()
+        .get(format!("{}/api/{}/{}", lichess_url, api_path(kind), id))
+        .header("Accept", "application/json")
+        .send().await;
+
+    match response {
+        Ok(res) => {
+            println!("Status: {}", res.status());
+            if !res.status().is_success() {
+                return None;
+            }
+            match res.json::<LichessTournament>().await {
+                Ok(tournament) => Some(tournament),
+                Err(e) => {
+                    println!("error parsing lichess tournament {}: {}", id, e);
+                    None
+                }
+            }
+        },
+        Err(e) => {
+            println!("error getting lichess tournament:\n{}", e);
+            None
+        }
+    }
+}
+
+pub fn is_finished(kind: TournamentKind, tournament: &LichessTournament) -> bool {
+    match kind {
+        TournamentKind::LichessSwiss => tournament.status.as_deref() == Some("finished"),
+        _ => tournament.is_finished
+    }
+}
+
+// final standings, best first
+pub async fn fetch_results(lichess_url: &str, kind: TournamentKind, id: &str) -> Option<Vec<LichessTournamentResult>> {
+    let response = Client::new()
+        .get(format!("{}/api/{}/{}/results", lichess_url, api_path(kind), id))
+        .header("Accept", "application/x-ndjson")
+        .send().await;
+
+    let text = match response {
+        Ok(res) if res.status().is_success() => match res.text().await {
+            Ok(text) => text,
+            Err(e) => {
+                println!("error in text():\n{}", e);
+                return None;
+            }
+        },
+        Ok(res) => {
+            println!("Status: {}", res.status());
+            return None;
+        },
+        Err(e) => {
+            println!("error getting lichess tournament results:\n{}", e);
+            return None;
+        }
+    };
+
+    let mut results = Vec::new();
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        match serde_json::from_str::<LichessTournamentResult>(line) {
+            Ok(result) => results.push(result),
+            Err(e) => {
+                println!("error parsing tournament result {}: {}", line, e);
+                return None;
+            }
+        }
+    }
+    results.sort_by_key(|r| r.rank);
+    Some(results)
+}
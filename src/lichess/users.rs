@@ -0,0 +1,68 @@
+use reqwest::Client;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use crate::lichess::clock::{lichess_limit, speed};
+use crate::models::{Challenge, LichessUser, Variant};
+
+// public profile, None if lichess doesn't know the user
+pub async fn fetch_user(lichess_url: &str, username: &str) -> Option<LichessUser> {
+    let response = Client::new()
+        .get(format!("{}/api/user/{}", lichess_url, username))
+        .header("Accept", "application/json")
+        .send().await;
+
+    match response {
+        Ok(res) => {
+            println!("Status: {}", res.status());
+            if !res.status().is_success() {
+                return None;
+            }
+            match res.text().await {
+                Ok(text) => match serde_json::from_str(&text) {
+                    Ok(user) => Some(user),
+                    Err(e) => {
+                        println!("error parsing lichess user {}: {}", username, e);
+                        None
+                    }
+                },
+                Err(e) => {
+                    println!("error in text():\n{}", e);
+                    None
+                }
+            }
+        },
+        Err(e) => {
+            println!("error from api/user:\n{}", e);
+            None
+        }
+    }
+}
+
+// variant games have their own rating, standard ones are split by speed
+pub fn rating_pool(challenge: &Challenge) -> &'static str {
+    match challenge.variant {
+        Variant::Standard => speed(lichess_limit(challenge), challenge.increment.unwrap_or(0)),
+        variant => variant.as_str()
+    }
+}
+
+pub fn account_created(user: &LichessUser) -> Option<DateTime<Utc>> {
+    Utc.timestamp_millis_opt(user.created_at).single()
+}
+
+// restrictions set on an open challenge, a player without a rating in the pool can't join a rating range
+pub fn is_eligible(challenge: &Challenge, user: &LichessUser, now: DateTime<Utc>) -> bool {
+    if let Some(days) = challenge.min_account_age_days {
+        match account_created(user) {
+            Some(created) if now - created >= Duration::days(days as i64) => (),
+            _ => return false
+        }
+    }
+    if challenge.min_rating.is_none() && challenge.max_rating.is_none() {
+        return true;
+    }
+    let rating = match user.perfs.get(rating_pool(challenge)).and_then(|perf| perf.rating) {
+        Some(rating) => rating,
+        None => return false
+    };
+    challenge.min_rating.is_none_or(|min| rating >= min) && challenge.max_rating.is_none_or(|max| rating <= max)
+}
@@ -0,0 +1,187 @@
+use bech32::u5;
+use chrono::{DateTime, Utc};
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use secp256k1::{Message, PublicKey, Secp256k1};
+use sha2::{Digest, Sha256};
+use crate::models::{Bolt11Invoice, InvoiceError, Network};
+
+// BOLT11 payment requests, decoded here so nothing reaches lnd before it's been checked:
+// ln + network prefix + optional amount, then a timestamp, tagged fields and the signature
+// over all of it
+
+const DEFAULT_EXPIRY: i64 = 3600;
+const SIGNATURE_U5S: usize = 104; // 64 byte signature and a recovery id
+const TIMESTAMP_U5S: usize = 7;
+
+// tagged fields we read, the rest are skipped
+const TAG_PAYMENT_HASH: u8 = 1;
+const TAG_DESCRIPTION: u8 = 13;
+const TAG_PAYEE: u8 = 19;
+const TAG_DESCRIPTION_HASH: u8 = 23;
+const TAG_EXPIRY: u8 = 6;
+
+// decodes payment_request and checks it's for network, unexpired at now and signed by its payee
+pub fn decode(payment_request: &str, network: Network, now: DateTime<Utc>) -> Result<Bolt11Invoice, InvoiceError> {
+    let payment_request = payment_request.trim();
+    let payment_request = payment_request.strip_prefix("lightning:").or(payment_request.strip_prefix("LIGHTNING:")).unwrap_or(payment_request);
+    let (hrp, data, variant) = match bech32::decode(payment_request) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            println!("error decoding payment request: {}", e);
+            return Err(InvoiceError::Malformed)
+        }
+    };
+    if variant != bech32::Variant::Bech32 || data.len() < TIMESTAMP_U5S + SIGNATURE_U5S {
+        return Err(InvoiceError::Malformed)
+    }
+
+    let amount = match hrp.strip_prefix("ln") {
+        Some(rest) => split_network(rest).ok_or(InvoiceError::Malformed)?,
+        None => return Err(InvoiceError::Malformed)
+    };
+    if amount.0 != network.bolt11_prefix() {
+        println!("invoice for {}, node is on {}", amount.0, network.bolt11_prefix());
+        return Err(InvoiceError::WrongNetwork)
+    }
+    let amount_msat = parse_amount(amount.1)?;
+
+    let (signed, signature) = data.split_at(data.len() - SIGNATURE_U5S);
+    let timestamp = to_int(&signed[..TIMESTAMP_U5S]);
+    let mut invoice = Bolt11Invoice {
+        payment_hash: String::new(),
+        amount_msat,
+        timestamp,
+        expiry: DEFAULT_EXPIRY,
+        description: None,
+        description_hash: None,
+        payee: String::new()
+    };
+
+    let mut payee = None;
+    let mut fields = &signed[TIMESTAMP_U5S..];
+    while !fields.is_empty() {
+        if fields.len() < 3 {
+            return Err(InvoiceError::Malformed)
+        }
+        let tag = fields[0].to_u8();
+        let length = to_int(&fields[1..3]) as usize;
+        if fields.len() < 3 + length {
+            return Err(InvoiceError::Malformed)
+        }
+        let field = &fields[3..3 + length];
+        fields = &fields[3 + length..];
+        // fields of the wrong length are skipped, as BOLT11 says readers must
+        match (tag, length) {
+            (TAG_PAYMENT_HASH, 52) => invoice.payment_hash = hex::encode(&to_bytes(field)[..32]),
+            (TAG_DESCRIPTION_HASH, 52) => invoice.description_hash = Some(hex::encode(&to_bytes(field)[..32])),
+            (TAG_PAYEE, 53) => payee = Some(to_bytes(field)[..33].to_vec()),
+            (TAG_DESCRIPTION, _) => match String::from_utf8(to_bytes(field)[..length * 5 / 8].to_vec()) {
+                Ok(description) => invoice.description = Some(description),
+                Err(_) => return Err(InvoiceError::Malformed)
+            },
+            (TAG_EXPIRY, 1..=12) => invoice.expiry = to_int(field),
+            _ => ()
+        }
+    }
+    if invoice.payment_hash.is_empty() {
+        return Err(InvoiceError::MissingPaymentHash)
+    }
+
+    // the signature is over the hrp and the data before it, the data padded out to whole bytes
+    let mut message = hrp.as_bytes().to_vec();
+    message.extend(to_bytes(signed));
+    invoice.payee = check_signature(&message, &to_bytes(signature), payee.as_deref())?;
+
+    if invoice.timestamp.saturating_add(invoice.expiry) <= now.timestamp() {
+        return Err(InvoiceError::Expired)
+    }
+    Ok(invoice)
+}
+
+// what to pay for invoice: its own amount, or for a zero-amount invoice the one given
+pub fn amount_to_pay(invoice: &Bolt11Invoice, amount_msat: Option<i64>) -> Result<i64, InvoiceError> {
+    match (invoice.amount_msat, amount_msat) {
+        (Some(amount), None) => Ok(amount),
+        (None, Some(amount)) if amount > 0 => Ok(amount),
+        (None, Some(_)) => Err(InvoiceError::InvalidAmount),
+        (None, None) => Err(InvoiceError::MissingAmount),
+        (Some(_), Some(_)) => Err(InvoiceError::UnexpectedAmount)
+    }
+}
+
+// the network prefix and the amount after it, longest prefixes first so bcrt isn't read as bc
+fn split_network(rest: &str) -> Option<(&'static str, &str)> {
+    ["bcrt", "tbs", "bc", "tb"].iter()
+        .find(|prefix| rest.starts_with(*prefix))
+        .map(|prefix| (*prefix, &rest[prefix.len()..]))
+}
+
+// an amount in bitcoin with an optional multiplier, in msat
+fn parse_amount(amount: &str) -> Result<Option<i64>, InvoiceError> {
+    if amount.is_empty() {
+        return Ok(None)
+    }
+    let (digits, multiplier) = match amount.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&amount[..i], Some(c)),
+        _ => (amount, None)
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) || digits.starts_with('0') {
+        return Err(InvoiceError::InvalidAmount)
+    }
+    let value = digits.parse::<i64>().map_err(|_| InvoiceError::InvalidAmount)?;
+    let msat = match multiplier {
+        None => value.checked_mul(100_000_000_000),
+        Some('m') => value.checked_mul(100_000_000),
+        Some('u') => value.checked_mul(100_000),
+        Some('n') => value.checked_mul(100),
+        // tenths of a msat, anything below a whole msat can't be paid
+        Some('p') if value % 10 == 0 => Some(value / 10),
+        _ => None
+    };
+    match msat {
+        Some(msat) if msat > 0 => Ok(Some(msat)),
+        _ => Err(InvoiceError::InvalidAmount)
+    }
+}
+
+// the hex payee key: the one in the invoice if it has one, else the one the signature recovers to
+fn check_signature(message: &[u8], signature: &[u8], payee: Option<&[u8]>) -> Result<String, InvoiceError> {
+    let digest = Message::from_digest_slice(&Sha256::digest(message)).map_err(|_| InvoiceError::InvalidSignature)?;
+    let recovery_id = RecoveryId::from_i32(signature[64] as i32).map_err(|_| InvoiceError::InvalidSignature)?;
+    let signature = RecoverableSignature::from_compact(&signature[..64], recovery_id).map_err(|_| InvoiceError::InvalidSignature)?;
+    let secp = Secp256k1::verification_only();
+    let recovered = secp.recover_ecdsa(&digest, &signature).map_err(|_| InvoiceError::InvalidSignature)?;
+    match payee {
+        Some(payee) => {
+            let payee = PublicKey::from_slice(payee).map_err(|_| InvoiceError::InvalidSignature)?;
+            match secp.verify_ecdsa(&digest, &signature.to_standard(), &payee) {
+                Ok(_) => Ok(payee.to_string()),
+                Err(_) => Err(InvoiceError::InvalidSignature)
+            }
+        },
+        None => Ok(recovered.to_string())
+    }
+}
+
+fn to_int(data: &[u5]) -> i64 {
+    data.iter().fold(0, |acc, d| (acc << 5) | d.to_u8() as i64)
+}
+
+// 5 bit groups to bytes, the last one padded with zero bits
+fn to_bytes(data: &[u5]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len() * 5 / 8 + 1);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for d in data {
+        acc = (acc << 5) | d.to_u8() as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    if bits > 0 {
+        bytes.push((acc << (8 - bits)) as u8);
+    }
+    bytes
+}
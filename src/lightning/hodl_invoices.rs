@@ -4,10 +4,10 @@ use serde_json::json;
 use std::{env, str};
 use sha2::{Digest, Sha256};
 
-pub async fn add_hodl_invoice(challenge: &Challenge, preimage_bytes: Vec<u8>) -> Option<AddInvoiceResponse> {
+pub async fn add_hodl_invoice(lnd_url: &str, challenge: &Challenge, preimage_bytes: Vec<u8>) -> Option<AddInvoiceResponse> {
     let macaroon = env::var("LND_MACAROON").unwrap();
-    let sats_str = challenge.sats.unwrap().to_string();
-    let memo = format!("lightningchess.io chess game");
+    let sats_str = challenge.sats.to_string();
+    let memo = "lightningchess.io chess game".to_string();
     let preimage_hash_bytes = Sha256::digest(preimage_bytes);
     let preimage_hash_base64 = base64::encode(preimage_hash_bytes);
     let body = json!({
@@ -16,10 +16,10 @@ pub async fn add_hodl_invoice(challenge: &Challenge, preimage_bytes: Vec<u8>) ->
         "memo": memo,
         "expiry": "1800"
     });
-    println!("body: {}", body.to_string());
+    println!("body: {}", body);
 
     let response = Client::new()
-        .post("https://lightningchess.m.voltageapp.io:8080/v2/invoices/hodl")
+        .post(format!("{lnd_url}/v2/invoices/hodl"))
         .json(&body)
         .header("Grpc-Metadata-macaroon", macaroon)
         .send().await;
@@ -49,18 +49,18 @@ pub async fn add_hodl_invoice(challenge: &Challenge, preimage_bytes: Vec<u8>) ->
     }
 }
 
-pub async fn lookup_hodl_invoice(payment_addr: &str) -> Option<LookupInvoiceResponse> {
+pub async fn lookup_hodl_invoice(lnd_url: &str, payment_addr: &str) -> Option<LookupInvoiceResponse> {
     let macaroon = env::var("LND_MACAROON").unwrap();
     println!("payment_addr: {}", payment_addr);
     let base64_decoded_bytes = base64::decode(payment_addr).unwrap();
     let base64_url_safe_encoded = base64::encode_config(base64_decoded_bytes, base64::URL_SAFE);
     println!("base64_url_safe_encoded: {}", base64_url_safe_encoded);
     let response = Client::new()
-        .get(format!("https://lightningchess.m.voltageapp.io:8080/v2/invoices/lookup?payment_addr={}", base64_url_safe_encoded))
+        .get(format!("{}/v2/invoices/lookup?payment_addr={}", lnd_url, base64_url_safe_encoded))
         .header("Grpc-Metadata-macaroon", macaroon)
         .send().await;
 
-    return match response {
+    match response {
         Ok(res) => {
             println!("Status: {}", res.status());
             println!("Headers:\n{:#?}", res.headers());
@@ -82,22 +82,22 @@ pub async fn lookup_hodl_invoice(payment_addr: &str) -> Option<LookupInvoiceResp
             println!("error from lnd lookup_invoice\n{}", e);
             None
         }
-    };
+    }
 }
 
-pub async fn settle_hodl_invoice(preimage: &str) -> bool {
+pub async fn settle_hodl_invoice(lnd_url: &str, preimage: &str) -> bool {
     let macaroon = env::var("LND_MACAROON").unwrap();
     let body = json!({
         "preimage": preimage
     });
-    println!("preimage body: {}", body.to_string());
+    println!("preimage body: {}", body);
     let response = Client::new()
-        .post("https://lightningchess.m.voltageapp.io:8080/v2/invoices/settle")
+        .post(format!("{lnd_url}/v2/invoices/settle"))
         .json(&body)
         .header("Grpc-Metadata-macaroon", macaroon)
         .send().await;
 
-    return match response {
+    match response {
         Ok(res) => {
 
             println!("Status: {}", res.status());
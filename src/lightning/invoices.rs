@@ -3,20 +3,24 @@ use reqwest::Client;
 use serde_json::json;
 use crate::models::{AddInvoiceResponse};
 
-pub async fn add_invoice(sats: i64, memo: &str, preimage_bytes: Vec<u8>) -> Option<AddInvoiceResponse> {
+pub async fn add_invoice(lnd_url: &str, value_msat: i64, memo: &str, description_hash: Option<&[u8]>, preimage_bytes: Vec<u8>) -> Option<AddInvoiceResponse> {
     let macaroon = env::var("LND_MACAROON").unwrap();
-    let sats_str = sats.to_string();
+    let value_msat_str = value_msat.to_string();
     let preimage_hash_base64 = base64::encode(preimage_bytes);
-    let body = json!({
+    let mut body = json!({
         "r_preimage": preimage_hash_base64,
-        "value": sats_str,
-        "memo": memo,
+        "value_msat": value_msat_str,
         "expiry": "1800"
     });
-    println!("body: {}", body.to_string());
+    // lnd takes a memo or a description_hash, not both
+    match description_hash {
+        Some(hash) => body["description_hash"] = json!(base64::encode(hash)),
+        None => body["memo"] = json!(memo)
+    }
+    println!("body: {}", body);
 
     let response = Client::new()
-        .post("https://lightningchess.m.voltageapp.io:8080/v1/invoices")
+        .post(format!("{lnd_url}/v1/invoices"))
         .json(&body)
         .header("Grpc-Metadata-macaroon", macaroon)
         .send().await;
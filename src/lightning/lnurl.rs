@@ -0,0 +1,194 @@
+use bech32::FromBase32;
+use chrono::Utc;
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, Ipv4Addr};
+use crate::lightning::bolt11;
+use crate::models::{Bolt11Invoice, LnurlPayInvoice, LnurlPayRequest, Network};
+
+const MAX_REDIRECTS: usize = 5;
+
+// the url behind a lightning address (LUD-16), a bech32 lnurl (LUD-01) or an lnurlp:// link (LUD-17),
+// allow_loopback lets it point at this machine or its network, plain http included, which only tests want
+pub fn pay_url(lnurl: &str, allow_loopback: bool) -> Option<Url> {
+    let lnurl = lnurl.trim();
+    let lnurl = lnurl.strip_prefix("lightning:").or(lnurl.strip_prefix("LIGHTNING:")).unwrap_or(lnurl);
+    let url = if let Some((name, domain)) = lnurl.split_once('@') {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c)) {
+            return None
+        }
+        format!("{}://{}/.well-known/lnurlp/{}", scheme_for(domain, allow_loopback), domain, name.to_lowercase())
+    } else if let Some(rest) = lnurl.strip_prefix("lnurlp://") {
+        format!("{}://{}", scheme_for(rest, allow_loopback), rest)
+    } else {
+        let (hrp, data, _) = match bech32::decode(lnurl) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                println!("error decoding lnurl: {}", e);
+                return None
+            }
+        };
+        if hrp != "lnurl" {
+            return None
+        }
+        String::from_utf8(Vec::<u8>::from_base32(&data).ok()?).ok()?
+    };
+    match Url::parse(&url) {
+        Ok(url) if is_allowed(&url, allow_loopback) => Some(url),
+        Ok(url) => {
+            println!("lnurl not over https: {}", url);
+            None
+        },
+        Err(e) => {
+            println!("error parsing lnurl {}: {}", url, e);
+            None
+        }
+    }
+}
+
+// http for a host reachable without tls, https otherwise; Url finds the host, an IPv6 one included
+fn scheme_for(rest: &str, allow_loopback: bool) -> &'static str {
+    match Url::parse(&format!("https://{}", rest)) {
+        Ok(url) if is_plain_http_host(url.host_str().unwrap_or_default(), allow_loopback) => "http",
+        _ => "https"
+    }
+}
+
+// lnurls are https, except on tor, and never at our own network unless tests allow it
+fn is_allowed(url: &Url, allow_loopback: bool) -> bool {
+    let host = url.host_str().unwrap_or_default();
+    if is_internal_host(host) && !allow_loopback {
+        return false
+    }
+    match url.scheme() {
+        "https" => true,
+        "http" => is_plain_http_host(host, allow_loopback),
+        _ => false
+    }
+}
+
+fn is_plain_http_host(host: &str, allow_loopback: bool) -> bool {
+    host.ends_with(".onion") || (allow_loopback && is_internal_host(host))
+}
+
+// localhost, or an IP literal that's loopback, private, link-local (cloud metadata) or otherwise not public;
+// Url keeps the brackets around an IPv6 host
+fn is_internal_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host == "localhost" || host.ends_with(".localhost") {
+        return true
+    }
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => is_internal_ipv4(ip),
+        Ok(IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_internal_ipv4(mapped),
+            None => ip.is_loopback() || ip.is_unspecified() || ip.is_unique_local() || ip.is_unicast_link_local()
+        },
+        Err(_) => false
+    }
+}
+
+fn is_internal_ipv4(ip: Ipv4Addr) -> bool {
+    // 100.64.0.0/10 is carrier-grade NAT
+    let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+    ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast() || shared
+}
+
+pub fn description_hash(metadata: &str) -> String {
+    hex::encode(Sha256::digest(metadata.as_bytes()))
+}
+
+// asks the lnurl's service for an invoice of amount_msat and checks it's the one asked for:
+// the amount and a description_hash committing to the service's metadata
+pub async fn fetch_invoice(network: Network, allow_loopback: bool, lnurl: &str, amount_msat: i64) -> Option<(String, Bolt11Invoice)> {
+    let url = pay_url(lnurl, allow_loopback)?;
+    let pay_request: LnurlPayRequest = get_json(url, allow_loopback).await?;
+    if pay_request.tag != "payRequest" {
+        println!("lnurl is a {}, not a payRequest", pay_request.tag);
+        return None
+    }
+    if amount_msat < pay_request.min_sendable || amount_msat > pay_request.max_sendable {
+        println!("{} msat outside lnurl's {}..{}", amount_msat, pay_request.min_sendable, pay_request.max_sendable);
+        return None
+    }
+
+    let mut callback = match Url::parse(&pay_request.callback) {
+        Ok(url) if is_allowed(&url, allow_loopback) => url,
+        _ => {
+            println!("bad lnurl callback {}", pay_request.callback);
+            return None
+        }
+    };
+    callback.query_pairs_mut().append_pair("amount", &amount_msat.to_string());
+    let invoice: LnurlPayInvoice = get_json(callback, allow_loopback).await?;
+
+    let decoded = match bolt11::decode(&invoice.pr, network, Utc::now()) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            println!("lnurl invoice refused: {}", e.as_str());
+            return None
+        }
+    };
+    if decoded.amount_msat != Some(amount_msat) {
+        println!("lnurl invoice is for {:?} msat, asked for {}", decoded.amount_msat, amount_msat);
+        return None
+    }
+    if decoded.description_hash.as_deref() != Some(description_hash(&pay_request.metadata).as_str()) {
+        println!("lnurl invoice description_hash {:?} doesn't match its metadata", decoded.description_hash);
+        return None
+    }
+    Some((invoice.pr, decoded))
+}
+
+// an lnurl service answers errors with 200 and {"status": "ERROR", "reason": ...}, which doesn't parse as T
+// redirects are followed only to where the lnurl itself could have pointed
+async fn get_json<T: serde::de::DeserializeOwned>(url: Url, allow_loopback: bool) -> Option<T> {
+    let redirects = Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if is_allowed(attempt.url(), allow_loopback) {
+            attempt.follow()
+        } else {
+            println!("lnurl redirect to {} refused", attempt.url());
+            attempt.stop()
+        }
+    });
+    let client = match Client::builder().redirect(redirects).build() {
+        Ok(c) => c,
+        Err(e) => {
+            println!("error building lnurl client: {}", e);
+            return None;
+        }
+    };
+    let response = client
+        .get(url.clone())
+        .header("Accept", "application/json")
+        .send().await;
+
+    match response {
+        Ok(res) => {
+            println!("Status: {}", res.status());
+            if !res.status().is_success() {
+                return None;
+            }
+            match res.text().await {
+                Ok(text) => match serde_json::from_str(&text) {
+                    Ok(body) => Some(body),
+                    Err(e) => {
+                        println!("error parsing lnurl response from {}: {} {}", url, e, text);
+                        None
+                    }
+                },
+                Err(e) => {
+                    println!("error in text():\n{}", e);
+                    None
+                }
+            }
+        },
+        Err(e) => {
+            println!("error from lnurl {}:\n{}", url, e);
+            None
+        }
+    }
+}
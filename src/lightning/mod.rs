@@ -1,3 +1,5 @@
+pub mod bolt11;
 pub mod hodl_invoices;
 pub mod invoices;
+pub mod lnurl;
 pub mod payment;
\ No newline at end of file
@@ -1,58 +1,32 @@
 use std::env;
-use reqwest::Client;
-use serde_json::json;
-use crate::models::{DecodedPayment};
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
 
-pub async fn decode_payment(payment_request: &str) -> Option<DecodedPayment> {
+// Some(true) if lnd reports the payment SUCCEEDED, Some(false) if it FAILED and
+// None if the final state is unknown (the payment may still go through).
+// amt_msat is only for a zero-amount invoice, lnd refuses it for one with an amount
+pub async fn make_payment(lnd_url: &str, payment_request: &str, amt_msat: Option<i64>) -> Option<bool> {
     let macaroon = env::var("LND_MACAROON").unwrap();
 
-    let response = Client::new()
-        .get(format!("https://lightningchess.m.voltageapp.io:8080/v1/payreq/{}", payment_request))
-        .header("Grpc-Metadata-macaroon", macaroon)
-        .send().await;
-
-    return match response {
-        Ok(res) => {
-            println!("Status: {}", res.status());
-            println!("Headers:\n{:#?}", res.headers());
-            let text = res.text().await;
-            match text {
-                Ok(text) => {
-                    println!("text: {}", text);
-                    let json_response: DecodedPayment = serde_json::from_str(&text).unwrap();
-                    println!("DecodedPayment: {}", serde_json::to_string(&json_response).unwrap());
-                    Some(json_response)
-                }
-                Err(e) => {
-                    println!("error in text() :\n{}", e);
-                    None
-                }
-            }
-        },
-        Err(e) => {
-            println!("error from lnd decode_payment\n{}", e);
-            None
-        }
-    };
-}
-
-pub async fn make_payment(payment_request: &str) -> Option<bool> {
-    let macaroon = env::var("LND_MACAROON").unwrap();
-
-    let body = json!({
+    let mut body = json!({
         "payment_request": payment_request,
         "timeout_seconds": 10,
         "max_parts": 3,
         "fee_limit_msat": 10000
     });
-    println!("body: {}", body.to_string());
+    if let Some(amt_msat) = amt_msat {
+        body["amt_msat"] = json!(amt_msat.to_string());
+    }
+    println!("body: {}", body);
 
     let res_result = Client::new()
-        .post(format!("https://lightningchess.m.voltageapp.io:8080/v2/router/send"))
+        .post(format!("{lnd_url}/v2/router/send"))
         .json(&body)
         .header("Grpc-Metadata-macaroon", macaroon)
         .send().await;
 
+    // the router streams one json payment update per line
+    let mut stream = Vec::new();
     match res_result {
         Ok(mut res) => {
             let mut still_chunky = true;
@@ -60,7 +34,10 @@ pub async fn make_payment(payment_request: &str) -> Option<bool> {
                 let chunk_result = res.chunk().await;
                 match chunk_result {
                     Ok(maybe_chunk) => match maybe_chunk {
-                        Some(chunk) => println!("Chunk: {:?}", chunk),
+                        Some(chunk) => {
+                            println!("Chunk: {:?}", chunk);
+                            stream.extend_from_slice(&chunk);
+                        },
                         None => {
                             println!("No chonks");
                             still_chunky = false;
@@ -78,5 +55,81 @@ pub async fn make_payment(payment_request: &str) -> Option<bool> {
             return None;
         }
     }
-    Some(true)
-}
\ No newline at end of file
+
+    match last_status(&stream).as_deref() {
+        Some("SUCCEEDED") => Some(true),
+        Some("FAILED") => Some(false),
+        _ => None
+    }
+}
+
+// what lnd knows about a payment it was asked to make
+#[derive(Debug, PartialEq, Eq)]
+pub enum PaymentStatus {
+    Succeeded,
+    Failed,
+    InFlight,
+    NotFound // never initiated, nothing left the node
+}
+
+// the payment's state from the router's tracking stream, None if lnd couldn't be asked;
+// the stream stays open while the payment is in flight, so only its first update is read
+pub async fn track_payment(lnd_url: &str, payment_hash: &str) -> Option<PaymentStatus> {
+    let macaroon = env::var("LND_MACAROON").unwrap();
+    let hash = match hex::decode(payment_hash) {
+        Ok(h) => base64::encode_config(h, base64::URL_SAFE),
+        Err(e) => {
+            println!("bad payment hash {}: {}", payment_hash, e);
+            return None;
+        }
+    };
+
+    let res_result = Client::new()
+        .get(format!("{lnd_url}/v2/router/track/{hash}"))
+        .header("Grpc-Metadata-macaroon", macaroon)
+        .send().await;
+    let mut res = match res_result {
+        Ok(res) if res.status() == StatusCode::NOT_FOUND => return Some(PaymentStatus::NotFound),
+        Ok(res) => res,
+        Err(e) => {
+            println!("error in v2/router/track :\n{}", e);
+            return None;
+        }
+    };
+
+    let mut stream = Vec::new();
+    while !stream.contains(&b'\n') {
+        match res.chunk().await {
+            Ok(Some(chunk)) => stream.extend_from_slice(&chunk),
+            Ok(None) => break,
+            Err(e) => {
+                println!("error reading payment {}: {}", payment_hash, e);
+                return None;
+            }
+        }
+    }
+
+    // a payment lnd never made comes back as a NOT_FOUND (5) grpc error
+    let first = String::from_utf8_lossy(&stream).lines().next().and_then(|line| serde_json::from_str::<Value>(line).ok());
+    match first {
+        Some(update) if update["error"]["code"] == 5 => Some(PaymentStatus::NotFound),
+        Some(update) => match update["result"]["status"].as_str() {
+            Some("SUCCEEDED") => Some(PaymentStatus::Succeeded),
+            Some("FAILED") => Some(PaymentStatus::Failed),
+            Some("IN_FLIGHT") | Some("INITIATED") => Some(PaymentStatus::InFlight),
+            _ => {
+                println!("unexpected payment update for {}: {}", payment_hash, update);
+                None
+            }
+        },
+        None => None
+    }
+}
+
+fn last_status(stream: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(stream)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|update| update["result"]["status"].as_str().map(|s| s.to_string()))
+        .next_back()
+}
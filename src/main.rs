@@ -1,48 +1,7 @@
-#[macro_use] extern crate rocket;
-
-use crate::config::parse_config;
-use crate::endpoints::callback::callback;
-use crate::endpoints::challenge::{accept_challenge, create_challenge, lookup_challenge, challenges};
-use crate::endpoints::money::{add_invoice_endpoint, balance, transactions, lookup_transaction, send_payment_endpoint};
-use crate::endpoints::login::login;
-use crate::endpoints::profile::profile;
-use crate::models::AppConfig;
-use rocket::fairing::AdHoc;
-use rocket::State;
-use rocket_dyn_templates::Template;
-use std::collections::HashMap;
 use std::env;
-use rocket::response::Redirect;
 use sqlx::postgres::PgPoolOptions;
 
-pub mod guard;
-pub mod models;
-pub mod lightning;
-pub mod endpoints;
-pub mod config;
-
-
-#[get("/")]
-async fn index(app_config: &State<AppConfig>) -> Template {
-    let mut context = HashMap::new();
-    context.insert("fe_url", app_config.fe_url.to_string());
-    Template::render("index", &context)
-}
-
-#[get("/<any_str>", rank = 100)]
-async fn index_catch_all(app_config: &State<AppConfig>, any_str: String) -> Template {
-    println!("index catch all / {}", any_str);
-    let mut context = HashMap::new();
-    context.insert("fe_url", app_config.fe_url.to_string());
-    Template::render("index", &context)
-}
-
-#[get("/api/<_any_str>", rank = 2)]
-async fn api_catch_all(_any_str: String) -> Redirect {
-    Redirect::to("/login")
-}
-
-#[launch]
+#[rocket::launch]
 async fn rocket() -> _ {
 
     let db_url = env::var("DB_URL").unwrap();
@@ -52,24 +11,5 @@ async fn rocket() -> _ {
         .connect(&db_url)
         .await.unwrap();
 
-    rocket::build()
-        .attach(AdHoc::try_on_ignite("appConfig", parse_config))
-        .manage(pool)
-        .mount("/", routes![
-            index,
-            index_catch_all,
-            api_catch_all,
-            login,
-            callback,
-            profile,
-            create_challenge,
-            accept_challenge,
-            lookup_challenge,
-            challenges,
-            add_invoice_endpoint,
-            balance,
-            transactions,
-            lookup_transaction,
-            send_payment_endpoint])
-        .attach(Template::fairing())
+    lightningchess::rocket(pool)
 }
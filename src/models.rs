@@ -1,15 +1,171 @@
+use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
-use sqlx::types::chrono::NaiveDateTime;
+use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::FromRow;
+use rocket::http::Status;
+use crate::ledger::msat;
 
 #[derive(Serialize, Deserialize)]
 pub struct Account {
+    pub id: String,
     pub username: String
 }
 
 pub struct AppConfig {
     pub url: String,
-    pub fe_url: String
+    pub fe_url: String,
+    pub lichess_url: String,
+    pub lnd_url: String,
+    pub fees: FeeSchedule,
+    pub limits: Limits,
+    pub network: Network,
+    pub start_deadline: i64, // seconds a challenged player has to start a game before forfeiting it
+    pub allow_loopback_lnurl: bool // plain http lnurls on localhost, for tests only
+}
+
+// the chain lnd runs on, invoices for any other are refused
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Bitcoin,
+    Testnet,
+    Signet,
+    Regtest
+}
+
+impl Network {
+    // what follows "ln" in a BOLT11 invoice
+    pub fn bolt11_prefix(&self) -> &'static str {
+        match self {
+            Network::Bitcoin => "bc",
+            Network::Testnet => "tb",
+            Network::Signet => "tbs",
+            Network::Regtest => "bcrt"
+        }
+    }
+}
+
+// whole sats, None for no limit
+// the limits section of Rocket.toml, or a user_limits row overriding it for one user
+#[derive(Serialize, Deserialize, FromRow, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    #[serde(default)]
+    pub max_deposit: Option<i64>, // a single invoice
+    #[serde(default)]
+    pub daily_withdrawal: Option<i64>, // over the last 24 hours
+    #[serde(default)]
+    pub max_stake: Option<i64>, // a single stake or buy-in
+    #[serde(default)]
+    pub max_exposure: Option<i64> // stakes and buy-ins in play at once
+}
+
+impl Limits {
+    pub fn is_valid(&self) -> bool {
+        [self.max_deposit, self.daily_withdrawal, self.max_stake, self.max_exposure].iter().all(|l| l.is_none_or(|l| l > 0))
+    }
+
+    // a user's own limits where they have them
+    pub fn or(self, global: Limits) -> Limits {
+        Limits {
+            max_deposit: self.max_deposit.or(global.max_deposit),
+            daily_withdrawal: self.daily_withdrawal.or(global.daily_withdrawal),
+            max_stake: self.max_stake.or(global.max_stake),
+            max_exposure: self.max_exposure.or(global.max_exposure)
+        }
+    }
+}
+
+// GET /api/limits, the limits that apply to this user and how much of them is used
+#[derive(Serialize, Deserialize)]
+pub struct LimitsResponse {
+    #[serde(flatten)]
+    pub limits: Limits,
+    pub withdrawn_msat: i64, // over the last 24 hours
+    pub exposure: i64 // sats in play
+}
+
+// a fee taken from a pot: rate_bps of it plus flat, kept between min and max
+// flat, min and max are whole sats so the config reads the same as stakes
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct FeeRule {
+    #[serde(default)]
+    pub rate_bps: i64, // hundredths of a percent
+    #[serde(default)]
+    pub flat: i64,
+    #[serde(default)]
+    pub min: i64,
+    #[serde(default)]
+    pub max: Option<i64>
+}
+
+impl FeeRule {
+    pub fn is_valid(&self) -> bool {
+        (0..=10000).contains(&self.rate_bps) && self.flat >= 0 && self.min >= 0 && self.max.is_none_or(|max| max >= self.min)
+    }
+
+    // rounded down to the msat and never more than the pot, in i128 like split so a big pot can't overflow
+    pub fn fee_msat(&self, pot_msat: i64) -> i64 {
+        let rate_fee = (pot_msat as i128 * self.rate_bps as i128 / 10000) as i64;
+        let fee = rate_fee.saturating_add(msat(self.flat)).max(msat(self.min));
+        self.max.map_or(fee, |max| fee.min(msat(max))).min(pot_msat)
+    }
+}
+
+// a window where a different rule applies to everything created in it, no fee unless it says otherwise
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FeePromotion {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    #[serde(default)]
+    pub fee: FeeRule
+}
+
+fn default_fee_rule() -> FeeRule {
+    FeeRule { rate_bps: 100, ..FeeRule::default() }
+}
+
+// the fees section of Rocket.toml
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FeeSchedule {
+    #[serde(default = "default_fee_rule")]
+    pub default: FeeRule,
+    #[serde(default)]
+    pub speeds: HashMap<String, FeeRule>, // by lichess speed, e.g. bullet
+    #[serde(default)]
+    pub tournament: Option<FeeRule>, // the default if left out
+    #[serde(default)]
+    pub promotions: Vec<FeePromotion>
+}
+
+impl Default for FeeSchedule {
+    fn default() -> FeeSchedule {
+        FeeSchedule { default: default_fee_rule(), speeds: HashMap::new(), tournament: None, promotions: vec![] }
+    }
+}
+
+impl FeeSchedule {
+    pub fn is_valid(&self) -> bool {
+        self.default.is_valid()
+            && self.speeds.values().all(FeeRule::is_valid)
+            && self.tournament.as_ref().is_none_or(FeeRule::is_valid)
+            && self.promotions.iter().all(|p| p.from < p.to && p.fee.is_valid())
+    }
+
+    fn promotion(&self, now: DateTime<Utc>) -> Option<FeeRule> {
+        self.promotions.iter().find(|p| p.from <= now && now < p.to).map(|p| p.fee)
+    }
+
+    // for a challenge or match game at this speed
+    pub fn rule_for_speed(&self, speed: &str, now: DateTime<Utc>) -> FeeRule {
+        self.promotion(now)
+            .or_else(|| self.speeds.iter().find(|(s, _)| s.eq_ignore_ascii_case(speed)).map(|(_, rule)| *rule))
+            .unwrap_or(self.default)
+    }
+
+    pub fn rule_for_tournament(&self, now: DateTime<Utc>) -> FeeRule {
+        self.promotion(now).or(self.tournament).unwrap_or(self.default)
+    }
 }
 
 pub struct EnvVariables {
@@ -22,6 +178,162 @@ fn default_string() -> String {
 fn default_i32() -> i32 {
     0
 }
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+#[sqlx(type_name = "challenge_status", rename_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ChallengeStatus {
+    #[default]
+    #[sqlx(rename = "WAITING FOR ACCEPTANCE")]
+    #[serde(rename = "WAITING FOR ACCEPTANCE")]
+    WaitingForAcceptance,
+    Accepted,
+    Completed
+}
+
+impl ChallengeStatus {
+    // the challenge_status_transition trigger enforces the same moves in the db
+    pub fn can_become(&self, next: ChallengeStatus) -> bool {
+        matches!((self, next),
+            (ChallengeStatus::WaitingForAcceptance, ChallengeStatus::Accepted) |
+            (ChallengeStatus::Accepted, ChallengeStatus::Completed))
+    }
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "transaction_type", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionType {
+    Invoice,
+    Withdrawal,
+    #[sqlx(rename = "create challenge")]
+    #[serde(rename = "create challenge")]
+    CreateChallenge,
+    #[sqlx(rename = "accept challenge")]
+    #[serde(rename = "accept challenge")]
+    AcceptChallenge,
+    #[sqlx(rename = "create match")]
+    #[serde(rename = "create match")]
+    CreateMatch,
+    #[sqlx(rename = "accept match")]
+    #[serde(rename = "accept match")]
+    AcceptMatch,
+    #[sqlx(rename = "tournament entry")]
+    #[serde(rename = "tournament entry")]
+    TournamentEntry,
+    #[sqlx(rename = "tournament prize")]
+    #[serde(rename = "tournament prize")]
+    TournamentPrize,
+    #[sqlx(rename = "tournament refund")]
+    #[serde(rename = "tournament refund")]
+    TournamentRefund,
+    #[sqlx(rename = "challenge refund")]
+    #[serde(rename = "challenge refund")]
+    ChallengeRefund, // a stake back from a void challenge
+    Fee,
+    Rounding, // what's left of a pot after it's shared out, paid to the house
+    Winnings,
+    Draw
+}
+
+// OPEN, ACCEPTED, SETTLED and CANCELED are lnd's invoice states
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "transaction_state", rename_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TransactionState {
+    Open,
+    Accepted,
+    Settled,
+    Canceled,
+    Failed
+}
+
+impl TransactionState {
+    pub fn from_lnd(state: &str) -> Option<TransactionState> {
+        match state {
+            "OPEN" => Some(TransactionState::Open),
+            "ACCEPTED" => Some(TransactionState::Accepted),
+            "SETTLED" => Some(TransactionState::Settled),
+            "CANCELED" => Some(TransactionState::Canceled),
+            _ => None
+        }
+    }
+
+    // the transaction_state_transition trigger enforces the same moves in the db
+    pub fn can_become(&self, next: TransactionState) -> bool {
+        matches!((self, next),
+            (TransactionState::Open, TransactionState::Accepted) |
+            (TransactionState::Open, TransactionState::Settled) |
+            (TransactionState::Open, TransactionState::Canceled) |
+            (TransactionState::Open, TransactionState::Failed) |
+            (TransactionState::Accepted, TransactionState::Settled) |
+            (TransactionState::Accepted, TransactionState::Canceled))
+    }
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[sqlx(type_name = "piece_color", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    White,
+    Black,
+    #[default]
+    Random
+}
+
+impl Color {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Color::White => "white",
+            Color::Black => "black",
+            Color::Random => "random"
+        }
+    }
+
+    pub fn opposite(&self) -> Color {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+            Color::Random => Color::Random
+        }
+    }
+}
+
+// lichess variant keys
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[sqlx(type_name = "chess_variant", rename_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
+pub enum Variant {
+    #[default]
+    Standard,
+    Chess960,
+    Crazyhouse,
+    Antichess,
+    Atomic,
+    Horde,
+    KingOfTheHill,
+    RacingKings,
+    ThreeCheck
+}
+
+impl Variant {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Variant::Standard => "standard",
+            Variant::Chess960 => "chess960",
+            Variant::Crazyhouse => "crazyhouse",
+            Variant::Antichess => "antichess",
+            Variant::Atomic => "atomic",
+            Variant::Horde => "horde",
+            Variant::KingOfTheHill => "kingOfTheHill",
+            Variant::RacingKings => "racingKings",
+            Variant::ThreeCheck => "threeCheck"
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, FromRow)]
 pub struct Challenge {
@@ -29,16 +341,304 @@ pub struct Challenge {
     pub id: i32,
     #[serde(default = "default_string")]
     pub username: String,
+    #[serde(default = "default_i32")]
+    pub user_id: i32,
     pub time_limit: Option<i32>, // seconds
     pub opponent_time_limit: Option<i32>, // seconds
     pub increment: Option<i32>, // seconds
-    pub color: Option<String>,
-    pub sats: Option<i64>,
-    pub opp_username: String,
-    pub status: Option<String>,
+    #[serde(default)]
+    pub color: Color, // the creator's, resolved when the challenge is accepted
+    pub sats: i64, // the creator's stake
+    pub opponent_sats: Option<i64>, // the opponent's stake, same as sats if left out
+    pub opp_username: Option<String>, // None for an open challenge until someone accepts it
+    pub opp_user_id: Option<i32>,
+    #[serde(default)]
+    pub status: ChallengeStatus,
     pub lichess_challenge_id: Option<String>,
-    pub created_on: Option<NaiveDateTime>, // UTC
-    pub expire_after: Option<i32> // seconds
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+    pub expire_after: Option<i32>, // seconds
+    pub min_rating: Option<i32>, // open challenges only, in the game's lichess rating pool
+    pub max_rating: Option<i32>,
+    pub min_account_age_days: Option<i32>,
+    #[serde(default)]
+    pub variant: Variant,
+    #[serde(default = "default_true")]
+    pub rated: bool,
+    #[serde(default)]
+    pub time_odds_applied: bool,
+    #[serde(default)]
+    pub rematch_of: Option<i32>, // set by the rematch endpoint only
+    #[serde(default)]
+    pub fee_msat: i64 // from the fee schedule when the challenge was created
+}
+
+impl Challenge {
+    pub fn opponent_stake(&self) -> i64 {
+        self.opponent_sats.unwrap_or(self.sats)
+    }
+
+    pub fn pot(&self) -> i64 {
+        self.sats + self.opponent_stake()
+    }
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[sqlx(type_name = "match_format", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum MatchFormat {
+    #[default]
+    #[sqlx(rename = "best of")]
+    #[serde(rename = "best of")]
+    BestOf,
+    #[sqlx(rename = "first to")]
+    #[serde(rename = "first to")]
+    FirstTo
+}
+
+// how a best of match that ends level is decided, first to matches play on until someone gets there
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[sqlx(type_name = "match_tiebreak", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Tiebreak {
+    #[default]
+    Split, // the pot goes back like a drawn challenge
+    #[sqlx(rename = "sudden death")]
+    #[serde(rename = "sudden death")]
+    SuddenDeath, // extra games until one is decisive
+    Armageddon // one extra game where a draw counts as a win for black
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "game_outcome", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum GameOutcome {
+    Creator,
+    Opponent,
+    Draw,
+    Aborted // replayed, doesn't count
+}
+
+// a series of lichess games between two players under one stake, settled once someone clinches it
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct MatchSeries {
+    #[serde(default = "default_i32")]
+    pub id: i32,
+    #[serde(default = "default_string")]
+    pub username: String,
+    #[serde(default = "default_i32")]
+    pub user_id: i32,
+    pub opp_username: String,
+    #[serde(default = "default_i32")]
+    pub opp_user_id: i32,
+    #[serde(default)]
+    pub format: MatchFormat,
+    pub target: i32, // games for best of, wins for first to
+    #[serde(default)]
+    pub tiebreak: Tiebreak,
+    pub sats: i64, // the creator's stake
+    pub opponent_sats: Option<i64>, // the opponent's stake, same as sats if left out
+    pub time_limit: Option<i32>, // seconds, the same clock for every game
+    pub increment: Option<i32>, // seconds
+    #[serde(default)]
+    pub variant: Variant,
+    #[serde(default = "default_true")]
+    pub rated: bool,
+    #[serde(default)]
+    pub color: Color, // the creator's in the first game, resolved on accept and alternating after that
+    #[serde(default)]
+    pub status: ChallengeStatus,
+    #[serde(default)]
+    pub winner_user_id: Option<i32>, // None for a split pot
+    #[serde(default)]
+    pub fee_msat: i64, // from the fee schedule when the match was created
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>
+}
+
+impl MatchSeries {
+    pub fn opponent_stake(&self) -> i64 {
+        self.opponent_sats.unwrap_or(self.sats)
+    }
+
+    pub fn pot(&self) -> i64 {
+        self.sats + self.opponent_stake()
+    }
+}
+
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct MatchGame {
+    pub id: i32,
+    pub match_id: i32,
+    pub game_number: i32, // from 1, aborted games are replayed under the next number
+    pub lichess_game_id: String,
+    pub creator_color: Color,
+    pub armageddon: bool,
+    pub outcome: Option<GameOutcome>, // None while the game is being played
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub challenged_user_id: Option<i32> // who has to accept the lichess challenge, None for older games
+}
+
+// scores are in points, a draw is half a point each and first to matches only count wins
+#[derive(Serialize, Deserialize)]
+pub struct MatchDetail {
+    #[serde(rename = "match")]
+    pub series: MatchSeries,
+    pub games: Vec<MatchGame>,
+    pub creator_score: f64,
+    pub opponent_score: f64
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[sqlx(type_name = "tournament_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TournamentStatus {
+    #[default]
+    Open, // taking entries until starts_at
+    Running,
+    Completed,
+    Canceled // too few entries by starts_at, everyone was refunded
+}
+
+impl TournamentStatus {
+    // the tournament_status_transition trigger enforces the same moves in the db
+    pub fn can_become(&self, next: TournamentStatus) -> bool {
+        matches!((self, next),
+            (TournamentStatus::Open, TournamentStatus::Running) |
+            (TournamentStatus::Open, TournamentStatus::Canceled) |
+            (TournamentStatus::Running, TournamentStatus::Completed))
+    }
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "pairing_result", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum PairingResult {
+    White,
+    Black,
+    Draw,
+    Bye, // a win for the unpaired player
+    Forfeit // neither player sent the challenge in time, no points for either
+}
+
+// swiss is paired here, the lichess kinds are run on lichess and linked by id
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[sqlx(type_name = "tournament_kind", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TournamentKind {
+    #[default]
+    Swiss,
+    #[sqlx(rename = "lichess arena")]
+    #[serde(rename = "lichess arena")]
+    LichessArena,
+    #[sqlx(rename = "lichess swiss")]
+    #[serde(rename = "lichess swiss")]
+    LichessSwiss
+}
+
+fn default_prizes() -> Vec<i32> {
+    vec![100]
+}
+
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct Tournament {
+    #[serde(default = "default_i32")]
+    pub id: i32,
+    #[serde(default = "default_string")]
+    pub username: String, // the organiser
+    #[serde(default = "default_i32")]
+    pub user_id: i32,
+    #[serde(default)]
+    pub kind: TournamentKind,
+    pub lichess_tournament_id: Option<String>, // linked kinds only
+    #[serde(default = "default_string")]
+    pub name: String, // the lichess name if left out of a linked tournament
+    pub buy_in: i64, // sats
+    pub min_players: i32, // refunded if fewer have joined by starts_at
+    pub max_players: Option<i32>,
+    pub rounds: Option<i32>, // swiss only
+    pub time_limit: Option<i32>, // seconds, taken from lichess for linked tournaments
+    pub increment: Option<i32>, // seconds
+    #[serde(default)]
+    pub variant: Variant,
+    #[serde(default = "default_true")]
+    pub rated: bool,
+    #[serde(default = "default_prizes")]
+    pub prizes: Vec<i32>, // percent of the pool after the fee for 1st, 2nd, ..., adding up to 100
+    #[serde(default)]
+    pub starts_at: DateTime<Utc>, // taken from lichess for linked tournaments
+    #[serde(default)]
+    pub status: TournamentStatus,
+    #[serde(default)]
+    pub current_round: i32, // 0 until it starts
+    #[serde(default)]
+    pub fee_rate_bps: i32, // the fee schedule's rule when the tournament was created
+    #[serde(default)]
+    pub fee_flat: i64,
+    #[serde(default)]
+    pub fee_min: i64,
+    #[serde(default)]
+    pub fee_max: Option<i64>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>
+}
+
+impl Tournament {
+    pub fn fee_rule(&self) -> FeeRule {
+        FeeRule { rate_bps: self.fee_rate_bps as i64, flat: self.fee_flat, min: self.fee_min, max: self.fee_max }
+    }
+}
+
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct TournamentEntry {
+    pub id: i32,
+    pub tournament_id: i32,
+    pub user_id: i32,
+    pub username: String,
+    pub half_points: i32, // a win is 2, a draw 1
+    pub place: Option<i32>, // set on completion, tied players share a place
+    pub prize_msat: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>
+}
+
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct TournamentPairing {
+    pub id: i32,
+    pub tournament_id: i32,
+    pub round: i32,
+    pub white_user_id: i32,
+    pub white_username: String,
+    pub black_user_id: Option<i32>, // None for a bye
+    pub black_username: Option<String>,
+    pub lichess_game_id: Option<String>, // sent by whichever player polls first
+    pub result: Option<PairingResult>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub challenged_user_id: Option<i32>, // who has to accept the challenge once it's sent
+    pub waiting_since: Option<DateTime<Utc>>, // the start deadline counts from here, or created_at
+    pub send_failed_at: Option<DateTime<Utc>> // lichess refused the last try at sending the challenge
+}
+
+// standings are best first
+#[derive(Serialize, Deserialize)]
+pub struct TournamentDetail {
+    pub tournament: Tournament,
+    pub standings: Vec<TournamentEntry>,
+    pub pairings: Vec<TournamentPairing>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TournamentPage {
+    pub tournaments: Vec<Tournament>,
+    pub next_cursor: Option<i32> // pass as ?cursor= to get the next (older) page
 }
 
 #[derive(Serialize, Deserialize, FromRow)]
@@ -47,15 +647,52 @@ pub struct Transaction {
     pub transaction_id: i32,
     #[serde(default = "default_string")]
     pub username: String,
-    pub ttype: String,
+    #[serde(default = "default_i32")]
+    pub user_id: i32,
+    pub ttype: TransactionType,
     pub detail: String,
-    pub amount: i64,
-    pub state: String,
+    pub amount_msat: i64,
+    pub state: TransactionState,
     pub preimage: Option<String>, // base64 encoded
     pub payment_addr: Option<String>, // base64 encoded
     pub payment_request: Option<String>,
     pub payment_hash: Option<String>,
-    pub lichess_challenge_id: Option<String>
+    pub lichess_challenge_id: Option<String>,
+    pub challenge_id: Option<i32>,
+    pub match_id: Option<i32>,
+    pub tournament_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TransactionPage {
+    pub transactions: Vec<Transaction>,
+    pub next_cursor: Option<i32> // pass as ?cursor= to get the next (older) page
+}
+
+// one row of GET /api/transactions/export, oldest first
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct LedgerEntry {
+    pub transaction_id: i32,
+    pub created_at: DateTime<Utc>,
+    pub ttype: TransactionType,
+    pub state: TransactionState,
+    pub amount_msat: i64,
+    pub detail: String,
+    pub counterparty: Option<String>, // the other player, for challenge and match transactions
+    pub challenge_id: Option<i32>,
+    pub running_balance_msat: i64 // failed and canceled transactions don't move it
+}
+
+// totals over a period, all positive msat
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct TransactionSummary {
+    pub deposited: i64,
+    pub withdrawn: i64,
+    pub won: i64, // winnings and tournament prizes paid out beyond the stake or buy-in, after fees
+    pub lost: i64, // stakes and buy-ins on completed challenges, matches and tournaments that weren't paid back
+    pub fees: i64 // this user's share of fees on what they won, drew or took a tournament prize from
 }
 
 #[derive(Serialize, Deserialize, FromRow)]
@@ -64,8 +701,23 @@ pub struct Balance {
     pub balance_id: i32,
     #[serde(default = "default_string")]
     pub username: String,
-    pub balance: i64
+    #[serde(default = "default_i32")]
+    pub user_id: i32,
+    pub balance_msat: i64
+}
+#[derive(Serialize, Deserialize)]
+pub struct ChallengePage {
+    pub challenges: Vec<Challenge>,
+    pub next_cursor: Option<i32> // pass as ?cursor= to get the next (older) page
 }
+
+// statuses with no challenges are left out
+#[derive(Serialize, Deserialize, Default)]
+pub struct ChallengeCounts {
+    pub created: HashMap<ChallengeStatus, i64>,
+    pub received: HashMap<ChallengeStatus, i64>
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ChallengeAcceptRequest {
     pub id: i32,
@@ -88,12 +740,17 @@ pub struct LichessChallengeClock {
 
 #[derive(Serialize, Deserialize)]
 pub struct AddInvoiceRequest {
-    pub sats: i64,
+    pub sats: Option<i64>,
+    pub msat: Option<i64>, // instead of sats for an amount that isn't whole sats
 }
 
+// a bolt11 payment_request, or an lnurl with sats or msat to send to it
 #[derive(Serialize, Deserialize)]
 pub struct SendPaymentRequest {
-    pub payment_request: String
+    pub payment_request: Option<String>,
+    pub lnurl: Option<String>, // a lightning address (name@domain) or an lnurl-pay link
+    pub sats: Option<i64>,
+    pub msat: Option<i64>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -101,6 +758,22 @@ pub struct LichessChallengeResponse {
     pub challenge: Url
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct LichessUser {
+    pub id: String,
+    pub username: String,
+    #[serde(rename = "createdAt", default)]
+    pub created_at: i64, // ms since epoch
+    #[serde(default)]
+    pub perfs: HashMap<String, LichessPerf>
+}
+
+// puzzle modes like storm have no rating
+#[derive(Serialize, Deserialize)]
+pub struct LichessPerf {
+    pub rating: Option<i32>
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct LichessExportGameResponse {
     pub id: String,
@@ -109,7 +782,52 @@ pub struct LichessExportGameResponse {
     pub speed: String,
     pub perf: String,
     pub status: String,
-    pub winner: Option<String>
+    pub winner: Option<String>,
+    pub players: Option<LichessGamePlayers>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LichessGamePlayers {
+    pub white: LichessGamePlayer,
+    pub black: LichessGamePlayer
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LichessGamePlayer {
+    pub user: Option<LichessUserRef> // None for anonymous players and the AI
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LichessUserRef {
+    pub id: String,
+    pub name: String
+}
+
+// GET /api/tournament/<id> for arenas and /api/swiss/<id>, only the fields both share that we use
+#[derive(Serialize, Deserialize)]
+pub struct LichessTournament {
+    pub id: String,
+    #[serde(alias = "fullName")]
+    pub name: String,
+    #[serde(rename = "startsAt")]
+    pub starts_at: DateTime<Utc>,
+    pub clock: LichessTournamentClock,
+    #[serde(rename = "isFinished", default)]
+    pub is_finished: bool, // arenas
+    pub status: Option<String> // swiss: created, started or finished
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LichessTournamentClock {
+    pub limit: i32, // seconds
+    pub increment: i32
+}
+
+// one line of the ndjson from the results endpoints, best first
+#[derive(Serialize, Deserialize)]
+pub struct LichessTournamentResult {
+    pub rank: i32,
+    pub username: String
 }
 
 #[derive(Serialize, Deserialize)]
@@ -131,6 +849,7 @@ pub struct Url {
 pub struct User {
     pub access_token: String,
     pub username: String,
+    pub user_id: i32,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -156,19 +875,143 @@ pub struct AddInvoiceResponse {
     pub payment_addr: String
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct DecodedPayment {
-    pub destination: String,
+// a BOLT11 payment request as lightning::bolt11 decodes it, hashes and keys hex encoded
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Bolt11Invoice {
     pub payment_hash: String,
-    pub num_satoshis: String,
-    pub timestamp: String,
-    pub expiry: String,
-    pub description: String,
-    pub description_hash: String,
-    pub fallback_addr: String,
-    pub cltv_expiry: String,
-    pub payment_addr: String,
-    pub num_msat: String
+    pub amount_msat: Option<i64>, // None for a zero-amount invoice, the payer picks the amount
+    pub timestamp: i64,
+    pub expiry: i64, // seconds after timestamp
+    pub description: Option<String>,
+    pub description_hash: Option<String>,
+    pub payee: String
+}
+
+// why a payment request was refused, each sent back to the client as its own error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvoiceError {
+    Malformed,
+    WrongNetwork,
+    Expired,
+    InvalidAmount,
+    MissingPaymentHash,
+    InvalidSignature,
+    MissingAmount, // a zero-amount invoice paid without an amount
+    UnexpectedAmount // an amount given for an invoice that has one
+}
+
+impl InvoiceError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvoiceError::Malformed => "malformed",
+            InvoiceError::WrongNetwork => "wrong_network",
+            InvoiceError::Expired => "expired",
+            InvoiceError::InvalidAmount => "invalid_amount",
+            InvoiceError::MissingPaymentHash => "missing_payment_hash",
+            InvoiceError::InvalidSignature => "invalid_signature",
+            InvoiceError::MissingAmount => "missing_amount",
+            InvoiceError::UnexpectedAmount => "unexpected_amount"
+        }
+    }
+}
+
+// {"error": ...} with a 400 for a refused invoice, a bare status otherwise
+#[derive(Responder)]
+pub enum PaymentError {
+    #[response(status = 400, content_type = "json")]
+    Invoice(String),
+    Status(Status)
+}
+
+impl From<Status> for PaymentError {
+    fn from(status: Status) -> PaymentError {
+        PaymentError::Status(status)
+    }
+}
+
+impl From<InvoiceError> for PaymentError {
+    fn from(e: InvoiceError) -> PaymentError {
+        PaymentError::Invoice(serde_json::json!({ "error": e.as_str() }).to_string())
+    }
+}
+
+// LNURL-pay (LUD-06), amounts in msat
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlPayRequest {
+    pub tag: String,
+    pub callback: String,
+    pub min_sendable: i64,
+    pub max_sendable: i64,
+    pub metadata: String // the invoice's description_hash is the sha256 of this
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LnurlPayInvoice {
+    pub pr: String,
+    #[serde(default)]
+    pub routes: Vec<serde_json::Value> // always empty, LUD-06 still asks for it
+}
+
+// a one-time LNURL-withdraw link, its amount reserved by the OPEN withdrawal transaction_id
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct LnurlWithdrawal {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub k1: String,
+    pub user_id: i32,
+    pub transaction_id: i32,
+    pub amount_msat: i64,
+    pub expires_at: DateTime<Utc>,
+    pub claimed_at: Option<DateTime<Utc>>,
+    pub payment_request: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>
+}
+
+// POST /api/withdraw/lnurl, sats or msat
+#[derive(Serialize, Deserialize)]
+pub struct LnurlWithdrawLinkRequest {
+    pub sats: Option<i64>,
+    pub msat: Option<i64>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LnurlWithdrawLink {
+    pub lnurl: String, // bech32, upper case so it makes a smaller QR code
+    pub transaction_id: i32,
+    pub amount_msat: i64,
+    pub expires_at: DateTime<Utc>
+}
+
+// LNURL-withdraw (LUD-03), amounts in msat
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlWithdrawRequest {
+    pub tag: String,
+    pub callback: String,
+    pub k1: String,
+    pub default_description: String,
+    pub min_withdrawable: i64,
+    pub max_withdrawable: i64
+}
+
+// what an lnurl service answers a wallet's callback with, and any lnurl error
+#[derive(Serialize, Deserialize)]
+pub struct LnurlStatus {
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>
+}
+
+impl LnurlStatus {
+    pub fn ok() -> LnurlStatus {
+        LnurlStatus { status: "OK".to_string(), reason: None }
+    }
+
+    pub fn error(reason: &str) -> LnurlStatus {
+        LnurlStatus { status: "ERROR".to_string(), reason: Some(reason.to_string()) }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -181,5 +1024,6 @@ pub struct LookupInvoiceResponse {
     pub payment_request: String,
     pub expiry: String,
     pub amt_paid_sat: String,
+    pub amt_paid_msat: String,
     pub state: String
 }
\ No newline at end of file
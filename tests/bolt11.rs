@@ -0,0 +1,139 @@
+mod common;
+
+use bech32::{FromBase32, ToBase32};
+use common::bolt11::{payee, InvoiceBuilder};
+use common::spawn_app;
+use lightningchess::lightning::bolt11::{amount_to_pay, decode};
+use lightningchess::models::{InvoiceError, Network};
+use rocket::http::Status;
+use serde_json::{json, Value};
+use sqlx::types::chrono::{TimeZone, Utc};
+
+#[rocket::async_test]
+async fn refused_invoices_never_reach_lnd() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let testnet = InvoiceBuilder::new(Some(1_000_000)).network("tb").build();
+    let expired = InvoiceBuilder::new(Some(1_000_000)).timestamp(1_600_000_000).build();
+    let zero_amount = InvoiceBuilder::new(None).build();
+    let with_amount = InvoiceBuilder::new(Some(1_000_000)).build();
+    for pr in [&testnet, &expired, &zero_amount, &with_amount] {
+        app.lnd.insert_payable(pr, false);
+    }
+
+    for (body, error) in [
+        (json!({ "payment_request": testnet }), "wrong_network"),
+        (json!({ "payment_request": expired }), "expired"),
+        (json!({ "payment_request": zero_amount }), "missing_amount"),
+        (json!({ "payment_request": zero_amount, "sats": 0 }), "invalid_amount"),
+        (json!({ "payment_request": with_amount, "sats": 1000 }), "unexpected_amount"),
+        (json!({ "payment_request": "lnbc1notaninvoice" }), "malformed"),
+    ] {
+        let (status, body) = app.post("/api/send-payment", &alice, body).await;
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["error"], error);
+    }
+    assert!(app.lnd.payments().is_empty());
+    assert_eq!(app.balance_of("alice").await, 10_000);
+}
+
+#[rocket::async_test]
+async fn zero_amount_invoice_paid_with_amount() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let pr = InvoiceBuilder::new(None).build();
+    app.lnd.insert_payable(&pr, false);
+
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "payment_request": pr, "sats": 2500 })).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.lnd.payment_amounts(), vec![Some(2_500_000)]);
+    assert_eq!(app.balance_of("alice").await, 7500);
+
+    // an invoice with an amount goes to lnd without one
+    let pr = app.lnd.add_payable(500, false);
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "payment_request": pr })).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.lnd.payment_amounts(), vec![Some(2_500_000), None]);
+}
+
+#[test]
+fn decodes_what_was_signed() {
+    let now = Utc::now();
+    let hash = "ab".repeat(32);
+    let pr = InvoiceBuilder::new(Some(1_234_567)).description_hash(&hash).expiry(600).with_payee().build();
+    let invoice = decode(&format!("lightning:{pr}"), Network::Bitcoin, now).unwrap();
+    assert_eq!(invoice.amount_msat, Some(1_234_567));
+    assert_eq!(invoice.description_hash, Some(hash));
+    assert_eq!(invoice.expiry, 600);
+    assert_eq!(invoice.payee, payee());
+    assert_eq!(invoice.payment_hash.len(), 64);
+
+    // without the n field the payee is recovered from the signature
+    let invoice = decode(&InvoiceBuilder::new(Some(100)).build(), Network::Bitcoin, now).unwrap();
+    assert_eq!(invoice.payee, payee());
+    assert_eq!(invoice.description.as_deref(), Some("mock invoice"));
+    assert_eq!(invoice.expiry, 3600);
+
+    let regtest = InvoiceBuilder::new(None).network("bcrt").build();
+    assert!(decode(&regtest, Network::Regtest, now).unwrap().amount_msat.is_none());
+    assert_eq!(decode(&regtest, Network::Bitcoin, now).unwrap_err(), InvoiceError::WrongNetwork);
+    let signet = InvoiceBuilder::new(Some(1000)).network("tbs").build();
+    assert_eq!(decode(&signet, Network::Testnet, now).unwrap_err(), InvoiceError::WrongNetwork);
+    assert!(decode(&signet, Network::Signet, now).is_ok());
+}
+
+#[test]
+fn expiry_counts_from_timestamp() {
+    let pr = InvoiceBuilder::new(Some(1000)).timestamp(1_700_000_000).expiry(60).build();
+    assert!(decode(&pr, Network::Bitcoin, Utc.timestamp_opt(1_700_000_059, 0).unwrap()).is_ok());
+    assert_eq!(decode(&pr, Network::Bitcoin, Utc.timestamp_opt(1_700_000_060, 0).unwrap()).unwrap_err(), InvoiceError::Expired);
+}
+
+#[test]
+fn tampered_invoice_fails_signature() {
+    let pr = InvoiceBuilder::new(Some(1_000_000)).with_payee().build();
+    // same signature over a bigger amount, with a valid checksum
+    let (_, data, _) = bech32::decode(&pr).unwrap();
+    let tampered = bech32::encode("lnbc20u", data, bech32::Variant::Bech32).unwrap();
+    assert_eq!(decode(&tampered, Network::Bitcoin, Utc::now()).unwrap_err(), InvoiceError::InvalidSignature);
+}
+
+#[test]
+fn bad_amounts_and_fields() {
+    let now = Utc::now();
+    let pr = InvoiceBuilder::new(Some(1000)).build();
+    let (_, data, _) = bech32::decode(&pr).unwrap();
+    for hrp in ["lnbc1x", "lnbc0n", "lnbc15p", "lnbcn", "lnbc99999999999999999999"] {
+        let pr = bech32::encode(hrp, data.clone(), bech32::Variant::Bech32).unwrap();
+        assert_eq!(decode(&pr, Network::Bitcoin, now).unwrap_err(), InvoiceError::InvalidAmount, "{hrp}");
+    }
+    for hrp in ["lnxx", "bc10n"] {
+        let pr = bech32::encode(hrp, data.clone(), bech32::Variant::Bech32).unwrap();
+        assert_eq!(decode(&pr, Network::Bitcoin, now).unwrap_err(), InvoiceError::Malformed, "{hrp}");
+    }
+    assert_eq!(decode("not an invoice", Network::Bitcoin, now).unwrap_err(), InvoiceError::Malformed);
+    let too_short = bech32::encode("lnbc", vec![0u8; 20].to_base32(), bech32::Variant::Bech32).unwrap();
+    assert_eq!(decode(&too_short, Network::Bitcoin, now).unwrap_err(), InvoiceError::Malformed);
+
+    // just a timestamp and a signature, no payment hash
+    let timestamp = &data[..7];
+    let bytes = Vec::<u8>::from_base32(&data[data.len() - 104..]).unwrap();
+    let mut bare = timestamp.to_vec();
+    bare.extend(bytes.to_base32());
+    let bare = bech32::encode("lnbc10n", bare, bech32::Variant::Bech32).unwrap();
+    assert_eq!(decode(&bare, Network::Bitcoin, now).unwrap_err(), InvoiceError::MissingPaymentHash);
+}
+
+#[test]
+fn amount_to_pay_needs_exactly_one_amount() {
+    let now = Utc::now();
+    let fixed = decode(&InvoiceBuilder::new(Some(5000)).build(), Network::Bitcoin, now).unwrap();
+    let open = decode(&InvoiceBuilder::new(None).build(), Network::Bitcoin, now).unwrap();
+    assert_eq!(amount_to_pay(&fixed, None), Ok(5000));
+    assert_eq!(amount_to_pay(&fixed, Some(5000)), Err(InvoiceError::UnexpectedAmount));
+    assert_eq!(amount_to_pay(&open, Some(7000)), Ok(7000));
+    assert_eq!(amount_to_pay(&open, Some(-1)), Err(InvoiceError::InvalidAmount));
+    assert_eq!(amount_to_pay(&open, None), Err(InvoiceError::MissingAmount));
+}
@@ -0,0 +1,518 @@
+mod common;
+
+use common::{spawn_app, TestApp};
+use lightningchess::models::{Challenge, ChallengeCounts, ChallengePage, ChallengeStatus};
+use rocket::http::Status;
+use serde_json::json;
+
+async fn create(app: &TestApp, token: &str, opp_username: &str, sats: i64) -> (Status, String) {
+    app.post("/api/challenge", token, json!({
+        "time_limit": 300,
+        "increment": 0,
+        "color": "white",
+        "sats": sats,
+        "opp_username": opp_username
+    })).await
+}
+
+#[rocket::async_test]
+async fn challenge_above_balance_is_payment_required() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.login("bob").await;
+    app.fund("alice", 500).await;
+
+    let (status, _) = create(&app, &alice, "bob", 1000).await;
+    assert_eq!(status, Status::PaymentRequired);
+    assert_eq!(app.balance_of("alice").await, 500);
+
+    let (_, body) = app.get("/api/challenges", &alice).await;
+    let challenges = serde_json::from_str::<ChallengePage>(&body).unwrap().challenges;
+    assert!(challenges.is_empty());
+}
+
+#[rocket::async_test]
+async fn malformed_challenge_is_bad_request() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 500).await;
+
+    // a challenge without an opponent is an open challenge, but the stake is required
+    let (status, _) = app.post("/api/challenge", &alice, json!({ "opp_username": "bob" })).await;
+    assert_eq!(status, Status::BadRequest);
+    let (status, _) = app.post("/api/challenge", &alice, json!({ "sats": "lots" })).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(app.balance_of("alice").await, 500);
+}
+
+#[rocket::async_test]
+async fn accept_above_balance_is_payment_required() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 100).await;
+
+    let (_, body) = create(&app, &alice, "bob", 1000).await;
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+
+    let (status, _) = app.post("/api/accept-challenge", &bob, json!({ "id": challenge.id })).await;
+    assert_eq!(status, Status::PaymentRequired);
+    assert_eq!(app.balance_of("bob").await, 100);
+    assert!(app.lichess.challenges().is_empty());
+}
+
+#[rocket::async_test]
+async fn only_the_opponent_can_accept_once() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let carol = app.login("carol").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+    app.fund("carol", 10_000).await;
+
+    let (_, body) = create(&app, &alice, "bob", 1000).await;
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+
+    let (status, _) = app.post("/api/accept-challenge", &carol, json!({ "id": challenge.id })).await;
+    assert_eq!(status, Status::BadRequest);
+    let (status, _) = app.post("/api/accept-challenge", &alice, json!({ "id": challenge.id })).await;
+    assert_eq!(status, Status::BadRequest);
+
+    let (status, _) = app.post("/api/accept-challenge", &bob, json!({ "id": challenge.id })).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = app.post("/api/accept-challenge", &bob, json!({ "id": challenge.id })).await;
+    assert_eq!(status, Status::BadRequest);
+
+    assert_eq!(app.balance_of("bob").await, 9000);
+    assert_eq!(app.balance_of("carol").await, 10_000);
+    assert_eq!(app.lichess.challenges().len(), 1);
+}
+
+#[rocket::async_test]
+async fn concurrent_accepts_send_one_lichess_challenge() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+
+    let (_, body) = create(&app, &alice, "bob", 1000).await;
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    // the first accept is still waiting on lichess when the second comes in
+    let held = app.lichess.hold_challenges().await;
+    let (first, second, _) = tokio::join!(
+        app.post("/api/accept-challenge", &bob, json!({ "id": challenge.id })),
+        app.post("/api/accept-challenge", &bob, json!({ "id": challenge.id })),
+        async {
+            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+            drop(held);
+        }
+    );
+    let mut statuses = vec![first.0, second.0];
+    statuses.sort_by_key(|s| s.code);
+    assert_eq!(statuses, vec![Status::Ok, Status::BadRequest]);
+    assert_eq!(app.balance_of("bob").await, 9000);
+    assert_eq!(app.lichess.challenges().len(), 1);
+}
+
+#[rocket::async_test]
+async fn expired_challenge_cannot_be_accepted() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+
+    let (_, body) = create(&app, &alice, "bob", 1000).await;
+    let directed: Challenge = serde_json::from_str(&body).unwrap();
+    let open = create_open(&app, &alice, 1000, json!({})).await;
+    sqlx::query("UPDATE challenge SET expire_after=60, created_at=now() - INTERVAL '61 seconds' WHERE id=ANY($1)")
+        .bind(vec![directed.id, open.id])
+        .execute(&app.pool).await.unwrap();
+
+    for id in [directed.id, open.id] {
+        let (status, _) = app.post("/api/accept-challenge", &bob, json!({ "id": id })).await;
+        assert_eq!(status, Status::BadRequest);
+    }
+    assert_eq!(app.balance_of("bob").await, 10_000);
+    assert!(app.lichess.challenges().is_empty());
+}
+
+#[rocket::async_test]
+async fn challenges_can_only_be_looked_up_by_players() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let carol = app.login("carol").await;
+    app.fund("alice", 10_000).await;
+
+    let (_, body) = create(&app, &alice, "bob", 1000).await;
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    let path = format!("/api/challenge/{}", challenge.id);
+
+    let (status, _) = app.get(&path, &alice).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = app.get(&path, &bob).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = app.get(&path, &carol).await;
+    assert_eq!(status, Status::Unauthorized);
+    let (status, _) = app.get("/api/challenge/abc", &alice).await;
+    assert_eq!(status, Status::BadRequest);
+
+    let (_, body) = app.get("/api/challenges", &bob).await;
+    let challenges = serde_json::from_str::<ChallengePage>(&body).unwrap().challenges;
+    assert_eq!(challenges.len(), 1);
+    let (_, body) = app.get("/api/challenges", &carol).await;
+    let challenges = serde_json::from_str::<ChallengePage>(&body).unwrap().challenges;
+    assert!(challenges.is_empty());
+}
+
+#[rocket::async_test]
+async fn challenge_must_stake_something() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 500).await;
+
+    let (status, _) = create(&app, &alice, "bob", -1000).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(app.balance_of("alice").await, 500);
+}
+
+#[rocket::async_test]
+async fn concurrent_challenges_cannot_overdraw() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.login("bob").await;
+    app.login("carol").await;
+    app.fund("alice", 1500).await;
+
+    let (first, second) = tokio::join!(
+        create(&app, &alice, "bob", 1000),
+        create(&app, &alice, "carol", 1000)
+    );
+    let mut statuses = vec![first.0, second.0];
+    statuses.sort_by_key(|s| s.code);
+    assert_eq!(statuses, vec![Status::Ok, Status::PaymentRequired]);
+    assert_eq!(app.balance_of("alice").await, 500);
+}
+
+#[rocket::async_test]
+async fn challenges_have_rfc3339_timestamps() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 1000).await;
+    app.fund("bob", 1000).await;
+
+    let (_, body) = create(&app, &alice, "bob", 100).await;
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    app.post("/api/accept-challenge", &bob, json!({ "id": challenge.id })).await;
+
+    let (_, body) = app.get("/api/challenges", &alice).await;
+    let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let challenges = page["challenges"].as_array().unwrap();
+    let created_at = chrono::DateTime::parse_from_rfc3339(challenges[0]["created_at"].as_str().unwrap()).unwrap();
+    let updated_at = chrono::DateTime::parse_from_rfc3339(challenges[0]["updated_at"].as_str().unwrap()).unwrap();
+    assert_eq!(Some(created_at.into()), challenge.created_at);
+    // accepting the challenge touched the row
+    assert!(updated_at > created_at);
+}
+
+async fn list(app: &TestApp, token: &str, query: &str) -> ChallengePage {
+    let (status, body) = app.get(&format!("/api/challenges?{query}"), token).await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[rocket::async_test]
+async fn challenges_can_be_filtered_by_role_status_and_opponent() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let carol = app.login("carol").await;
+    for user in ["alice", "bob", "carol"] {
+        app.fund(user, 10_000).await;
+    }
+    let (_, body) = create(&app, &alice, "bob", 100).await;
+    let to_bob: Challenge = serde_json::from_str(&body).unwrap();
+    create(&app, &alice, "carol", 100).await;
+    create(&app, &carol, "alice", 100).await;
+    app.post("/api/accept-challenge", &bob, json!({ "id": to_bob.id })).await;
+
+    assert_eq!(list(&app, &alice, "").await.challenges.len(), 3);
+    assert_eq!(list(&app, &alice, "role=created").await.challenges.len(), 2);
+    let received = list(&app, &alice, "role=received").await.challenges;
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].username, "carol");
+
+    let accepted = list(&app, &alice, "status=ACCEPTED").await.challenges;
+    assert_eq!(accepted.len(), 1);
+    assert_eq!(accepted[0].id, to_bob.id);
+    let waiting = list(&app, &alice, "status=WAITING%20FOR%20ACCEPTANCE&role=created").await.challenges;
+    assert_eq!(waiting.len(), 1);
+    assert_eq!(waiting[0].opp_username.as_deref(), Some("carol"));
+
+    // the opponent is whoever the user is playing against, in either role
+    assert_eq!(list(&app, &alice, "opponent=Carol").await.challenges.len(), 2);
+    assert_eq!(list(&app, &alice, "opponent=bob&role=received").await.challenges.len(), 0);
+    assert_eq!(list(&app, &alice, "from=2100-01-01T00:00:00Z").await.challenges.len(), 0);
+    assert_eq!(list(&app, &alice, "to=2100-01-01T00:00:00Z").await.challenges.len(), 3);
+
+    for bad in ["role=both", "status=accepted", "from=now", "limit=-1"] {
+        let (status, _) = app.get(&format!("/api/challenges?{bad}"), &alice).await;
+        assert_eq!(status, Status::BadRequest, "{bad}");
+    }
+}
+
+#[rocket::async_test]
+async fn challenges_are_paged_by_cursor() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    for _ in 0..5 {
+        create(&app, &alice, "bob", 100).await;
+    }
+
+    let first = list(&app, &alice, "limit=3").await;
+    assert_eq!(first.challenges.len(), 3);
+    let cursor = first.next_cursor.unwrap();
+    let second = list(&app, &alice, &format!("limit=3&cursor={cursor}")).await;
+    assert_eq!(second.challenges.len(), 2);
+    assert_eq!(second.next_cursor, None);
+    assert!(second.challenges.iter().all(|c| c.id < cursor));
+}
+
+#[rocket::async_test]
+async fn challenge_counts_by_role_and_status() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+    let (_, body) = create(&app, &alice, "bob", 100).await;
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    create(&app, &alice, "bob", 100).await;
+    create(&app, &alice, "bob", 100).await;
+    app.post("/api/accept-challenge", &bob, json!({ "id": challenge.id })).await;
+
+    let (status, body) = app.get("/api/challenges/counts", &bob).await;
+    assert_eq!(status, Status::Ok);
+    let counts: ChallengeCounts = serde_json::from_str(&body).unwrap();
+    assert_eq!(counts.received.get(&ChallengeStatus::WaitingForAcceptance), Some(&2));
+    assert_eq!(counts.received.get(&ChallengeStatus::Accepted), Some(&1));
+    assert!(counts.created.is_empty());
+
+    let (_, body) = app.get("/api/challenges/counts", &alice).await;
+    let counts: ChallengeCounts = serde_json::from_str(&body).unwrap();
+    assert_eq!(counts.created.get(&ChallengeStatus::WaitingForAcceptance), Some(&2));
+    assert!(counts.received.is_empty());
+}
+
+async fn create_open(app: &TestApp, token: &str, sats: i64, restrictions: serde_json::Value) -> Challenge {
+    let mut body = json!({
+        "time_limit": 300,
+        "increment": 0,
+        "color": "white",
+        "sats": sats
+    });
+    body.as_object_mut().unwrap().extend(restrictions.as_object().unwrap().clone());
+    let (status, body) = app.post("/api/challenge", token, body).await;
+    assert_eq!(status, Status::Ok, "{body}");
+    serde_json::from_str(&body).unwrap()
+}
+
+#[rocket::async_test]
+async fn open_challenge_is_listed_in_lobby_and_taken_by_first_acceptor() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let carol = app.login("carol").await;
+    for user in ["alice", "bob", "carol"] {
+        app.fund(user, 1000).await;
+    }
+    let open = create_open(&app, &alice, 100, json!({})).await;
+    assert_eq!(open.opp_username, None);
+
+    // the lobby needs no login
+    let response = app.client.get("/api/lobby").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let lobby: ChallengePage = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(lobby.challenges.len(), 1);
+    assert_eq!(lobby.challenges[0].id, open.id);
+
+    let (status, _) = app.post("/api/accept-challenge", &alice, json!({ "id": open.id })).await;
+    assert_eq!(status, Status::BadRequest);
+
+    let (status, body) = app.post("/api/accept-challenge", &bob, json!({ "id": open.id })).await;
+    assert_eq!(status, Status::Ok);
+    let accepted: Challenge = serde_json::from_str(&body).unwrap();
+    assert_eq!(accepted.opp_username.as_deref(), Some("bob"));
+    assert_eq!(app.balance_of("bob").await, 900);
+    assert_eq!(app.lichess.challenges()[0].dest_user, "alice");
+
+    let (status, _) = app.post("/api/accept-challenge", &carol, json!({ "id": open.id })).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(app.balance_of("carol").await, 1000);
+
+    let response = app.client.get("/api/lobby").dispatch().await;
+    let lobby: ChallengePage = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert!(lobby.challenges.is_empty());
+}
+
+#[rocket::async_test]
+async fn open_challenge_stays_open_when_acceptor_cannot_pay() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let carol = app.login("carol").await;
+    app.fund("alice", 1000).await;
+    app.fund("bob", 50).await;
+    app.fund("carol", 1000).await;
+    let open = create_open(&app, &alice, 100, json!({})).await;
+
+    let (status, _) = app.post("/api/accept-challenge", &bob, json!({ "id": open.id })).await;
+    assert_eq!(status, Status::PaymentRequired);
+    let (status, _) = app.post("/api/accept-challenge", &carol, json!({ "id": open.id })).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn open_challenge_restrictions_are_checked_on_lichess() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let newbie = app.login("newbie").await;
+    let strong = app.login("strong").await;
+    let unrated = app.login("unrated").await;
+    let fits = app.login("fits").await;
+    for user in ["alice", "newbie", "strong", "unrated", "fits"] {
+        app.fund(user, 1000).await;
+    }
+    // 300+0 is a blitz game
+    app.lichess.set_profile("newbie", 2, &[("blitz", 1500)]);
+    app.lichess.set_profile("strong", 400, &[("blitz", 2400)]);
+    app.lichess.set_profile("unrated", 400, &[("bullet", 1500)]);
+    app.lichess.set_profile("fits", 400, &[("blitz", 1550), ("bullet", 2400)]);
+    let open = create_open(&app, &alice, 100, json!({ "min_rating": 1400, "max_rating": 1600, "min_account_age_days": 30 })).await;
+
+    for token in [&newbie, &strong, &unrated] {
+        let (status, _) = app.post("/api/accept-challenge", token, json!({ "id": open.id })).await;
+        assert_eq!(status, Status::Forbidden);
+    }
+    assert_eq!(app.balance_of("newbie").await, 1000);
+
+    let (status, _) = app.post("/api/accept-challenge", &fits, json!({ "id": open.id })).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn invalid_restrictions_are_bad_request() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 1000).await;
+
+    let bad = [
+        json!({ "min_rating": 1600, "max_rating": 1400 }),
+        json!({ "min_account_age_days": -1 }),
+        // restrictions are for open challenges only
+        json!({ "opp_username": "bob", "min_rating": 1000 }),
+    ];
+    for restrictions in bad {
+        let mut body = json!({ "time_limit": 300, "increment": 0, "color": "white", "sats": 100 });
+        body.as_object_mut().unwrap().extend(restrictions.as_object().unwrap().clone());
+        let (status, _) = app.post("/api/challenge", &alice, body).await;
+        assert_eq!(status, Status::BadRequest, "{restrictions}");
+    }
+    assert_eq!(app.balance_of("alice").await, 1000);
+}
+
+#[rocket::async_test]
+async fn variant_and_clock_are_validated() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.login("bob").await;
+    app.fund("alice", 10_000).await;
+
+    let challenge = |variant: &str, time_limit: i32, increment: i32| json!({
+        "time_limit": time_limit,
+        "increment": increment,
+        "color": "white",
+        "sats": 100,
+        "opp_username": "bob",
+        "variant": variant
+    });
+    let allowed = [("standard", 15, 0), ("standard", 90, 1), ("chess960", 60, 0), ("atomic", 0, 1), ("threeCheck", 10800, 180)];
+    for (variant, time_limit, increment) in allowed {
+        let (status, _) = app.post("/api/challenge", &alice, challenge(variant, time_limit, increment)).await;
+        assert_eq!(status, Status::Ok, "{variant} {time_limit}+{increment}");
+    }
+    let rejected = [
+        ("fromPosition", 300, 0),
+        ("Standard", 300, 0),
+        ("standard", 0, 0),
+        ("standard", 100, 0),
+        ("standard", 10860, 0),
+        ("standard", 300, 181),
+        ("standard", 300, -1),
+        // ultrabullet is only offered for standard
+        ("crazyhouse", 15, 0),
+    ];
+    for (variant, time_limit, increment) in rejected {
+        let (status, _) = app.post("/api/challenge", &alice, challenge(variant, time_limit, increment)).await;
+        assert_eq!(status, Status::BadRequest, "{variant} {time_limit}+{increment}");
+    }
+    assert_eq!(app.balance_of("alice").await, 10_000 - 100 * allowed.len() as i64);
+}
+
+#[rocket::async_test]
+async fn variant_restrictions_use_the_variant_rating() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 1000).await;
+    app.fund("bob", 1000).await;
+    app.lichess.set_profile("bob", 400, &[("blitz", 2500), ("atomic", 1500)]);
+
+    let open = create_open(&app, &alice, 100, json!({ "variant": "atomic", "max_rating": 1600 })).await;
+    let (status, _) = app.post("/api/accept-challenge", &bob, json!({ "id": open.id })).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn odds_are_validated() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+
+    let odds = [
+        json!({ "opponent_time_limit": 100 }),
+        json!({ "opponent_time_limit": 0 }),
+        json!({ "opponent_sats": 0 }),
+        json!({ "opponent_sats": -5 }),
+    ];
+    for o in odds {
+        let mut body = json!({ "time_limit": 300, "increment": 0, "color": "white", "sats": 100, "opp_username": "bob" });
+        body.as_object_mut().unwrap().extend(o.as_object().unwrap().clone());
+        let (status, _) = app.post("/api/challenge", &alice, body).await;
+        assert_eq!(status, Status::BadRequest, "{o}");
+    }
+    assert_eq!(app.balance_of("alice").await, 10_000);
+}
+
+#[rocket::async_test]
+async fn unknown_colour_is_bad_request() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 1000).await;
+
+    let (status, _) = app.post("/api/challenge", &alice, json!({
+        "time_limit": 300,
+        "increment": 0,
+        "color": "purple",
+        "sats": 100,
+        "opp_username": "bob"
+    })).await;
+    assert_eq!(status, Status::BadRequest);
+}
@@ -0,0 +1,565 @@
+mod common;
+
+use common::mock_lichess::{Color, GameResult};
+use common::{spawn_app, TestApp, ADMIN};
+use lightningchess::models::{Challenge, ChallengeStatus, Color as PieceColor, TransactionSummary, Variant};
+use rocket::http::Status;
+use serde_json::json;
+
+const STAKE: i64 = 1000;
+const FEE: i64 = 20;
+
+// alice challenges bob as white and bob accepts, returns the lichess game id
+async fn create_and_accept(app: &TestApp, alice: &str, bob: &str) -> (i32, String) {
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+    app.fund(ADMIN, 0).await;
+
+    let (status, body) = app.post("/api/challenge", alice, json!({
+        "time_limit": 300,
+        "increment": 0,
+        "color": "white",
+        "sats": STAKE,
+        "opp_username": "bob"
+    })).await;
+    assert_eq!(status, Status::Ok);
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    assert_eq!(challenge.status, ChallengeStatus::WaitingForAcceptance);
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE);
+
+    let (status, body) = app.post("/api/accept-challenge", bob, json!({ "id": challenge.id })).await;
+    assert_eq!(status, Status::Ok);
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    assert_eq!(challenge.status, ChallengeStatus::Accepted);
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE);
+
+    (challenge.id, challenge.lichess_challenge_id.unwrap())
+}
+
+async fn settle(app: &TestApp, token: &str, challenge_id: i32) -> Challenge {
+    let (status, _) = app.get("/api/balance", token).await;
+    assert_eq!(status, Status::Ok);
+    let (status, body) = app.get(&format!("/api/challenge/{challenge_id}"), token).await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[rocket::async_test]
+async fn accepted_challenge_is_created_on_lichess() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.lichess.script_challenge_id("abcd1234");
+
+    let (_, game_id) = create_and_accept(&app, &alice, &bob).await;
+    assert_eq!(game_id, "abcd1234");
+
+    let created = app.lichess.challenges();
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0].challenger, "bob");
+    assert_eq!(created[0].dest_user, "alice");
+    assert_eq!(created[0].body["color"], "black");
+    assert_eq!(created[0].body["clock"]["limit"], "300");
+}
+
+#[rocket::async_test]
+async fn mate_pays_winner_minus_fee() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let (id, game_id) = create_and_accept(&app, &alice, &bob).await;
+
+    app.lichess.set_game_result(&game_id, GameResult::Mate(Color::White));
+    let challenge = settle(&app, &alice, id).await;
+
+    assert_eq!(challenge.status, ChallengeStatus::Completed);
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE + 2 * STAKE - FEE);
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE);
+    assert_eq!(app.balance_of(ADMIN).await, FEE);
+}
+
+#[rocket::async_test]
+async fn resign_pays_the_other_side() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let (id, game_id) = create_and_accept(&app, &alice, &bob).await;
+
+    app.lichess.set_game_result(&game_id, GameResult::Resign(Color::Black));
+    settle(&app, &bob, id).await;
+
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE);
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE + 2 * STAKE - FEE);
+    assert_eq!(app.balance_of(ADMIN).await, FEE);
+}
+
+#[rocket::async_test]
+async fn timeout_pays_winner() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let (id, game_id) = create_and_accept(&app, &alice, &bob).await;
+
+    app.lichess.set_game_result(&game_id, GameResult::Timeout(Color::White));
+    settle(&app, &bob, id).await;
+
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE + 2 * STAKE - FEE);
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE);
+}
+
+#[rocket::async_test]
+async fn draw_splits_pot_minus_fee() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let (id, game_id) = create_and_accept(&app, &alice, &bob).await;
+
+    app.lichess.set_game_result(&game_id, GameResult::Draw);
+    let challenge = settle(&app, &alice, id).await;
+
+    assert_eq!(challenge.status, ChallengeStatus::Completed);
+    assert_eq!(app.balance_of("alice").await, 10_000 - FEE / 2);
+    assert_eq!(app.balance_of("bob").await, 10_000 - FEE / 2);
+    assert_eq!(app.balance_of(ADMIN).await, FEE);
+}
+
+#[rocket::async_test]
+async fn aborted_game_is_settled_like_a_draw() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let (id, game_id) = create_and_accept(&app, &alice, &bob).await;
+
+    app.lichess.set_game_result(&game_id, GameResult::Aborted);
+    let challenge = settle(&app, &alice, id).await;
+
+    assert_eq!(challenge.status, ChallengeStatus::Completed);
+    assert_eq!(app.balance_of("alice").await, 10_000 - FEE / 2);
+    assert_eq!(app.balance_of("bob").await, 10_000 - FEE / 2);
+}
+
+#[rocket::async_test]
+async fn game_in_progress_is_not_settled() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let (id, game_id) = create_and_accept(&app, &alice, &bob).await;
+
+    app.lichess.set_game_result(&game_id, GameResult::Started);
+    let challenge = settle(&app, &alice, id).await;
+
+    assert_eq!(challenge.status, ChallengeStatus::Accepted);
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE);
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE);
+    assert_eq!(app.balance_of(ADMIN).await, 0);
+}
+
+async fn summary(app: &TestApp, token: &str) -> TransactionSummary {
+    let (status, body) = app.get("/api/transactions/summary", token).await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[rocket::async_test]
+async fn summary_reports_winnings_losses_and_fees() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let (id, game_id) = create_and_accept(&app, &alice, &bob).await;
+
+    // nothing is lost until the game is over
+    assert_eq!(summary(&app, &bob).await.lost, 0);
+
+    app.lichess.set_game_result(&game_id, GameResult::Mate(Color::White));
+    settle(&app, &alice, id).await;
+
+    let alice_summary = summary(&app, &alice).await;
+    // bob's stake less the fee, alice's own stake came back
+    assert_eq!(alice_summary.won, (STAKE - FEE) * 1000);
+    assert_eq!(alice_summary.lost, 0);
+    assert_eq!(alice_summary.fees, FEE * 1000);
+    let bob_summary = summary(&app, &bob).await;
+    assert_eq!(bob_summary.won, 0);
+    assert_eq!(bob_summary.lost, STAKE * 1000);
+    assert_eq!(bob_summary.fees, 0);
+}
+
+#[rocket::async_test]
+async fn summary_splits_fee_on_draw() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let (id, game_id) = create_and_accept(&app, &alice, &bob).await;
+
+    app.lichess.set_game_result(&game_id, GameResult::Draw);
+    settle(&app, &alice, id).await;
+
+    for token in [&alice, &bob] {
+        let s = summary(&app, token).await;
+        assert_eq!(s.won, 0);
+        assert_eq!(s.lost, 0);
+        assert_eq!(s.fees, FEE / 2 * 1000);
+    }
+}
+
+#[rocket::async_test]
+async fn variant_and_rated_are_sent_to_lichess() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+
+    let (status, body) = app.post("/api/challenge", &alice, json!({
+        "time_limit": 180,
+        "increment": 2,
+        "color": "black",
+        "sats": STAKE,
+        "opp_username": "bob",
+        "variant": "kingOfTheHill",
+        "rated": false
+    })).await;
+    assert_eq!(status, Status::Ok);
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    assert_eq!(challenge.variant, Variant::KingOfTheHill);
+    assert!(!challenge.rated);
+
+    app.post("/api/accept-challenge", &bob, json!({ "id": challenge.id })).await;
+    let created = app.lichess.challenges();
+    assert_eq!(created[0].body["variant"], "kingOfTheHill");
+    assert_eq!(created[0].body["rated"], false);
+}
+
+#[rocket::async_test]
+async fn challenges_default_to_rated_standard() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    create_and_accept(&app, &alice, &bob).await;
+
+    let created = app.lichess.challenges();
+    assert_eq!(created[0].body["variant"], "standard");
+    assert_eq!(created[0].body["rated"], true);
+}
+
+#[rocket::async_test]
+async fn time_odds_are_added_for_the_player_on_the_shorter_clock() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+
+    // bob plays on 1 minute against alice's 5
+    let (status, body) = app.post("/api/challenge", &alice, json!({
+        "time_limit": 300,
+        "opponent_time_limit": 60,
+        "increment": 0,
+        "color": "white",
+        "sats": STAKE,
+        "opp_username": "bob"
+    })).await;
+    assert_eq!(status, Status::Ok);
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    let (_, body) = app.post("/api/accept-challenge", &bob, json!({ "id": challenge.id })).await;
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    let game_id = challenge.lichess_challenge_id.unwrap();
+    assert_eq!(app.lichess.challenges()[0].body["clock"]["limit"], "60");
+
+    // alice's poll is enough, the time goes on with the token bob accepted with
+    app.lichess.set_game_result(&game_id, GameResult::Started);
+    let challenge = settle(&app, &alice, challenge.id).await;
+    assert!(challenge.time_odds_applied);
+    settle(&app, &bob, challenge.id).await;
+    let added = app.lichess.time_added();
+    assert_eq!(added.len(), 1);
+    assert_eq!(added[0].game_id, game_id);
+    assert_eq!(added[0].by, "bob");
+    assert_eq!(added[0].seconds, 240);
+}
+
+#[rocket::async_test]
+async fn game_over_before_time_odds_is_void() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+    app.fund(ADMIN, 0).await;
+
+    // alice is on the shorter clock and risks more, bob wins before any time was added
+    let (_, body) = app.post("/api/challenge", &alice, json!({
+        "time_limit": 60,
+        "opponent_time_limit": 300,
+        "color": "white",
+        "sats": STAKE,
+        "opponent_sats": 250,
+        "opp_username": "bob"
+    })).await;
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    let (_, body) = app.post("/api/accept-challenge", &bob, json!({ "id": challenge.id })).await;
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    app.lichess.set_game_result(challenge.lichess_challenge_id.as_ref().unwrap(), GameResult::Mate(Color::Black));
+
+    let settled = settle(&app, &bob, challenge.id).await;
+    assert_eq!(settled.status, ChallengeStatus::Completed);
+    assert!(!settled.time_odds_applied);
+    assert!(app.lichess.time_added().is_empty());
+    assert_eq!(app.balance_of("alice").await, 10_000);
+    assert_eq!(app.balance_of("bob").await, 10_000);
+    assert_eq!(app.balance_of(ADMIN).await, 0);
+}
+
+// alice risks 1000 against bob's 250, the fee is 1% of the 1250 pot, 12.5 sats
+async fn create_with_stake_odds(app: &TestApp, alice: &str, bob: &str) -> (i32, String) {
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+    app.fund(ADMIN, 0).await;
+    let (status, body) = app.post("/api/challenge", alice, json!({
+        "time_limit": 300,
+        "increment": 0,
+        "color": "white",
+        "sats": STAKE,
+        "opponent_sats": 250,
+        "opp_username": "bob"
+    })).await;
+    assert_eq!(status, Status::Ok);
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    let (status, body) = app.post("/api/accept-challenge", bob, json!({ "id": challenge.id })).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.balance_of("bob").await, 10_000 - 250);
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    (challenge.id, challenge.lichess_challenge_id.unwrap())
+}
+
+#[rocket::async_test]
+async fn stake_odds_winner_takes_the_pot() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let (id, game_id) = create_with_stake_odds(&app, &alice, &bob).await;
+
+    app.lichess.set_game_result(&game_id, GameResult::Mate(Color::Black));
+    settle(&app, &bob, id).await;
+
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE);
+    assert_eq!(app.balance_msat_of("bob").await, (10_000 - 250 + 1250) * 1000 - 12_500);
+    assert_eq!(app.balance_msat_of(ADMIN).await, 12_500);
+}
+
+#[rocket::async_test]
+async fn stake_odds_draw_splits_fee_by_stake() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let (id, game_id) = create_with_stake_odds(&app, &alice, &bob).await;
+
+    app.lichess.set_game_result(&game_id, GameResult::Draw);
+    settle(&app, &alice, id).await;
+
+    // no longer rounded to whole sats, so each side pays exactly its share
+    assert_eq!(app.balance_of("alice").await, 10_000 - 10);
+    assert_eq!(app.balance_msat_of("bob").await, 10_000 * 1000 - 2_500);
+    assert_eq!(app.balance_msat_of(ADMIN).await, 12_500);
+    assert_eq!(summary(&app, &alice).await.fees, 10_000);
+    assert_eq!(summary(&app, &bob).await.fees, 2_500);
+}
+
+async fn create_random(app: &TestApp, alice: &str, bob: &str, color: Option<&str>) -> Challenge {
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+    let mut body = json!({ "time_limit": 300, "increment": 0, "sats": STAKE, "opp_username": "bob" });
+    if let Some(color) = color {
+        body["color"] = json!(color);
+    }
+    let (status, body) = app.post("/api/challenge", alice, body).await;
+    assert_eq!(status, Status::Ok);
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+    assert_eq!(challenge.color, PieceColor::Random);
+
+    let (status, body) = app.post("/api/accept-challenge", bob, json!({ "id": challenge.id })).await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[rocket::async_test]
+async fn random_colour_is_resolved_on_accept_and_used_at_settlement() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let challenge = create_random(&app, &alice, &bob, Some("random")).await;
+
+    assert_ne!(challenge.color, PieceColor::Random);
+    let sent = &app.lichess.challenges()[0];
+    assert_eq!(sent.body["color"], challenge.color.opposite().as_str());
+
+    // alice wins with whichever colour she got
+    let alice_wins = if challenge.color == PieceColor::White { Color::White } else { Color::Black };
+    app.lichess.set_game_result(&sent.id, GameResult::Mate(alice_wins));
+    let settled = settle(&app, &bob, challenge.id).await;
+    assert_eq!(settled.color, challenge.color);
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE + 2 * STAKE - FEE);
+}
+
+#[rocket::async_test]
+async fn missing_colour_is_random() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let challenge = create_random(&app, &alice, &bob, None).await;
+    assert_ne!(challenge.color, PieceColor::Random);
+}
+
+#[rocket::async_test]
+async fn unresolved_colour_is_settled_from_lichess_players() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let challenge = create_random(&app, &alice, &bob, Some("random")).await;
+    let alice_color = challenge.color;
+    // challenges accepted before colours were resolved
+    sqlx::query("UPDATE challenge SET color='random' WHERE id=$1")
+        .bind(challenge.id)
+        .execute(&app.pool).await.unwrap();
+
+    let bob_wins = if alice_color == PieceColor::White { Color::Black } else { Color::White };
+    app.lichess.set_game_result(challenge.lichess_challenge_id.as_ref().unwrap(), GameResult::Resign(bob_wins));
+    settle(&app, &alice, challenge.id).await;
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE + 2 * STAKE - FEE);
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE);
+}
+
+#[rocket::async_test]
+async fn rematch_swaps_colours_and_escrows_stake() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let (id, game_id) = create_and_accept(&app, &alice, &bob).await;
+
+    let (status, _) = app.post_empty(&format!("/api/challenge/{id}/rematch"), &alice).await;
+    assert_eq!(status, Status::BadRequest);
+
+    app.lichess.set_game_result(&game_id, GameResult::Mate(Color::White));
+    settle(&app, &alice, id).await;
+    let before = app.balance_of("alice").await;
+
+    let (status, body) = app.post_empty(&format!("/api/challenge/{id}/rematch"), &alice).await;
+    assert_eq!(status, Status::Ok);
+    let rematch: Challenge = serde_json::from_str(&body).unwrap();
+    assert_eq!(rematch.rematch_of, Some(id));
+    assert_eq!(rematch.status, ChallengeStatus::WaitingForAcceptance);
+    assert_eq!(rematch.color, PieceColor::Black);
+    assert_eq!(rematch.opp_username.as_deref(), Some("bob"));
+    assert_eq!(rematch.time_limit, Some(300));
+    assert_eq!(rematch.sats, STAKE);
+    assert_eq!(app.balance_of("alice").await, before - STAKE);
+
+    // only one rematch per game
+    let (status, _) = app.post_empty(&format!("/api/challenge/{id}/rematch"), &bob).await;
+    assert_eq!(status, Status::Conflict);
+
+    let (status, _) = app.post("/api/accept-challenge", &bob, json!({ "id": rematch.id })).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.lichess.challenges()[1].body["color"], "white");
+}
+
+#[rocket::async_test]
+async fn rematch_of_cannot_be_set_by_client() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let mallory = app.login("mallory").await;
+    app.fund("mallory", 10_000).await;
+    let (id, game_id) = create_and_accept(&app, &alice, &bob).await;
+    app.lichess.set_game_result(&game_id, GameResult::Mate(Color::White));
+    settle(&app, &alice, id).await;
+
+    // claiming someone else's rematch slot is refused and takes nothing
+    let (status, _) = app.post("/api/challenge", &mallory, json!({
+        "time_limit": 300,
+        "color": "white",
+        "sats": 100,
+        "opp_username": "alice",
+        "rematch_of": id
+    })).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(app.balance_of("mallory").await, 10_000);
+
+    let (status, _) = app.post_empty(&format!("/api/challenge/{id}/rematch"), &alice).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn rematch_by_opponent_keeps_each_players_odds() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let (id, game_id) = create_with_stake_odds(&app, &alice, &bob).await;
+    app.lichess.set_game_result(&game_id, GameResult::Draw);
+    settle(&app, &alice, id).await;
+
+    let (status, body) = app.post_empty(&format!("/api/challenge/{id}/rematch"), &bob).await;
+    assert_eq!(status, Status::Ok);
+    let rematch: Challenge = serde_json::from_str(&body).unwrap();
+    assert_eq!(rematch.username, "bob");
+    assert_eq!(rematch.opp_username.as_deref(), Some("alice"));
+    // bob was black
+    assert_eq!(rematch.color, PieceColor::White);
+    assert_eq!(rematch.sats, 250);
+    assert_eq!(rematch.opponent_sats, Some(STAKE));
+}
+
+#[rocket::async_test]
+async fn rematch_needs_a_player_with_funds() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let carol = app.login("carol").await;
+    let (id, game_id) = create_and_accept(&app, &alice, &bob).await;
+    app.lichess.set_game_result(&game_id, GameResult::Mate(Color::White));
+    settle(&app, &alice, id).await;
+
+    let (status, _) = app.post_empty(&format!("/api/challenge/{id}/rematch"), &carol).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    app.fund("bob", 10).await;
+    let (status, _) = app.post_empty(&format!("/api/challenge/{id}/rematch"), &bob).await;
+    assert_eq!(status, Status::PaymentRequired);
+    assert_eq!(app.balance_of("bob").await, 10);
+
+    let (status, _) = app.post_empty("/api/challenge/9999/rematch", &bob).await;
+    assert_eq!(status, Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn rematch_of_open_challenge_drops_its_requirements() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+    app.lichess.set_profile("bob", 400, &[("blitz", 1500)]);
+    let (status, body) = app.post("/api/challenge", &alice, json!({
+        "time_limit": 300,
+        "increment": 0,
+        "color": "white",
+        "sats": STAKE,
+        "min_rating": 1400,
+        "max_rating": 1600,
+        "min_account_age_days": 30
+    })).await;
+    assert_eq!(status, Status::Ok);
+    let open: Challenge = serde_json::from_str(&body).unwrap();
+    let (status, body) = app.post("/api/accept-challenge", &bob, json!({ "id": open.id })).await;
+    assert_eq!(status, Status::Ok);
+    let accepted: Challenge = serde_json::from_str(&body).unwrap();
+    app.lichess.set_game_result(accepted.lichess_challenge_id.as_ref().unwrap(), GameResult::Mate(Color::White));
+    settle(&app, &alice, open.id).await;
+
+    let (status, body) = app.post_empty(&format!("/api/challenge/{}/rematch", open.id), &alice).await;
+    assert_eq!(status, Status::Ok, "{body}");
+    let rematch: Challenge = serde_json::from_str(&body).unwrap();
+    assert_eq!(rematch.opp_username.as_deref(), Some("bob"));
+    assert_eq!(rematch.color, PieceColor::Black);
+    assert_eq!((rematch.min_rating, rematch.max_rating, rematch.min_account_age_days), (None, None, None));
+}
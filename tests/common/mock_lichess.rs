@@ -0,0 +1,350 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock};
+
+// Stand-in for lichess.org. Serves the endpoints the backend calls
+// (api/account, api/token, api/user/<username>, api/challenge/<username>,
+// api/round/<id>/add-time/<seconds>, game/export/<id>,
+// api/tournament/<id>, api/swiss/<id> and their /results)
+// from scripted accounts, challenge ids and game results.
+
+#[derive(Clone, Copy, Debug)]
+pub enum Color {
+    White,
+    Black,
+}
+
+impl Color {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Color::White => "white",
+            Color::Black => "black",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum GameResult {
+    Created,
+    Started,
+    Mate(Color),
+    Resign(Color),
+    Draw,
+    Aborted,
+    Timeout(Color),
+}
+
+impl GameResult {
+    fn status(&self) -> &'static str {
+        match self {
+            GameResult::Created => "created",
+            GameResult::Started => "started",
+            GameResult::Mate(_) => "mate",
+            GameResult::Resign(_) => "resign",
+            GameResult::Draw => "draw",
+            GameResult::Aborted => "aborted",
+            GameResult::Timeout(_) => "outoftime",
+        }
+    }
+
+    fn winner(&self) -> Option<&'static str> {
+        match self {
+            GameResult::Mate(c) | GameResult::Resign(c) | GameResult::Timeout(c) => Some(c.as_str()),
+            _ => None,
+        }
+    }
+}
+
+// a challenge as it was posted to lichess by the backend
+#[derive(Clone, Debug)]
+pub struct CreatedChallenge {
+    pub id: String,
+    pub challenger: String,
+    pub dest_user: String,
+    pub body: Value,
+}
+
+// public profile served by /api/user/<username>
+#[derive(Clone, Debug)]
+struct Profile {
+    created_at: i64, // ms since epoch
+    ratings: Vec<(String, i32)>, // perf -> rating
+}
+
+// a call to add-time, by the player who gave the time
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddedTime {
+    pub game_id: String,
+    pub by: String,
+    pub seconds: i32,
+}
+
+// an arena or swiss event on lichess, ranking is filled in when it finishes
+#[derive(Clone, Debug)]
+struct Tournament {
+    swiss: bool,
+    name: String,
+    starts_at: chrono::DateTime<chrono::Utc>,
+    ranking: Option<Vec<String>>,
+}
+
+#[derive(Default)]
+struct MockState {
+    profiles: HashMap<String, Profile>, // lichess id -> profile
+    accounts: HashMap<String, String>, // access token -> username
+    oauth_codes: HashMap<String, String>, // code -> access token
+    challenge_ids: VecDeque<String>,
+    challenges: Vec<CreatedChallenge>,
+    games: HashMap<String, GameResult>,
+    time_added: Vec<AddedTime>,
+    tournaments: HashMap<String, Tournament>,
+    next_id: u32,
+    rate_limited: bool, // challenges and game exports answer 429
+}
+
+pub struct MockLichess {
+    pub url: String,
+    state: Arc<Mutex<MockState>>,
+    gate: Arc<RwLock<()>>, // challenge creation waits for it
+}
+
+impl MockLichess {
+    pub async fn start() -> MockLichess {
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let gate = Arc::new(RwLock::new(()));
+        let service_state = state.clone();
+        let service_gate = gate.clone();
+        let make_svc = make_service_fn(move |_| {
+            let state = service_state.clone();
+            let gate = service_gate.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), gate.clone(), req))) }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        MockLichess { url, state, gate }
+    }
+
+    // challenge creation hangs until the guard is dropped, like a slow lichess
+    pub async fn hold_challenges(&self) -> OwnedRwLockWriteGuard<()> {
+        self.gate.clone().write_owned().await
+    }
+
+    // returns an access token that /api/account resolves to username
+    pub fn add_account(&self, username: &str) -> String {
+        let token = format!("token-{username}");
+        self.state.lock().unwrap().accounts.insert(token.clone(), username.to_string());
+        token
+    }
+
+    // account_age_days before now, ratings like ("blitz", 1500)
+    pub fn set_profile(&self, username: &str, account_age_days: i64, ratings: &[(&str, i32)]) {
+        let created_at = chrono::Utc::now().timestamp_millis() - account_age_days * 24 * 60 * 60 * 1000;
+        let ratings = ratings.iter().map(|(perf, rating)| (perf.to_string(), *rating)).collect();
+        self.state.lock().unwrap().profiles.insert(username.to_lowercase(), Profile { created_at, ratings });
+    }
+
+    pub fn add_oauth_code(&self, code: &str, access_token: &str) {
+        self.state.lock().unwrap().oauth_codes.insert(code.to_string(), access_token.to_string());
+    }
+
+    // id handed out by the next POST /api/challenge, otherwise ids are generated
+    pub fn script_challenge_id(&self, id: &str) {
+        self.state.lock().unwrap().challenge_ids.push_back(id.to_string());
+    }
+
+    pub fn set_game_result(&self, id: &str, result: GameResult) {
+        self.state.lock().unwrap().games.insert(id.to_string(), result);
+    }
+
+    // a declined challenge never becomes a game, exporting it is a 404 like on lichess
+    pub fn decline_challenge(&self, id: &str) {
+        self.state.lock().unwrap().games.remove(id);
+    }
+
+    // like lichess telling a busy client to back off, until set back to false
+    pub fn set_rate_limited(&self, rate_limited: bool) {
+        self.state.lock().unwrap().rate_limited = rate_limited;
+    }
+
+    pub fn time_added(&self) -> Vec<AddedTime> {
+        self.state.lock().unwrap().time_added.clone()
+    }
+
+    // an arena, or a swiss event if swiss, starting in an hour with a 3+2 clock
+    pub fn add_tournament(&self, id: &str, swiss: bool, name: &str) {
+        let starts_at = chrono::Utc::now() + chrono::Duration::hours(1);
+        let tournament = Tournament { swiss, name: name.to_string(), starts_at, ranking: None };
+        self.state.lock().unwrap().tournaments.insert(id.to_string(), tournament);
+    }
+
+    // usernames best first
+    pub fn finish_tournament(&self, id: &str, ranking: &[&str]) {
+        let ranking = ranking.iter().map(|u| u.to_string()).collect();
+        self.state.lock().unwrap().tournaments.get_mut(id).unwrap().ranking = Some(ranking);
+    }
+
+    pub fn challenges(&self) -> Vec<CreatedChallenge> {
+        self.state.lock().unwrap().challenges.clone()
+    }
+}
+
+async fn handle(state: Arc<Mutex<MockState>>, gate: Arc<RwLock<()>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    if method == Method::POST && path.starts_with("/api/challenge/") {
+        drop(gate.read().await);
+    }
+    let token = req.headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(|t| t.to_string());
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+
+    let mut state = state.lock().unwrap();
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    let response = match (method, segments.as_slice()) {
+        (Method::GET, ["api", "account"]) => {
+            match token.and_then(|t| state.accounts.get(&t).cloned()) {
+                Some(username) => reply(StatusCode::OK, json!({ "id": username.to_lowercase(), "username": username })),
+                None => reply(StatusCode::UNAUTHORIZED, json!({ "error": "No such token" })),
+            }
+        },
+        (Method::POST, ["api", "token"]) => {
+            let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+            let code = request["code"].as_str().unwrap_or_default();
+            match state.oauth_codes.get(code) {
+                Some(access_token) => reply(StatusCode::OK, json!({ "token_type": "Bearer", "access_token": access_token })),
+                None => reply(StatusCode::BAD_REQUEST, json!({ "error": "invalid_grant" })),
+            }
+        },
+        (Method::GET, ["api", "user", username]) => {
+            let known = state.accounts.values().any(|u| u.eq_ignore_ascii_case(username));
+            // accounts without a profile are old and unrated
+            match state.profiles.get(&username.to_lowercase()).cloned() {
+                Some(profile) => {
+                    let perfs: serde_json::Map<String, Value> = profile.ratings.iter()
+                        .map(|(perf, rating)| (perf.clone(), json!({ "games": 10, "rating": rating, "rd": 60, "prog": 0 })))
+                        .collect();
+                    reply(StatusCode::OK, json!({ "id": username.to_lowercase(), "username": username, "createdAt": profile.created_at, "perfs": perfs }))
+                },
+                None if known => reply(StatusCode::OK, json!({ "id": username.to_lowercase(), "username": username, "createdAt": 0, "perfs": { "storm": { "runs": 1, "score": 5 } } })),
+                None => reply(StatusCode::NOT_FOUND, json!({ "error": "Not found" })),
+            }
+        },
+        (Method::POST, ["api", "challenge", _]) | (Method::GET, ["game", "export", _]) if state.rate_limited => {
+            reply(StatusCode::TOO_MANY_REQUESTS, json!({ "error": "Too many requests" }))
+        },
+        (Method::POST, ["api", "challenge", dest_user]) => {
+            match token.and_then(|t| state.accounts.get(&t).cloned()) {
+                Some(challenger) => {
+                    let id = match state.challenge_ids.pop_front() {
+                        Some(id) => id,
+                        None => {
+                            state.next_id += 1;
+                            format!("mock{:04}", state.next_id)
+                        }
+                    };
+                    state.games.insert(id.clone(), GameResult::Created);
+                    state.challenges.push(CreatedChallenge {
+                        id: id.clone(),
+                        challenger,
+                        dest_user: dest_user.to_string(),
+                        body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+                    });
+                    reply(StatusCode::OK, json!({ "challenge": { "id": id, "url": format!("https://lichess.org/{id}") } }))
+                },
+                None => reply(StatusCode::UNAUTHORIZED, json!({ "error": "No such token" })),
+            }
+        },
+        (Method::POST, ["api", "round", id, "add-time", seconds]) => {
+            let player = token.and_then(|t| state.accounts.get(&t).cloned());
+            let playing = matches!(state.games.get(*id), Some(GameResult::Created) | Some(GameResult::Started));
+            match (player, seconds.parse::<i32>()) {
+                (Some(by), Ok(seconds)) if playing => {
+                    state.time_added.push(AddedTime { game_id: id.to_string(), by, seconds });
+                    reply(StatusCode::OK, json!({ "ok": true }))
+                },
+                (None, _) => reply(StatusCode::UNAUTHORIZED, json!({ "error": "No such token" })),
+                _ => reply(StatusCode::BAD_REQUEST, json!({ "error": "This game cannot be changed" })),
+            }
+        },
+        (Method::GET, ["game", "export", id]) => {
+            match state.games.get(*id) {
+                Some(result) => {
+                    let mut game = json!({
+                        "id": id,
+                        "rated": true,
+                        "variant": "standard",
+                        "speed": "blitz",
+                        "perf": "blitz",
+                        "status": result.status(),
+                    });
+                    if let Some(winner) = result.winner() {
+                        game["winner"] = json!(winner);
+                    }
+                    // the challenger plays the colour they asked for, random gets white
+                    if let Some(created) = state.challenges.iter().find(|c| c.id == *id) {
+                        let challenger = json!({ "user": { "id": created.challenger.to_lowercase(), "name": created.challenger } });
+                        let dest = json!({ "user": { "id": created.dest_user.to_lowercase(), "name": created.dest_user } });
+                        game["players"] = if created.body["color"] == "black" {
+                            json!({ "white": dest, "black": challenger })
+                        } else {
+                            json!({ "white": challenger, "black": dest })
+                        };
+                    }
+                    reply(StatusCode::OK, game)
+                },
+                None => reply(StatusCode::NOT_FOUND, json!({ "error": "Not found" })),
+            }
+        },
+        (Method::GET, ["api", kind @ ("tournament" | "swiss"), id]) => {
+            match state.tournaments.get(*id).filter(|t| t.swiss == (*kind == "swiss")) {
+                Some(t) => {
+                    let starts_at = t.starts_at.to_rfc3339();
+                    let clock = json!({ "limit": 180, "increment": 2 });
+                    let finished = t.ranking.is_some();
+                    reply(StatusCode::OK, if t.swiss {
+                        json!({ "id": id, "name": t.name, "startsAt": starts_at, "clock": clock, "nbRounds": 7,
+                            "status": if finished { "finished" } else { "created" } })
+                    } else {
+                        json!({ "id": id, "fullName": format!("{} Arena", t.name), "startsAt": starts_at, "clock": clock, "minutes": 60,
+                            "isFinished": finished })
+                    })
+                },
+                None => reply(StatusCode::NOT_FOUND, json!({ "error": "Not found" })),
+            }
+        },
+        (Method::GET, ["api", "tournament" | "swiss", id, "results"]) => {
+            match state.tournaments.get(*id) {
+                Some(t) => {
+                    let lines: Vec<String> = t.ranking.clone().unwrap_or_default().iter().enumerate()
+                        .map(|(i, username)| json!({ "rank": i + 1, "score": 10 - i as i32, "rating": 1500, "username": username }).to_string())
+                        .collect();
+                    let ndjson = lines.iter().map(|l| format!("{l}\n")).collect::<String>();
+                    Response::builder()
+                        .status(StatusCode::OK)
+                        .header("Content-Type", "application/x-ndjson")
+                        .body(Body::from(ndjson))
+                        .unwrap()
+                },
+                None => reply(StatusCode::NOT_FOUND, json!({ "error": "Not found" })),
+            }
+        },
+        _ => reply(StatusCode::NOT_FOUND, json!({ "error": "Not found" })),
+    };
+    Ok(response)
+}
+
+fn reply(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
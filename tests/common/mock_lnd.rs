@@ -0,0 +1,227 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock};
+use super::bolt11::{payment_hash, InvoiceBuilder};
+
+// Stand-in for the LND REST api. Invoices created through /v1/invoices
+// stay OPEN until a test pays them; outgoing payments go to real invoices
+// the test added as payable and succeed unless scripted to fail, or stay
+// in flight until the test finishes them.
+
+#[derive(Clone, Debug)]
+pub struct Invoice {
+    pub payment_request: String,
+    pub payment_addr: String, // base64 encoded
+    pub memo: String,
+    pub description_hash: String, // base64 encoded
+    pub value_msat: i64,
+    pub state: String,
+    pub amt_paid_msat: i64,
+}
+
+#[derive(Default)]
+struct MockState {
+    invoices: Vec<Invoice>,
+    payables: HashMap<String, bool>, // payment request -> whether paying it fails
+    payments: Vec<(String, Option<i64>)>, // payment requests sent through the router and any amt_msat
+    in_flight: HashSet<String>, // payment requests whose payment hasn't finished
+}
+
+pub struct MockLnd {
+    pub url: String,
+    state: Arc<Mutex<MockState>>,
+    gate: Arc<RwLock<()>>, // outgoing payments wait for it
+}
+
+impl MockLnd {
+    pub async fn start() -> MockLnd {
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let gate = Arc::new(RwLock::new(()));
+        let service_state = state.clone();
+        let service_gate = gate.clone();
+        let make_svc = make_service_fn(move |_| {
+            let state = service_state.clone();
+            let gate = service_gate.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), gate.clone(), req))) }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        MockLnd { url, state, gate }
+    }
+
+    // outgoing payments hang until the guard is dropped, like ones still finding a route
+    pub async fn hold_payments(&self) -> OwnedRwLockWriteGuard<()> {
+        self.gate.clone().write_owned().await
+    }
+
+    pub fn invoices(&self) -> Vec<Invoice> {
+        self.state.lock().unwrap().invoices.clone()
+    }
+
+    // marks an invoice created by the app as paid in full
+    pub fn pay_invoice(&self, payment_request: &str) {
+        let mut state = self.state.lock().unwrap();
+        let invoice = state.invoices.iter_mut()
+            .find(|i| i.payment_request == payment_request)
+            .expect("unknown invoice");
+        invoice.state = "SETTLED".to_string();
+        invoice.amt_paid_msat = invoice.value_msat;
+    }
+
+    pub fn cancel_invoice(&self, payment_request: &str) {
+        let mut state = self.state.lock().unwrap();
+        let invoice = state.invoices.iter_mut()
+            .find(|i| i.payment_request == payment_request)
+            .expect("unknown invoice");
+        invoice.state = "CANCELED".to_string();
+    }
+
+    // an external invoice for sats the app can pay, returned as its payment request
+    pub fn add_payable(&self, sats: i64, fails: bool) -> String {
+        let payment_request = InvoiceBuilder::new(Some(sats * 1000)).build();
+        self.insert_payable(&payment_request, fails);
+        payment_request
+    }
+
+    // like an invoice from an lnurl service, committing to a description by its hash
+    pub fn add_described_payable(&self, msat: i64, description_hash: &str) -> String {
+        let payment_request = InvoiceBuilder::new(Some(msat)).description_hash(description_hash).build();
+        self.insert_payable(&payment_request, false);
+        payment_request
+    }
+
+    // an invoice the test built itself
+    pub fn insert_payable(&self, payment_request: &str, fails: bool) {
+        self.state.lock().unwrap().payables.insert(payment_request.to_string(), fails);
+    }
+
+    // a payable whose payment is still in flight when the send stream ends, as when lnd loses the client
+    pub fn add_in_flight_payable(&self, sats: i64) -> String {
+        let payment_request = self.add_payable(sats, false);
+        self.state.lock().unwrap().in_flight.insert(payment_request.clone());
+        payment_request
+    }
+
+    pub fn finish_payment(&self, payment_request: &str, fails: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight.remove(payment_request);
+        state.payables.insert(payment_request.to_string(), fails);
+    }
+
+    pub fn payments(&self) -> Vec<String> {
+        self.state.lock().unwrap().payments.iter().map(|(pr, _)| pr.clone()).collect()
+    }
+
+    // the amt_msat sent with each payment, only set for zero-amount invoices
+    pub fn payment_amounts(&self) -> Vec<Option<i64>> {
+        self.state.lock().unwrap().payments.iter().map(|(_, amt_msat)| *amt_msat).collect()
+    }
+}
+
+async fn handle(state: Arc<Mutex<MockState>>, gate: Arc<RwLock<()>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    if path == "/v2/router/send" {
+        drop(gate.read().await);
+    }
+    let query = req.uri().query().unwrap_or_default().to_string();
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+    let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let mut state = state.lock().unwrap();
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    let response = match (method, segments.as_slice()) {
+        (Method::POST, ["v1", "invoices"]) => {
+            let index = state.invoices.len() + 1;
+            let invoice = Invoice {
+                payment_request: format!("lnbcrt{index}mock"),
+                payment_addr: base64::encode(format!("payment-addr-{index:020}")),
+                memo: request["memo"].as_str().unwrap_or_default().to_string(),
+                description_hash: request["description_hash"].as_str().unwrap_or_default().to_string(),
+                value_msat: request["value_msat"].as_str().and_then(|v| v.parse().ok()).unwrap_or(0),
+                state: "OPEN".to_string(),
+                amt_paid_msat: 0,
+            };
+            let response = json!({
+                "payment_request": invoice.payment_request,
+                "add_index": index.to_string(),
+                "payment_addr": invoice.payment_addr,
+            });
+            state.invoices.push(invoice);
+            reply(StatusCode::OK, response)
+        },
+        (Method::GET, ["v2", "invoices", "lookup"]) => {
+            // payment_addr arrives url safe base64 encoded
+            let payment_addr = query.strip_prefix("payment_addr=")
+                .and_then(|addr| base64::decode_config(addr, base64::URL_SAFE).ok())
+                .map(base64::encode)
+                .unwrap_or_default();
+            match state.invoices.iter().find(|i| i.payment_addr == payment_addr) {
+                Some(invoice) => reply(StatusCode::OK, json!({
+                    "memo": invoice.memo,
+                    "value": (invoice.value_msat / 1000).to_string(),
+                    "value_msat": invoice.value_msat.to_string(),
+                    "settled": invoice.state == "SETTLED",
+                    "creation_date": "0",
+                    "settle_date": "0",
+                    "payment_request": invoice.payment_request,
+                    "expiry": "1800",
+                    "amt_paid_sat": (invoice.amt_paid_msat / 1000).to_string(),
+                    "amt_paid_msat": invoice.amt_paid_msat.to_string(),
+                    "state": invoice.state,
+                })),
+                None => reply(StatusCode::NOT_FOUND, json!({ "code": 5, "message": "there are no existing invoices" })),
+            }
+        },
+        (Method::POST, ["v2", "router", "send"]) => {
+            let payment_request = request["payment_request"].as_str().unwrap_or_default().to_string();
+            let amt_msat = request["amt_msat"].as_str().and_then(|a| a.parse().ok());
+            let mut updates = vec![json!({ "result": { "status": "IN_FLIGHT" } })];
+            if !state.in_flight.contains(&payment_request) {
+                updates.push(json!({ "result": { "status": payment_status(&state, &payment_request) } }));
+            }
+            state.payments.push((payment_request, amt_msat));
+            let stream = updates.iter().map(|u| u.to_string() + "\n").collect::<String>();
+            Response::builder()
+                .status(StatusCode::OK)
+                .body(Body::from(stream))
+                .unwrap()
+        },
+        (Method::GET, ["v2", "router", "track", hash]) => {
+            // the payment hash arrives url safe base64 encoded
+            let hash = base64::decode_config(hash, base64::URL_SAFE).map(hex::encode).unwrap_or_default();
+            let payment_request = state.payments.iter()
+                .map(|(pr, _)| pr.clone())
+                .find(|pr| payment_hash(pr).as_deref() == Some(hash.as_str()));
+            match payment_request {
+                Some(pr) => reply(StatusCode::OK, json!({ "result": { "status": payment_status(&state, &pr) } })),
+                None => reply(StatusCode::NOT_FOUND, json!({ "code": 5, "message": "payment isn't initiated" })),
+            }
+        },
+        _ => reply(StatusCode::NOT_FOUND, json!({ "code": 5, "message": "Not Found" })),
+    };
+    Ok(response)
+}
+
+fn payment_status(state: &MockState, payment_request: &str) -> &'static str {
+    if state.in_flight.contains(payment_request) {
+        return "IN_FLIGHT";
+    }
+    match state.payables.get(payment_request) {
+        Some(false) => "SUCCEEDED",
+        _ => "FAILED",
+    }
+}
+
+fn reply(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
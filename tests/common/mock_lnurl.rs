@@ -0,0 +1,128 @@
+use bech32::ToBase32;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+// Stand-in for a wallet's LNURL-pay service. Every name is a lightning address
+// (name@host) and an lnurl (/lnurlp/<name>); the callback answers with the
+// payment request a test set for the name, whatever amount was asked for.
+
+pub const MIN_SENDABLE: i64 = 1000;
+pub const MAX_SENDABLE: i64 = 1_000_000_000;
+
+#[derive(Default)]
+struct MockState {
+    callbacks: Vec<String>, // name and amount of each invoice asked for
+    invoices: HashMap<String, String>, // name -> payment request
+    redirect: Option<String>, // where /redirect sends a client
+}
+
+pub struct MockLnurl {
+    pub url: String,
+    pub host: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockLnurl {
+    pub async fn start() -> MockLnurl {
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let make_svc_state = state.clone();
+        // bound first, the payRequests it serves carry its own address
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let url = format!("http://{host}");
+        let service_url = url.clone();
+        let service_host = host.clone();
+        let make_svc = make_service_fn(move |_| {
+            let state = make_svc_state.clone();
+            let url = service_url.clone();
+            let host = service_host.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), url.clone(), host.clone(), req))) }
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_svc));
+        MockLnurl { url, host, state }
+    }
+
+    pub fn address(&self, name: &str) -> String {
+        format!("{name}@{}", self.host)
+    }
+
+    // bech32 lnurl pointing at /lnurlp/<name>
+    pub fn lnurl(&self, name: &str) -> String {
+        bech32::encode("lnurl", format!("{}/lnurlp/{name}", self.url).as_bytes().to_base32(), bech32::Variant::Bech32).unwrap()
+    }
+
+    pub fn metadata(&self, name: &str) -> String {
+        metadata(&self.host, name)
+    }
+
+    pub fn set_invoice(&self, name: &str, payment_request: &str) {
+        self.state.lock().unwrap().invoices.insert(name.to_string(), payment_request.to_string());
+    }
+
+    // a bech32 lnurl that answers with a redirect to location
+    pub fn redirect_to(&self, location: &str) -> String {
+        self.state.lock().unwrap().redirect = Some(location.to_string());
+        bech32::encode("lnurl", format!("{}/redirect", self.url).as_bytes().to_base32(), bech32::Variant::Bech32).unwrap()
+    }
+
+    pub fn callbacks(&self) -> Vec<String> {
+        self.state.lock().unwrap().callbacks.clone()
+    }
+}
+
+fn metadata(host: &str, name: &str) -> String {
+    json!([["text/plain", format!("pay {name}")], ["text/identifier", format!("{name}@{host}")]]).to_string()
+}
+
+async fn handle(state: Arc<Mutex<MockState>>, url: String, host: String, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let query = req.uri().query().unwrap_or_default().to_string();
+
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    let response = match (method, segments.as_slice()) {
+        (Method::GET, [".well-known", "lnurlp", name]) | (Method::GET, ["lnurlp", name]) => reply(json!({
+            "tag": "payRequest",
+            "callback": format!("{url}/lnurlp/{name}/callback"),
+            "minSendable": MIN_SENDABLE,
+            "maxSendable": MAX_SENDABLE,
+            "metadata": metadata(&host, name),
+        })),
+        (Method::GET, ["lnurlp", name, "callback"]) => {
+            match query.strip_prefix("amount=").and_then(|a| a.parse::<i64>().ok()) {
+                Some(amount) => {
+                    let mut state = state.lock().unwrap();
+                    state.callbacks.push(format!("{name} {amount}"));
+                    match state.invoices.get(*name) {
+                        Some(pr) => reply(json!({ "pr": pr, "routes": [] })),
+                        None => reply(json!({ "status": "ERROR", "reason": "no invoice" })),
+                    }
+                },
+                None => reply(json!({ "status": "ERROR", "reason": "amount missing" })),
+            }
+        },
+        (Method::GET, ["redirect"]) => {
+            let location = state.lock().unwrap().redirect.clone().unwrap_or_default();
+            Response::builder()
+                .status(StatusCode::FOUND)
+                .header("Location", location)
+                .body(Body::empty())
+                .unwrap()
+        },
+        _ => reply(json!({ "status": "ERROR", "reason": "not found" })),
+    };
+    Ok(response)
+}
+
+fn reply(body: Value) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
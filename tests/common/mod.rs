@@ -0,0 +1,159 @@
+#![allow(dead_code)]
+
+pub mod bolt11;
+pub mod mock_lichess;
+pub mod mock_lnd;
+pub mod mock_lnurl;
+
+use mock_lichess::MockLichess;
+use mock_lnd::MockLnd;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Cookie, Status};
+use rocket::local::asynchronous::Client;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Connection, Executor, PgConnection, Pool, Postgres};
+use std::env;
+use std::str::FromStr;
+
+pub const ADMIN: &str = "admin";
+
+// A Postgres schema of its own, dropped again when this goes out of scope.
+// Tests need TEST_DATABASE_URL and are skipped without it.
+pub struct TestDb {
+    pub pool: Pool<Postgres>,
+    database_url: String,
+    schema: String,
+}
+
+pub async fn test_db() -> Option<TestDb> {
+    let database_url = match env::var("TEST_DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            println!("TEST_DATABASE_URL not set, skipping");
+            return None
+        }
+    };
+
+    let suffix: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(12)
+        .map(|c| (c as char).to_ascii_lowercase())
+        .collect();
+    let schema = format!("test_{suffix}");
+
+    let mut conn = PgConnection::connect(&database_url).await.unwrap();
+    conn.execute(format!("CREATE SCHEMA {schema}").as_str()).await.unwrap();
+    conn.close().await.unwrap();
+
+    let options = PgConnectOptions::from_str(&database_url).unwrap()
+        .options([("search_path", schema.as_str())]);
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect_with(options)
+        .await.unwrap();
+
+    Some(TestDb { pool, database_url, schema })
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let database_url = self.database_url.clone();
+        let schema = self.schema.clone();
+        // the test runtime is shutting down, so drop the schema from a fresh one
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(async {
+                if let Ok(mut conn) = PgConnection::connect(&database_url).await {
+                    let _ = conn.execute(format!("DROP SCHEMA {schema} CASCADE").as_str()).await;
+                }
+            });
+        }).join().unwrap();
+    }
+}
+
+// A running app on a migrated TestDb, talking to mock lichess and mock lnd.
+pub struct TestApp {
+    pub client: Client,
+    pub pool: Pool<Postgres>,
+    pub lichess: MockLichess,
+    pub lnd: MockLnd,
+    _db: TestDb,
+}
+
+pub async fn spawn_app() -> Option<TestApp> {
+    spawn_app_with(|figment| figment).await
+}
+
+// spawn_app with extra Rocket config, e.g. a fee schedule
+pub async fn spawn_app_with(configure: impl FnOnce(Figment) -> Figment) -> Option<TestApp> {
+    let db = test_db().await?;
+    env::set_var("ADMIN_ACCOUNT", ADMIN);
+    env::set_var("LND_MACAROON", "00");
+
+    let lichess = MockLichess::start().await;
+    let lnd = MockLnd::start().await;
+    let figment = rocket::Config::figment()
+        .merge(("lichess_url", &lichess.url))
+        .merge(("lnd_url", &lnd.url))
+        .merge(("allow_loopback_lnurl", true))
+        .merge(("log_level", "off"));
+    let rocket = lightningchess::rocket(db.pool.clone()).configure(configure(figment));
+    let client = Client::tracked(rocket).await.unwrap();
+
+    Some(TestApp { client, pool: db.pool.clone(), lichess, lnd, _db: db })
+}
+
+impl TestApp {
+    // registers username with mock lichess and returns its access token, with the
+    // user and balance rows the OAuth callback would have created
+    pub async fn login(&self, username: &str) -> String {
+        lightningchess::db::ensure_user(&self.pool, username).await.unwrap();
+        self.lichess.add_account(username)
+    }
+
+    // fund and balance_of are in whole sats, like the stakes tests use
+    pub async fn fund(&self, username: &str, sats: i64) {
+        let user_id = lightningchess::db::ensure_user(&self.pool, username).await.unwrap();
+        sqlx::query("UPDATE lightningchess_balance SET balance_msat=$1 WHERE user_id=$2")
+            .bind(sats * 1000)
+            .bind(user_id)
+            .execute(&self.pool).await.unwrap();
+    }
+
+    pub async fn balance_of(&self, username: &str) -> i64 {
+        let balance_msat = self.balance_msat_of(username).await;
+        assert_eq!(balance_msat % 1000, 0, "{username} has {balance_msat} msat");
+        balance_msat / 1000
+    }
+
+    pub async fn balance_msat_of(&self, username: &str) -> i64 {
+        sqlx::query_scalar("SELECT b.balance_msat FROM lightningchess_balance b JOIN users u ON u.user_id=b.user_id WHERE u.lichess_id=lower($1)")
+            .bind(username)
+            .fetch_one(&self.pool).await.unwrap()
+    }
+
+    pub async fn post_empty(&self, path: &str, token: &str) -> (Status, String) {
+        let response = self.client.post(path.to_string())
+            .cookie(Cookie::new("llchess_access_token", token.to_string()))
+            .dispatch().await;
+        (response.status(), response.into_string().await.unwrap_or_default())
+    }
+
+    pub async fn get(&self, path: &str, token: &str) -> (Status, String) {
+        let response = self.client.get(path.to_string())
+            .cookie(Cookie::new("llchess_access_token", token.to_string()))
+            .dispatch().await;
+        (response.status(), response.into_string().await.unwrap_or_default())
+    }
+
+    pub async fn post(&self, path: &str, token: &str, body: serde_json::Value) -> (Status, String) {
+        let response = self.client.post(path.to_string())
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("llchess_access_token", token.to_string()))
+            .body(body.to_string())
+            .dispatch().await;
+        (response.status(), response.into_string().await.unwrap_or_default())
+    }
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use common::{spawn_app, TestApp};
+use lightningchess::models::{Challenge, LedgerEntry, Transaction, TransactionState, TransactionType};
+use rocket::http::{ContentType, Cookie, Status};
+use serde_json::json;
+
+// alice deposits 5000, withdraws 1500, fails to withdraw 500 and stakes 1000 against bob
+async fn history(app: &TestApp, alice: &str) {
+    app.login("bob").await;
+    let (_, body) = app.post("/api/invoice", alice, json!({ "sats": 5000 })).await;
+    let invoice: Transaction = serde_json::from_str(&body).unwrap();
+    app.lnd.pay_invoice(invoice.payment_request.as_ref().unwrap());
+    app.get("/api/balance", alice).await;
+    let out = app.lnd.add_payable(1500, false);
+    let fail = app.lnd.add_payable(500, true);
+    app.post("/api/send-payment", alice, json!({ "payment_request": out })).await;
+    app.post("/api/send-payment", alice, json!({ "payment_request": fail })).await;
+    let (status, body) = app.post("/api/challenge", alice, json!({
+        "time_limit": 300,
+        "increment": 0,
+        "color": "white",
+        "sats": 1000,
+        "opp_username": "bob"
+    })).await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str::<Challenge>(&body).unwrap();
+}
+
+async fn export(app: &TestApp, token: &str, format: &str) -> (Status, Option<ContentType>, String) {
+    let response = app.client.get(format!("/api/transactions/export?format={format}"))
+        .cookie(Cookie::new("llchess_access_token", token.to_string()))
+        .dispatch().await;
+    (response.status(), response.content_type(), response.into_string().await.unwrap_or_default())
+}
+
+#[rocket::async_test]
+async fn json_export_has_running_balance() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    history(&app, &alice).await;
+
+    let (status, content_type, body) = export(&app, &alice, "json").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::JSON));
+    let entries: Vec<LedgerEntry> = serde_json::from_str(&body).unwrap();
+
+    let types: Vec<TransactionType> = entries.iter().map(|e| e.ttype).collect();
+    assert_eq!(types, vec![TransactionType::Invoice, TransactionType::Withdrawal, TransactionType::Withdrawal, TransactionType::CreateChallenge]);
+    let running: Vec<i64> = entries.iter().map(|e| e.running_balance_msat).collect();
+    assert_eq!(running, vec![5_000_000, 3_500_000, 3_500_000, 2_500_000]);
+    assert_eq!(entries[2].state, TransactionState::Failed);
+    assert_eq!(entries[3].counterparty.as_deref(), Some("bob"));
+    assert!(entries[3].challenge_id.is_some());
+    assert_eq!(entries[0].counterparty, None);
+    assert_eq!(app.balance_of("alice").await, 2500);
+}
+
+#[rocket::async_test]
+async fn csv_export_matches_json() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    history(&app, &alice).await;
+
+    let (status, content_type, body) = export(&app, &alice, "csv").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::CSV));
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines[0], "transaction_id,created_at,ttype,state,amount_msat,detail,counterparty,challenge_id,running_balance_msat");
+    assert_eq!(lines.len(), 5);
+
+    let (_, _, body) = export(&app, &alice, "json").await;
+    let entries: Vec<LedgerEntry> = serde_json::from_str(&body).unwrap();
+    let last: Vec<&str> = lines[4].split(',').collect();
+    assert_eq!(last[0], entries[3].transaction_id.to_string());
+    assert_eq!(last[2], "create challenge");
+    assert_eq!(last[6], "bob");
+    assert_eq!(last[8], "2500000");
+    chrono::DateTime::parse_from_rfc3339(last[1]).unwrap();
+}
+
+#[rocket::async_test]
+async fn csv_export_does_not_start_formulas() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    history(&app, &alice).await;
+    sqlx::query("UPDATE lightningchess_transaction SET detail='=HYPERLINK(\"http://evil\")' WHERE ttype='invoice'")
+        .execute(&app.pool).await.unwrap();
+    sqlx::query("UPDATE challenge SET opp_username='@bob'")
+        .execute(&app.pool).await.unwrap();
+
+    let (_, _, body) = export(&app, &alice, "csv").await;
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines[1].split(',').nth(5), Some("\"'=HYPERLINK(\"\"http://evil\"\")\""));
+    let last: Vec<&str> = lines[4].split(',').collect();
+    assert_eq!(last[4], "-1000000");
+    assert_eq!(last[6], "'@bob");
+
+    // json is left as it is
+    let (_, _, body) = export(&app, &alice, "json").await;
+    let entries: Vec<LedgerEntry> = serde_json::from_str(&body).unwrap();
+    assert_eq!(entries[0].detail, "=HYPERLINK(\"http://evil\")");
+    assert_eq!(entries[3].counterparty.as_deref(), Some("@bob"));
+}
+
+#[rocket::async_test]
+async fn export_is_per_user() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    history(&app, &alice).await;
+
+    let (_, _, body) = export(&app, &bob, "json").await;
+    assert_eq!(body, "[]");
+    let (_, _, body) = export(&app, &bob, "csv").await;
+    assert_eq!(body.lines().count(), 1);
+}
+
+#[rocket::async_test]
+async fn unknown_export_format_is_bad_request() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let (status, _, _) = export(&app, &alice, "xlsx").await;
+    assert_eq!(status, Status::BadRequest);
+}
@@ -0,0 +1,207 @@
+mod common;
+
+use common::mock_lichess::{Color, GameResult};
+use common::{spawn_app, spawn_app_with, test_db, TestApp, ADMIN};
+use lightningchess::models::{Challenge, FeeRule, MatchSeries, Tournament, TournamentDetail, TournamentStatus};
+use rocket::error::ErrorKind;
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use serde_json::json;
+
+const STAKE: i64 = 1000;
+
+async fn challenge(app: &TestApp, alice: &str, time_limit: i32) -> Challenge {
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+    app.fund(ADMIN, 0).await;
+    let (status, body) = app.post("/api/challenge", alice, json!({
+        "time_limit": time_limit,
+        "increment": 0,
+        "color": "white",
+        "sats": STAKE,
+        "opp_username": "bob",
+        "fee_msat": 0
+    })).await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str(&body).unwrap()
+}
+
+async fn accept_and_finish(app: &TestApp, bob: &str, challenge_id: i32, result: GameResult) {
+    let (status, body) = app.post("/api/accept-challenge", bob, json!({ "id": challenge_id })).await;
+    assert_eq!(status, Status::Ok);
+    let accepted: Challenge = serde_json::from_str(&body).unwrap();
+    app.lichess.set_game_result(&accepted.lichess_challenge_id.unwrap(), result);
+    let (status, _) = app.get("/api/balance", bob).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn speed_rule_overrides_default() {
+    let app = match spawn_app_with(|figment| figment
+        .merge(("fees.speeds.bullet", json!({ "rate_bps": 0, "min": 30 })))).await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+
+    // the client can't pick its own fee
+    let blitz = challenge(&app, &alice, 300).await;
+    assert_eq!(blitz.fee_msat, 20_000);
+
+    let bullet = challenge(&app, &alice, 60).await;
+    assert_eq!(bullet.fee_msat, 30_000);
+    accept_and_finish(&app, &bob, bullet.id, GameResult::Mate(Color::White)).await;
+    assert_eq!(app.balance_of(ADMIN).await, 30);
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE + 2 * STAKE - 30);
+}
+
+#[rocket::async_test]
+async fn promotion_waives_fee() {
+    let from = (chrono::Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+    let to = (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+    let app = match spawn_app_with(|figment| figment
+        .merge(("fees.promotions", json!([{ "from": from, "to": to }])))).await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+
+    let created = challenge(&app, &alice, 300).await;
+    assert_eq!(created.fee_msat, 0);
+    accept_and_finish(&app, &bob, created.id, GameResult::Draw).await;
+    assert_eq!(app.balance_of("alice").await, 10_000);
+    assert_eq!(app.balance_of("bob").await, 10_000);
+    assert_eq!(app.balance_of(ADMIN).await, 0);
+    // no zero fee row in the house's ledger
+    let fees: i64 = sqlx::query_scalar("SELECT count(*) FROM lightningchess_transaction WHERE ttype='fee'")
+        .fetch_one(&app.pool).await.unwrap();
+    assert_eq!(fees, 0);
+}
+
+#[rocket::async_test]
+async fn settlement_uses_fee_fixed_at_creation() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+
+    // as if the schedule had charged more when the challenge was created
+    let created = challenge(&app, &alice, 300).await;
+    sqlx::query("UPDATE challenge SET fee_msat=50000 WHERE id=$1")
+        .bind(created.id)
+        .execute(&app.pool).await.unwrap();
+    accept_and_finish(&app, &bob, created.id, GameResult::Resign(Color::Black)).await;
+    assert_eq!(app.balance_of(ADMIN).await, 50);
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE + 2 * STAKE - 50);
+}
+
+#[rocket::async_test]
+async fn match_fee_is_capped_at_max() {
+    let app = match spawn_app_with(|figment| figment
+        .merge(("fees.default", json!({ "rate_bps": 1000, "flat": 5, "max": 41 })))).await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.login("bob").await;
+    app.fund("alice", 10_000).await;
+
+    let (status, body) = app.post("/api/match", &alice, json!({ "opp_username": "bob", "target": 3, "sats": STAKE })).await;
+    assert_eq!(status, Status::Ok);
+    let series: MatchSeries = serde_json::from_str(&body).unwrap();
+    assert_eq!(series.fee_msat, 41_000);
+}
+
+#[rocket::async_test]
+async fn tournament_keeps_its_fee_rule() {
+    let app = match spawn_app_with(|figment| figment
+        .merge(("fees.tournament", json!({ "rate_bps": 500 })))).await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    app.fund(ADMIN, 0).await;
+
+    let starts_at = (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+    let (status, body) = app.post("/api/tournament", &organiser, json!({
+        "name": "Friday blitz", "buy_in": STAKE, "min_players": 2, "rounds": 1, "time_limit": 180, "increment": 0, "starts_at": starts_at, "fee_rate_bps": 0
+    })).await;
+    assert_eq!(status, Status::Ok);
+    let tournament: Tournament = serde_json::from_str(&body).unwrap();
+    assert_eq!(tournament.fee_rate_bps, 500);
+
+    let mut tokens = Vec::new();
+    for player in ["alice", "bob"] {
+        let token = app.login(player).await;
+        app.fund(player, 10_000).await;
+        let (status, _) = app.post_empty(&format!("/api/tournament/{}/join", tournament.id), &token).await;
+        assert_eq!(status, Status::Ok);
+        tokens.push(token);
+    }
+    sqlx::query("UPDATE tournament SET starts_at=now() - INTERVAL '1 minute' WHERE id=$1")
+        .bind(tournament.id)
+        .execute(&app.pool).await.unwrap();
+    app.get("/api/balance", &tokens[0]).await;
+    app.get("/api/balance", &tokens[1]).await;
+
+    let (_, body) = app.get(&format!("/api/tournament/{}", tournament.id), "").await;
+    let detail: TournamentDetail = serde_json::from_str(&body).unwrap();
+    let game_id = detail.pairings[0].lichess_game_id.clone().unwrap();
+    app.lichess.set_game_result(&game_id, GameResult::Mate(Color::White));
+    app.get("/api/balance", &tokens[0]).await;
+
+    let (_, body) = app.get(&format!("/api/tournament/{}", tournament.id), "").await;
+    let detail: TournamentDetail = serde_json::from_str(&body).unwrap();
+    assert_eq!(detail.tournament.status, TournamentStatus::Completed);
+    assert_eq!(app.balance_of(ADMIN).await, 100);
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE + 2 * STAKE - 100);
+}
+
+#[rocket::async_test]
+async fn invalid_fee_schedule_fails_to_launch() {
+    let db = match test_db().await { Some(db) => db, None => return };
+    let figment = rocket::Config::figment()
+        .merge(("fees.default", json!({ "rate_bps": 100, "min": 50, "max": 10 })))
+        .merge(("log_level", "off"));
+    let rocket = lightningchess::rocket(db.pool.clone()).configure(figment);
+    match Client::tracked(rocket).await {
+        Ok(_) => panic!("launched with min above max"),
+        Err(e) => assert!(matches!(e.kind(), ErrorKind::FailedFairings(_)))
+    }
+}
+
+#[rocket::async_test]
+async fn small_stakes_pay_a_fee() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.login("bob").await;
+    app.fund("alice", 10_000).await;
+
+    let (status, body) = app.post("/api/challenge", &alice, json!({ "time_limit": 300, "sats": 10, "opp_username": "bob" })).await;
+    assert_eq!(status, Status::Ok);
+    let created: Challenge = serde_json::from_str(&body).unwrap();
+    assert_eq!(created.fee_msat, 200);
+}
+
+#[rocket::async_test]
+async fn draw_rounding_goes_to_the_house() {
+    let app = match spawn_app_with(|figment| figment
+        .merge(("fees.default", json!({ "rate_bps": 7 })))).await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+    app.fund(ADMIN, 0).await;
+
+    // 0.07% of a 3 sat pot is 2.1 msat, the 2998 msat left splits 1:2 into 999.33 and 1998.67
+    let (status, body) = app.post("/api/challenge", &alice, json!({ "time_limit": 300, "color": "white", "sats": 1, "opponent_sats": 2, "opp_username": "bob" })).await;
+    assert_eq!(status, Status::Ok);
+    let created: Challenge = serde_json::from_str(&body).unwrap();
+    assert_eq!(created.fee_msat, 2);
+    accept_and_finish(&app, &bob, created.id, GameResult::Draw).await;
+
+    assert_eq!(app.balance_msat_of("alice").await, 10_000_000 - 1000 + 999);
+    assert_eq!(app.balance_msat_of("bob").await, 10_000_000 - 2000 + 1998);
+    assert_eq!(app.balance_msat_of(ADMIN).await, 3);
+    let rounding: i64 = sqlx::query_scalar("SELECT amount_msat FROM lightningchess_transaction WHERE ttype='rounding' AND challenge_id=$1")
+        .bind(created.id)
+        .fetch_one(&app.pool).await.unwrap();
+    assert_eq!(rounding, 1);
+}
+
+#[test]
+fn fee_on_a_huge_pot_does_not_overflow() {
+    let rule = FeeRule { rate_bps: 10000, ..Default::default() };
+    assert_eq!(rule.fee_msat(i64::MAX), i64::MAX);
+    let rule = FeeRule { rate_bps: 250, flat: 1, ..Default::default() };
+    assert_eq!(rule.fee_msat(i64::MAX / 2), (i64::MAX / 2) / 40 + 1000);
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::{spawn_app, spawn_app_with, TestApp};
+use lightningchess::lightning::lnurl::description_hash;
+use lightningchess::models::{LnurlPayInvoice, LnurlPayRequest};
+use rocket::http::{ContentType, Header, Status};
+use serde_json::{json, Value};
+
+// what a wallet gets, always json even for an error
+async fn get_json(app: &TestApp, path: &str) -> Value {
+    let response = app.client.get(path.to_string()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    serde_json::from_str(&response.into_string().await.unwrap()).unwrap()
+}
+
+// the callback as a wallet would call it, from the payRequest's absolute url
+async fn call_back(app: &TestApp, pay_request: &LnurlPayRequest, amount_msat: i64) -> Value {
+    let (_, path) = pay_request.callback.split_once("/api/").unwrap();
+    get_json(app, &format!("/api/{path}?amount={amount_msat}")).await
+}
+
+#[rocket::async_test]
+async fn friend_funds_player_through_address() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("Alice").await;
+    app.fund("Alice", 1000).await;
+
+    let pay_request: LnurlPayRequest = serde_json::from_value(get_json(&app, "/.well-known/lnurlp/alice").await).unwrap();
+    assert_eq!(pay_request.tag, "payRequest");
+    assert_eq!(pay_request.min_sendable, 1000);
+    let metadata: Vec<(String, String)> = serde_json::from_str(&pay_request.metadata).unwrap();
+    assert!(metadata.contains(&("text/identifier".to_string(), "alice@localhost:8000".to_string())));
+
+    let reply = call_back(&app, &pay_request, 21_500).await;
+    assert_eq!(reply["routes"], json!([]));
+    let invoice: LnurlPayInvoice = serde_json::from_value(reply).unwrap();
+    let created = app.lnd.invoices().into_iter().find(|i| i.payment_request == invoice.pr).unwrap();
+    assert_eq!(created.value_msat, 21_500);
+    assert_eq!(hex::encode(base64::decode(&created.description_hash).unwrap()), description_hash(&pay_request.metadata));
+
+    // settled into alice's balance the next time she looks
+    app.lnd.pay_invoice(&invoice.pr);
+    let (status, _) = app.get("/api/balance", &alice).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.balance_msat_of("alice").await, 1_021_500);
+}
+
+#[rocket::async_test]
+async fn metadata_is_stable() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    app.fund("alice", 0).await;
+
+    // the case a wallet uses doesn't change what the invoice commits to
+    let lower: LnurlPayRequest = serde_json::from_value(get_json(&app, "/.well-known/lnurlp/alice").await).unwrap();
+    let upper: LnurlPayRequest = serde_json::from_value(get_json(&app, "/.well-known/lnurlp/ALICE").await).unwrap();
+    assert_eq!(lower.metadata, upper.metadata);
+    assert_eq!(lower.callback, upper.callback);
+}
+
+#[rocket::async_test]
+async fn unknown_player_has_no_address() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+
+    let reply = get_json(&app, "/.well-known/lnurlp/nobody").await;
+    assert_eq!(reply["status"], "ERROR");
+    let reply = get_json(&app, "/api/lnurlp/nobody/callback?amount=1000").await;
+    assert_eq!(reply["status"], "ERROR");
+    assert!(app.lnd.invoices().is_empty());
+}
+
+#[rocket::async_test]
+async fn amount_outside_range_is_refused() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    app.fund("alice", 0).await;
+
+    for query in ["", "?amount=999", "?amount=lots", "?amount=100000000001"] {
+        let reply = get_json(&app, &format!("/api/lnurlp/alice/callback{query}")).await;
+        assert_eq!(reply["status"], "ERROR");
+    }
+    assert!(app.lnd.invoices().is_empty());
+}
+
+#[rocket::async_test]
+async fn deposit_limit_caps_address() {
+    let app = match spawn_app_with(|figment| figment.merge(("limits.max_deposit", 5000))).await { Some(app) => app, None => return };
+    app.fund("alice", 0).await;
+
+    let pay_request: LnurlPayRequest = serde_json::from_value(get_json(&app, "/.well-known/lnurlp/alice").await).unwrap();
+    assert_eq!(pay_request.max_sendable, 5_000_000);
+    let reply = call_back(&app, &pay_request, 5_000_001).await;
+    assert_eq!(reply["status"], "ERROR");
+    assert!(app.lnd.invoices().is_empty());
+    let reply = call_back(&app, &pay_request, 5_000_000).await;
+    assert!(reply["pr"].is_string());
+}
+
+// the callback from a wallet at ip
+async fn call_back_from(app: &TestApp, ip: &str, pay_request: &LnurlPayRequest, amount_msat: i64) -> Value {
+    let (_, path) = pay_request.callback.split_once("/api/").unwrap();
+    let response = app.client.get(format!("/api/{path}?amount={amount_msat}"))
+        .header(Header::new("X-Real-IP", ip.to_string()))
+        .dispatch().await;
+    serde_json::from_str(&response.into_string().await.unwrap()).unwrap()
+}
+
+#[rocket::async_test]
+async fn unpaid_invoices_are_capped_per_requester() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("bob", 0).await;
+    let to_alice: LnurlPayRequest = serde_json::from_value(get_json(&app, "/.well-known/lnurlp/alice").await).unwrap();
+    let to_bob: LnurlPayRequest = serde_json::from_value(get_json(&app, "/.well-known/lnurlp/bob").await).unwrap();
+
+    // the cap counts a requester's invoices across every address
+    for _ in 0..3 {
+        assert!(call_back_from(&app, "203.0.113.7", &to_alice, 1000).await["pr"].is_string());
+    }
+    for _ in 0..2 {
+        assert!(call_back_from(&app, "203.0.113.7", &to_bob, 1000).await["pr"].is_string());
+    }
+    assert_eq!(call_back_from(&app, "203.0.113.7", &to_alice, 1000).await["status"], "ERROR");
+
+    // anyone else can still pay alice, and her own invoices are still polled
+    assert!(call_back_from(&app, "198.51.100.1", &to_alice, 1000).await["pr"].is_string());
+    assert_eq!(app.lnd.invoices().len(), 6);
+    let (status, _) = app.post("/api/invoice", &alice, json!({ "sats": 5 })).await;
+    assert_eq!(status, Status::Ok);
+    let own = app.lnd.invoices().last().unwrap().payment_request.clone();
+    app.lnd.pay_invoice(&own);
+    app.get("/api/balance", &alice).await;
+    assert_eq!(app.balance_msat_of("alice").await, 5000);
+
+    // paying one frees a slot
+    let first = app.lnd.invoices()[0].payment_request.clone();
+    app.lnd.pay_invoice(&first);
+    app.get("/api/balance", &alice).await;
+    assert_eq!(app.balance_msat_of("alice").await, 6000);
+    assert!(call_back_from(&app, "203.0.113.7", &to_alice, 1000).await["pr"].is_string());
+
+    // expired ones don't count
+    sqlx::query("UPDATE lightningchess_transaction SET created_at=now() - INTERVAL '31 minutes'")
+        .execute(&app.pool).await.unwrap();
+    assert!(call_back_from(&app, "203.0.113.7", &to_alice, 1000).await["pr"].is_string());
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use common::{spawn_app_with, TestApp};
+use lightningchess::models::{Challenge, LimitsResponse};
+use rocket::http::Status;
+use serde_json::json;
+
+async fn limited(limits: serde_json::Value) -> Option<TestApp> {
+    spawn_app_with(|figment| figment.merge(("limits", limits))).await
+}
+
+async fn challenge(app: &TestApp, token: &str, sats: i64) -> (Status, String) {
+    app.post("/api/challenge", token, json!({ "time_limit": 300, "color": "white", "sats": sats, "opp_username": "bob" })).await
+}
+
+#[rocket::async_test]
+async fn deposit_over_limit_is_forbidden() {
+    let app = match limited(json!({ "max_deposit": 5000 })).await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+
+    let (status, _) = app.post("/api/invoice", &alice, json!({ "sats": 5001 })).await;
+    assert_eq!(status, Status::Forbidden);
+    let (status, _) = app.post("/api/invoice", &alice, json!({ "msat": 5_000_001 })).await;
+    assert_eq!(status, Status::Forbidden);
+    assert!(app.lnd.invoices().is_empty());
+
+    let (status, _) = app.post("/api/invoice", &alice, json!({ "sats": 5000 })).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn stake_over_limit_is_forbidden() {
+    let app = match limited(json!({ "max_stake": 1000 })).await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+
+    let (status, _) = challenge(&app, &alice, 1001).await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(app.balance_of("alice").await, 10_000);
+
+    // alice's side is within the limit, bob's isn't
+    let (status, body) = app.post("/api/challenge", &alice, json!({ "time_limit": 300, "color": "white", "sats": 500, "opponent_sats": 2000, "opp_username": "bob" })).await;
+    assert_eq!(status, Status::Ok);
+    let created: Challenge = serde_json::from_str(&body).unwrap();
+    let (status, _) = app.post("/api/accept-challenge", &bob, json!({ "id": created.id })).await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(app.balance_of("bob").await, 10_000);
+}
+
+#[rocket::async_test]
+async fn exposure_counts_open_stakes() {
+    let app = match limited(json!({ "max_exposure": 1500 })).await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.login("bob").await;
+    app.fund("alice", 10_000).await;
+
+    let (status, _) = challenge(&app, &alice, 1000).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = challenge(&app, &alice, 600).await;
+    assert_eq!(status, Status::Forbidden);
+    let (status, _) = challenge(&app, &alice, 500).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.balance_of("alice").await, 8500);
+}
+
+#[rocket::async_test]
+async fn daily_withdrawal_limit() {
+    let app = match limited(json!({ "daily_withdrawal": 2000 })).await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let first = app.lnd.add_payable(1500, false);
+    let fail = app.lnd.add_payable(1500, true);
+    let second = app.lnd.add_payable(600, false);
+    let third = app.lnd.add_payable(500, false);
+
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "payment_request": first })).await;
+    assert_eq!(status, Status::Ok);
+    // a failed payment gives its share of the limit back
+    app.post("/api/send-payment", &alice, json!({ "payment_request": fail })).await;
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "payment_request": second })).await;
+    assert_eq!(status, Status::Forbidden);
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "payment_request": third })).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.balance_of("alice").await, 8000);
+
+    // yesterday's withdrawals don't count
+    sqlx::query("UPDATE lightningchess_transaction SET created_at=now() - INTERVAL '25 hours' WHERE ttype='withdrawal'")
+        .execute(&app.pool).await.unwrap();
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "payment_request": second })).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn user_limits_override_global() {
+    let app = match limited(json!({ "max_stake": 1000, "max_exposure": 5000 })).await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    let user_id = lightningchess::db::ensure_user(&app.pool, "alice").await.unwrap();
+    sqlx::query("INSERT INTO user_limits (user_id, max_stake) VALUES ($1, 3000)")
+        .bind(user_id)
+        .execute(&app.pool).await.unwrap();
+
+    let (status, _) = challenge(&app, &alice, 3000).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = challenge(&app, &alice, 2500).await;
+    assert_eq!(status, Status::Forbidden);
+
+    let (status, body) = app.get("/api/limits", &alice).await;
+    assert_eq!(status, Status::Ok);
+    let limits: LimitsResponse = serde_json::from_str(&body).unwrap();
+    assert_eq!(limits.limits.max_stake, Some(3000));
+    assert_eq!(limits.limits.max_exposure, Some(5000));
+    assert_eq!(limits.limits.max_deposit, None);
+    assert_eq!(limits.exposure, 3000);
+    assert_eq!(limits.withdrawn_msat, 0);
+}
+
+#[rocket::async_test]
+async fn limits_report_accepted_stakes() {
+    let app = match limited(json!({})).await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+
+    let (_, body) = app.post("/api/challenge", &alice, json!({ "time_limit": 300, "color": "white", "sats": 1000, "opponent_sats": 400, "opp_username": "bob" })).await;
+    let created: Challenge = serde_json::from_str(&body).unwrap();
+    let (_, body) = app.get("/api/limits", &bob).await;
+    let limits: LimitsResponse = serde_json::from_str(&body).unwrap();
+    assert_eq!(limits.exposure, 0);
+
+    let (status, _) = app.post("/api/accept-challenge", &bob, json!({ "id": created.id })).await;
+    assert_eq!(status, Status::Ok);
+    let (_, body) = app.get("/api/limits", &bob).await;
+    let limits: LimitsResponse = serde_json::from_str(&body).unwrap();
+    assert_eq!(limits.exposure, 400);
+    assert_eq!(limits.limits.max_stake, None);
+}
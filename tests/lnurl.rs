@@ -0,0 +1,177 @@
+mod common;
+
+use common::mock_lnurl::{MockLnurl, MAX_SENDABLE};
+use common::{spawn_app, spawn_app_with, TestApp};
+use lightningchess::lightning::lnurl::{description_hash, pay_url};
+use rocket::http::Status;
+use serde_json::json;
+
+// scripts the invoice the service will hand out, committing to its metadata
+fn script_invoice(app: &TestApp, lnurl: &MockLnurl, name: &str, amount_msat: i64) -> String {
+    let pr = app.lnd.add_described_payable(amount_msat, &description_hash(&lnurl.metadata(name)));
+    lnurl.set_invoice(name, &pr);
+    pr
+}
+
+#[rocket::async_test]
+async fn withdraw_to_lightning_address() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let lnurl = MockLnurl::start().await;
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let pr = script_invoice(&app, &lnurl, "carol", 2_000_000);
+
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "lnurl": lnurl.address("carol"), "sats": 2000 })).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(lnurl.callbacks(), vec!["carol 2000000"]);
+    assert_eq!(app.lnd.payments(), vec![pr]);
+    assert_eq!(app.balance_of("alice").await, 8000);
+}
+
+#[rocket::async_test]
+async fn withdraw_msat_to_bech32_lnurl() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let lnurl = MockLnurl::start().await;
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    script_invoice(&app, &lnurl, "carol", 1_500_500);
+
+    let link = format!("lightning:{}", lnurl.lnurl("carol").to_uppercase());
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "lnurl": link, "msat": 1_500_500 })).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.balance_msat_of("alice").await, 10_000_000 - 1_500_500);
+}
+
+#[rocket::async_test]
+async fn invoice_for_another_amount_is_not_paid() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let lnurl = MockLnurl::start().await;
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    // the service asks for more than it was told to
+    let pr = app.lnd.add_described_payable(3_000_000, &description_hash(&lnurl.metadata("mallory")));
+    lnurl.set_invoice("mallory", &pr);
+
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "lnurl": lnurl.address("mallory"), "sats": 2000 })).await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(app.lnd.payments().is_empty());
+    assert_eq!(app.balance_of("alice").await, 10_000);
+}
+
+#[rocket::async_test]
+async fn invoice_for_another_description_is_not_paid() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let lnurl = MockLnurl::start().await;
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let pr = app.lnd.add_described_payable(2_000_000, &description_hash(&lnurl.metadata("someone else")));
+    lnurl.set_invoice("mallory", &pr);
+
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "lnurl": lnurl.address("mallory"), "sats": 2000 })).await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(app.lnd.payments().is_empty());
+    assert_eq!(app.balance_of("alice").await, 10_000);
+}
+
+#[rocket::async_test]
+async fn amount_outside_pay_request_is_rejected() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let lnurl = MockLnurl::start().await;
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000_000).await;
+
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "lnurl": lnurl.address("carol"), "msat": 999 })).await;
+    assert_eq!(status, Status::BadRequest);
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "lnurl": lnurl.address("carol"), "msat": MAX_SENDABLE + 1 })).await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(lnurl.callbacks().is_empty());
+}
+
+#[rocket::async_test]
+async fn lnurl_needs_exactly_one_amount() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let lnurl = MockLnurl::start().await;
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let out = app.lnd.add_payable(1500, false);
+
+    for body in [
+        json!({ "lnurl": lnurl.address("carol") }),
+        json!({ "lnurl": lnurl.address("carol"), "sats": 1000, "msat": 1_000_000 }),
+        json!({ "lnurl": lnurl.address("carol"), "sats": 0 }),
+        json!({ "lnurl": lnurl.address("carol"), "payment_request": out, "sats": 1000 }),
+        json!({ "payment_request": out, "sats": 1500 }),
+    ] {
+        let (status, _) = app.post("/api/send-payment", &alice, body).await;
+        assert_eq!(status, Status::BadRequest);
+    }
+    assert!(lnurl.callbacks().is_empty());
+    assert!(app.lnd.payments().is_empty());
+}
+
+#[test]
+fn lnurls_resolve_to_https() {
+    assert_eq!(pay_url("alice@example.com", false).unwrap().as_str(), "https://example.com/.well-known/lnurlp/alice");
+    assert_eq!(pay_url("Alice@wallet.onion", false).unwrap().as_str(), "http://wallet.onion/.well-known/lnurlp/alice");
+    assert_eq!(pay_url("lnurlp://example.com/pay/1", false).unwrap().as_str(), "https://example.com/pay/1");
+    assert!(pay_url("alice/../admin@example.com", false).is_none());
+    assert!(pay_url("@example.com", false).is_none());
+
+    let plain_http = bech32::encode("lnurl", bech32::ToBase32::to_base32(&"http://example.com/pay"), bech32::Variant::Bech32).unwrap();
+    assert!(pay_url(&plain_http, false).is_none());
+    let not_lnurl = bech32::encode("lnbc", bech32::ToBase32::to_base32(&"https://example.com/pay"), bech32::Variant::Bech32).unwrap();
+    assert!(pay_url(&not_lnurl, false).is_none());
+
+    // this machine and its network are only reachable when tests allow it, over plain http too
+    for lnurl in ["alice@localhost:8000", "alice@127.0.0.1", "lnurlp://[::1]/pay/1", "alice@10.1.2.3", "alice@192.168.1.1",
+                  "alice@172.16.0.1", "alice@100.64.0.1", "lnurlp://169.254.169.254/latest", "lnurlp://[fe80::1]/pay", "lnurlp://[fd00::1]/pay",
+                  "lnurlp://[::ffff:127.0.0.1]/pay", "alice@0.0.0.0"] {
+        assert!(pay_url(lnurl, false).is_none(), "{lnurl}");
+        assert_eq!(pay_url(lnurl, true).unwrap().scheme(), "http", "{lnurl}");
+    }
+    for internal in ["https://127.0.0.1/pay", "https://10.0.0.1/pay", "https://169.254.169.254/pay", "https://[::1]/pay", "https://app.localhost/pay"] {
+        let lnurl = bech32::encode("lnurl", bech32::ToBase32::to_base32(&internal), bech32::Variant::Bech32).unwrap();
+        assert!(pay_url(&lnurl, false).is_none(), "{internal}");
+        assert!(pay_url(&lnurl, true).is_some(), "{internal}");
+    }
+    assert!(pay_url("alice@8.8.8.8", false).is_some());
+    assert!(pay_url("lnurlp://[2001:db8::1]/pay", false).is_some());
+    let loopback = bech32::encode("lnurl", bech32::ToBase32::to_base32(&"http://127.0.0.1:9735/pay"), bech32::Variant::Bech32).unwrap();
+    assert!(pay_url(&loopback, false).is_none());
+    assert!(pay_url(&loopback, true).is_some());
+}
+
+#[rocket::async_test]
+async fn loopback_lnurl_is_refused_by_default() {
+    let app = match spawn_app_with(|figment| figment.merge(("allow_loopback_lnurl", false))).await { Some(app) => app, None => return };
+    let lnurl = MockLnurl::start().await;
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    script_invoice(&app, &lnurl, "carol", 2_000_000);
+
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "lnurl": lnurl.address("carol"), "sats": 2000 })).await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(lnurl.callbacks().is_empty());
+    assert!(app.lnd.payments().is_empty());
+    assert_eq!(app.balance_of("alice").await, 10_000);
+}
+
+#[rocket::async_test]
+async fn redirects_are_checked() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let lnurl = MockLnurl::start().await;
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    script_invoice(&app, &lnurl, "carol", 2_000_000);
+
+    // somewhere the lnurl couldn't have pointed itself isn't followed
+    let link = lnurl.redirect_to("http://example.com/.well-known/lnurlp/carol");
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "lnurl": link, "sats": 2000 })).await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(lnurl.callbacks().is_empty());
+
+    let link = lnurl.redirect_to(&format!("{}/lnurlp/carol", lnurl.url));
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "lnurl": link, "sats": 2000 })).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(lnurl.callbacks(), vec!["carol 2000000"]);
+}
@@ -0,0 +1,247 @@
+mod common;
+
+use bech32::FromBase32;
+use common::{spawn_app, spawn_app_with, TestApp};
+use lightningchess::models::{LnurlWithdrawLink, LnurlWithdrawRequest, TransactionState};
+use rocket::http::Status;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+async fn create_link(app: &TestApp, token: &str, sats: i64) -> LnurlWithdrawLink {
+    let (status, body) = app.post("/api/withdraw/lnurl", token, json!({ "sats": sats })).await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str(&body).unwrap()
+}
+
+// what a wallet scanning the QR code would fetch
+async fn scan(app: &TestApp, link: &LnurlWithdrawLink) -> Value {
+    let (_, data, _) = bech32::decode(&link.lnurl).unwrap();
+    let url = String::from_utf8(Vec::<u8>::from_base32(&data).unwrap()).unwrap();
+    let (_, path) = url.split_once("/api/").unwrap();
+    let (status, body) = app.get(&format!("/api/{path}"), "").await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str(&body).unwrap()
+}
+
+async fn call_back(app: &TestApp, k1: &str, pr: &str) -> Value {
+    let (status, body) = app.get(&format!("/api/withdraw/lnurl/callback?k1={k1}&pr={pr}"), "").await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str(&body).unwrap()
+}
+
+async fn state_of(app: &TestApp, transaction_id: i32) -> TransactionState {
+    sqlx::query_scalar("SELECT state FROM lightningchess_transaction WHERE transaction_id=$1")
+        .bind(transaction_id)
+        .fetch_one(&app.pool).await.unwrap()
+}
+
+// the callback answers before the payment is made, so wait for it to settle or fail
+async fn paid(app: &TestApp, transaction_id: i32) -> TransactionState {
+    for _ in 0..100 {
+        match state_of(app, transaction_id).await {
+            TransactionState::Open => tokio::time::sleep(Duration::from_millis(50)).await,
+            state => return state
+        }
+    }
+    panic!("withdrawal {transaction_id} still open")
+}
+
+#[rocket::async_test]
+async fn wallet_withdraws_through_link() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let wallet = app.lnd.add_payable(3000, false);
+
+    let link = create_link(&app, &alice, 3000).await;
+    assert!(link.lnurl.starts_with("LNURL1"));
+    assert_eq!(link.amount_msat, 3_000_000);
+    // reserved as soon as the link exists
+    assert_eq!(app.balance_of("alice").await, 7000);
+
+    let request: LnurlWithdrawRequest = serde_json::from_value(scan(&app, &link).await).unwrap();
+    assert_eq!(request.tag, "withdrawRequest");
+    assert_eq!(request.min_withdrawable, 3_000_000);
+    assert_eq!(request.max_withdrawable, 3_000_000);
+    assert!(request.callback.ends_with("/api/withdraw/lnurl/callback"));
+
+    let reply = call_back(&app, &request.k1, &wallet).await;
+    assert_eq!(reply["status"], "OK");
+    assert_eq!(paid(&app, link.transaction_id).await, TransactionState::Settled);
+    assert_eq!(app.lnd.payments(), vec![wallet.clone()]);
+    assert_eq!(app.balance_of("alice").await, 7000);
+
+    // one time only
+    let reply = call_back(&app, &request.k1, &wallet).await;
+    assert_eq!(reply["status"], "ERROR");
+    assert_eq!(scan(&app, &link).await["status"], "ERROR");
+    assert_eq!(app.lnd.payments().len(), 1);
+}
+
+#[rocket::async_test]
+async fn invoice_for_another_amount_is_refused() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let greedy = app.lnd.add_payable(4000, false);
+    let wallet = app.lnd.add_payable(3000, false);
+
+    let link = create_link(&app, &alice, 3000).await;
+    let k1 = scan(&app, &link).await["k1"].as_str().unwrap().to_string();
+    let reply = call_back(&app, &k1, &greedy).await;
+    assert_eq!(reply["status"], "ERROR");
+    let reply = call_back(&app, &k1, "lnbcunknown").await;
+    assert_eq!(reply["status"], "ERROR");
+    assert!(app.lnd.payments().is_empty());
+
+    // the link is still good for the right invoice
+    let reply = call_back(&app, &k1, &wallet).await;
+    assert_eq!(reply["status"], "OK");
+    assert_eq!(paid(&app, link.transaction_id).await, TransactionState::Settled);
+    assert_eq!(app.balance_of("alice").await, 7000);
+}
+
+#[rocket::async_test]
+async fn callback_answers_before_payment() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let wallet = app.lnd.add_payable(3000, false);
+    let held = app.lnd.hold_payments().await;
+
+    let link = create_link(&app, &alice, 3000).await;
+    let k1 = scan(&app, &link).await["k1"].as_str().unwrap().to_string();
+    assert_eq!(call_back(&app, &k1, &wallet).await["status"], "OK");
+    assert_eq!(state_of(&app, link.transaction_id).await, TransactionState::Open);
+    // claimed, so a retry while the payment is on its way can't pay it twice
+    assert_eq!(call_back(&app, &k1, &wallet).await["status"], "ERROR");
+
+    drop(held);
+    assert_eq!(paid(&app, link.transaction_id).await, TransactionState::Settled);
+    assert_eq!(app.lnd.payments(), vec![wallet]);
+    assert_eq!(app.balance_of("alice").await, 7000);
+}
+
+#[rocket::async_test]
+async fn failed_payment_refunds_link() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let fail = app.lnd.add_payable(3000, true);
+
+    let link = create_link(&app, &alice, 3000).await;
+    let k1 = scan(&app, &link).await["k1"].as_str().unwrap().to_string();
+    // the wallet was told OK before the payment failed, the link's amount comes back
+    let reply = call_back(&app, &k1, &fail).await;
+    assert_eq!(reply["status"], "OK");
+    assert_eq!(paid(&app, link.transaction_id).await, TransactionState::Failed);
+    assert_eq!(app.balance_of("alice").await, 10_000);
+}
+
+// a payment still in flight when the spawned payer gives up is looked up again on a later balance poll
+#[rocket::async_test]
+async fn unconfirmed_payment_is_reconciled() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let sent = app.lnd.add_in_flight_payable(3000);
+    let lost = app.lnd.add_in_flight_payable(2000);
+
+    let mut links = vec![];
+    for (sats, wallet) in [(3000, &sent), (2000, &lost)] {
+        let link = create_link(&app, &alice, sats).await;
+        let k1 = scan(&app, &link).await["k1"].as_str().unwrap().to_string();
+        assert_eq!(call_back(&app, &k1, wallet).await["status"], "OK");
+        links.push(link);
+    }
+    while app.lnd.payments().len() < 2 {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    app.get("/api/balance", &alice).await;
+    // too recent to look up, and still in flight anyway
+    assert_eq!(state_of(&app, links[0].transaction_id).await, TransactionState::Open);
+    assert_eq!(app.balance_of("alice").await, 5000);
+
+    sqlx::query("UPDATE lightningchess_transaction SET updated_at=now() - INTERVAL '2 minutes' WHERE username='alice'")
+        .execute(&app.pool).await.unwrap();
+    app.get("/api/balance", &alice).await;
+    assert_eq!(state_of(&app, links[0].transaction_id).await, TransactionState::Open);
+
+    app.lnd.finish_payment(&sent, false);
+    app.lnd.finish_payment(&lost, true);
+    app.get("/api/balance", &alice).await;
+    assert_eq!(state_of(&app, links[0].transaction_id).await, TransactionState::Settled);
+    assert_eq!(state_of(&app, links[1].transaction_id).await, TransactionState::Failed);
+    assert_eq!(app.balance_of("alice").await, 7000);
+    // only refunded once
+    app.get("/api/balance", &alice).await;
+    assert_eq!(app.balance_of("alice").await, 7000);
+}
+
+#[rocket::async_test]
+async fn callback_without_invoice_is_an_lnurl_error() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+
+    let link = create_link(&app, &alice, 3000).await;
+    let k1 = scan(&app, &link).await["k1"].as_str().unwrap().to_string();
+    let (status, body) = app.get(&format!("/api/withdraw/lnurl/callback?k1={k1}"), "").await;
+    assert_eq!(status, Status::Ok);
+    let reply: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(reply["status"], "ERROR");
+    assert!(reply["reason"].is_string());
+    assert_eq!(state_of(&app, link.transaction_id).await, TransactionState::Open);
+}
+
+#[rocket::async_test]
+async fn expired_link_is_refunded() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let wallet = app.lnd.add_payable(3000, false);
+
+    let link = create_link(&app, &alice, 3000).await;
+    let k1 = scan(&app, &link).await["k1"].as_str().unwrap().to_string();
+    sqlx::query("UPDATE lnurl_withdrawal SET expires_at=now() - INTERVAL '1 second' WHERE transaction_id=$1")
+        .bind(link.transaction_id)
+        .execute(&app.pool).await.unwrap();
+
+    assert_eq!(scan(&app, &link).await["status"], "ERROR");
+    assert_eq!(call_back(&app, &k1, &wallet).await["status"], "ERROR");
+    assert!(app.lnd.payments().is_empty());
+
+    let (status, _) = app.get("/api/balance", &alice).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.balance_of("alice").await, 10_000);
+    assert_eq!(state_of(&app, link.transaction_id).await, TransactionState::Canceled);
+    // only once
+    app.get("/api/balance", &alice).await;
+    assert_eq!(app.balance_of("alice").await, 10_000);
+}
+
+#[rocket::async_test]
+async fn link_needs_the_balance() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 1000).await;
+
+    let (status, _) = app.post("/api/withdraw/lnurl", &alice, json!({ "sats": 1001 })).await;
+    assert_eq!(status, Status::PaymentRequired);
+    let (status, _) = app.post("/api/withdraw/lnurl", &alice, json!({ "sats": 500, "msat": 500_000 })).await;
+    assert_eq!(status, Status::BadRequest);
+    let (status, _) = app.post("/api/withdraw/lnurl", &alice, json!({ "msat": 0 })).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(app.balance_of("alice").await, 1000);
+}
+
+#[rocket::async_test]
+async fn links_count_against_daily_withdrawals() {
+    let app = match spawn_app_with(|figment| figment.merge(("limits", json!({ "daily_withdrawal": 5000 })))).await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+
+    create_link(&app, &alice, 3000).await;
+    let (status, _) = app.post("/api/withdraw/lnurl", &alice, json!({ "sats": 2001 })).await;
+    assert_eq!(status, Status::Forbidden);
+}
@@ -0,0 +1,330 @@
+mod common;
+
+use common::mock_lichess::{Color, GameResult};
+use common::{spawn_app, TestApp, ADMIN};
+use lightningchess::models::{ChallengeStatus, Color as PieceColor, GameOutcome, MatchDetail, MatchSeries, TransactionSummary};
+use rocket::http::Status;
+use serde_json::{json, Value};
+
+const STAKE: i64 = 1000;
+const FEE: i64 = 20;
+
+// alice creates a match against bob, playing white in the first game, and bob accepts
+async fn create_and_accept(app: &TestApp, alice: &str, bob: &str, terms: Value) -> i32 {
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+    app.fund(ADMIN, 0).await;
+
+    let mut request = json!({ "opp_username": "bob", "sats": STAKE, "color": "white", "time_limit": 180, "increment": 2 });
+    request.as_object_mut().unwrap().extend(terms.as_object().unwrap().clone());
+    let (status, body) = app.post("/api/match", alice, request).await;
+    assert_eq!(status, Status::Ok);
+    let series: MatchSeries = serde_json::from_str(&body).unwrap();
+    assert_eq!(series.status, ChallengeStatus::WaitingForAcceptance);
+    assert_eq!(app.balance_of("alice").await, 10_000 - series.sats);
+
+    let (status, body) = app.post_empty(&format!("/api/match/{}/accept", series.id), bob).await;
+    assert_eq!(status, Status::Ok);
+    let series: MatchSeries = serde_json::from_str(&body).unwrap();
+    assert_eq!(series.status, ChallengeStatus::Accepted);
+    assert_eq!(app.balance_of("bob").await, 10_000 - series.opponent_stake());
+    series.id
+}
+
+async fn detail(app: &TestApp, token: &str, match_id: i32) -> MatchDetail {
+    let (status, body) = app.get(&format!("/api/match/{match_id}"), token).await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str(&body).unwrap()
+}
+
+// finishes the game being played and polls, returns the match afterwards
+async fn play(app: &TestApp, token: &str, match_id: i32, result: GameResult) -> MatchDetail {
+    let current = detail(app, token, match_id).await;
+    let game_id = current.games.last().unwrap().lichess_game_id.clone();
+    app.lichess.set_game_result(&game_id, result);
+    let (status, _) = app.get("/api/balance", token).await;
+    assert_eq!(status, Status::Ok);
+    detail(app, token, match_id).await
+}
+
+#[rocket::async_test]
+async fn accepting_a_match_starts_the_first_game() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let id = create_and_accept(&app, &alice, &bob, json!({ "format": "best of", "target": 3 })).await;
+
+    let created = app.lichess.challenges();
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0].challenger, "bob");
+    assert_eq!(created[0].dest_user, "alice");
+    assert_eq!(created[0].body["color"], "black");
+    assert_eq!(created[0].body["clock"]["limit"], "180");
+    assert_eq!(created[0].body["clock"]["increment"], "2");
+
+    let detail = detail(&app, &alice, id).await;
+    assert_eq!(detail.games.len(), 1);
+    assert_eq!(detail.games[0].game_number, 1);
+    assert_eq!(detail.games[0].creator_color, PieceColor::White);
+    assert_eq!(detail.games[0].lichess_game_id, created[0].id);
+    assert!(detail.games[0].outcome.is_none());
+
+    // unfinished games leave it alone
+    let (status, _) = app.get("/api/balance", &alice).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.lichess.challenges().len(), 1);
+}
+
+#[rocket::async_test]
+async fn unaccepted_game_is_forfeited_after_deadline() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let id = create_and_accept(&app, &alice, &bob, json!({ "format": "best of", "target": 1 })).await;
+    // bob's accept sent the first game's challenge to alice, who declines it
+    let game_id = app.lichess.challenges()[0].id.clone();
+    app.lichess.decline_challenge(&game_id);
+
+    // inside the deadline the game is waited for
+    app.get("/api/balance", &alice).await;
+    assert_eq!(detail(&app, &alice, id).await.series.status, ChallengeStatus::Accepted);
+
+    sqlx::query("UPDATE match_game SET created_at=now() - INTERVAL '16 minutes' WHERE match_id=$1")
+        .bind(id)
+        .execute(&app.pool).await.unwrap();
+    let (status, _) = app.get("/api/balance", &bob).await;
+    assert_eq!(status, Status::Ok);
+    let settled = detail(&app, &bob, id).await;
+    assert_eq!(settled.series.status, ChallengeStatus::Completed);
+    assert_eq!(settled.games[0].outcome, Some(GameOutcome::Opponent));
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE + 2 * STAKE - FEE);
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE);
+}
+
+#[rocket::async_test]
+async fn failed_export_is_not_a_no_show() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let id = create_and_accept(&app, &alice, &bob, json!({ "format": "best of", "target": 1 })).await;
+    let game_id = app.lichess.challenges()[0].id.clone();
+    app.lichess.set_game_result(&game_id, GameResult::Started);
+    sqlx::query("UPDATE match_game SET created_at=now() - INTERVAL '16 minutes' WHERE match_id=$1")
+        .bind(id)
+        .execute(&app.pool).await.unwrap();
+
+    // lichess can't be asked, so the game in progress is left alone
+    app.lichess.set_rate_limited(true);
+    app.get("/api/balance", &bob).await;
+    let waiting = detail(&app, &bob, id).await;
+    assert_eq!(waiting.series.status, ChallengeStatus::Accepted);
+    assert_eq!(waiting.games[0].outcome, None);
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE);
+
+    app.lichess.set_rate_limited(false);
+    app.lichess.set_game_result(&game_id, GameResult::Mate(Color::White));
+    app.get("/api/balance", &bob).await;
+    let settled = detail(&app, &bob, id).await;
+    assert_eq!(settled.games[0].outcome, Some(GameOutcome::Creator));
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE + 2 * STAKE - FEE);
+}
+
+#[rocket::async_test]
+async fn best_of_three_settles_once_clinched() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let id = create_and_accept(&app, &alice, &bob, json!({ "format": "best of", "target": 3 })).await;
+
+    let after_one = play(&app, &alice, id, GameResult::Mate(Color::White)).await;
+    assert_eq!(after_one.games[0].outcome, Some(GameOutcome::Creator));
+    assert_eq!(after_one.games.len(), 2);
+    // colours alternate, and the poller sends the next challenge
+    assert_eq!(after_one.games[1].creator_color, PieceColor::Black);
+    let created = app.lichess.challenges();
+    assert_eq!(created[1].challenger, "alice");
+    assert_eq!(created[1].body["color"], "black");
+
+    let after_two = play(&app, &alice, id, GameResult::Resign(Color::Black)).await;
+    assert_eq!(after_two.series.status, ChallengeStatus::Completed);
+    assert_eq!(after_two.games.len(), 2);
+    assert_eq!(after_two.creator_score, 2.0);
+    assert_eq!(after_two.opponent_score, 0.0);
+    assert_eq!(after_two.series.winner_user_id, Some(after_two.series.user_id));
+
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE + 2 * STAKE - FEE);
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE);
+    assert_eq!(app.balance_of(ADMIN).await, FEE);
+
+    // settled once
+    let (status, _) = app.get("/api/balance", &bob).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE + 2 * STAKE - FEE);
+}
+
+#[rocket::async_test]
+async fn either_player_can_move_the_match_on() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let id = create_and_accept(&app, &alice, &bob, json!({ "format": "best of", "target": 3 })).await;
+
+    let after_one = play(&app, &bob, id, GameResult::Mate(Color::Black)).await;
+    assert_eq!(after_one.games[0].outcome, Some(GameOutcome::Opponent));
+    let created = app.lichess.challenges();
+    assert_eq!(created[1].challenger, "bob");
+    assert_eq!(created[1].dest_user, "alice");
+    // alice is black in game 2, so bob asks for white
+    assert_eq!(created[1].body["color"], "white");
+
+    let after_two = play(&app, &bob, id, GameResult::Timeout(Color::White)).await;
+    assert_eq!(after_two.series.status, ChallengeStatus::Completed);
+    assert_eq!(after_two.series.winner_user_id, Some(after_two.series.opp_user_id));
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE + 2 * STAKE - FEE);
+}
+
+#[rocket::async_test]
+async fn level_best_of_match_splits_the_pot() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let id = create_and_accept(&app, &alice, &bob, json!({ "format": "best of", "target": 2 })).await;
+
+    play(&app, &alice, id, GameResult::Mate(Color::White)).await;
+    let done = play(&app, &alice, id, GameResult::Mate(Color::White)).await;
+    assert_eq!(done.series.status, ChallengeStatus::Completed);
+    assert_eq!(done.series.winner_user_id, None);
+    assert_eq!(done.creator_score, 1.0);
+    assert_eq!(done.opponent_score, 1.0);
+
+    assert_eq!(app.balance_of("alice").await, 10_000 - FEE / 2);
+    assert_eq!(app.balance_of("bob").await, 10_000 - FEE / 2);
+    assert_eq!(app.balance_of(ADMIN).await, FEE);
+
+    let (_, body) = app.get("/api/transactions/summary", &alice).await;
+    let summary: TransactionSummary = serde_json::from_str(&body).unwrap();
+    assert_eq!(summary.lost, 0);
+    assert_eq!(summary.fees, FEE / 2 * 1000);
+}
+
+#[rocket::async_test]
+async fn sudden_death_plays_until_a_decisive_game() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let id = create_and_accept(&app, &alice, &bob, json!({ "format": "best of", "target": 1, "tiebreak": "sudden death" })).await;
+
+    let after_draw = play(&app, &alice, id, GameResult::Draw).await;
+    assert_eq!(after_draw.series.status, ChallengeStatus::Accepted);
+    assert_eq!(after_draw.games.len(), 2);
+    let after_draw = play(&app, &alice, id, GameResult::Draw).await;
+    assert_eq!(after_draw.games.len(), 3);
+
+    // alice is white again in game 3
+    let done = play(&app, &alice, id, GameResult::Mate(Color::Black)).await;
+    assert_eq!(done.series.status, ChallengeStatus::Completed);
+    assert_eq!(done.series.winner_user_id, Some(done.series.opp_user_id));
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE + 2 * STAKE - FEE);
+}
+
+#[rocket::async_test]
+async fn armageddon_draw_goes_to_black() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let id = create_and_accept(&app, &alice, &bob, json!({ "format": "best of", "target": 1, "tiebreak": "armageddon" })).await;
+
+    let level = play(&app, &alice, id, GameResult::Draw).await;
+    assert_eq!(level.games.len(), 2);
+    assert!(level.games[1].armageddon);
+    assert_eq!(level.games[1].creator_color, PieceColor::Black);
+
+    let done = play(&app, &alice, id, GameResult::Draw).await;
+    assert_eq!(done.games[1].outcome, Some(GameOutcome::Creator));
+    assert_eq!(done.series.status, ChallengeStatus::Completed);
+    assert_eq!(done.series.winner_user_id, Some(done.series.user_id));
+    assert_eq!(app.balance_of("alice").await, 10_000 - STAKE + 2 * STAKE - FEE);
+}
+
+#[rocket::async_test]
+async fn first_to_ignores_draws_and_aborted_games() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let id = create_and_accept(&app, &alice, &bob, json!({ "format": "first to", "target": 2, "sats": 2000, "opponent_sats": 1000 })).await;
+
+    let aborted = play(&app, &alice, id, GameResult::Aborted).await;
+    assert_eq!(aborted.games[0].outcome, Some(GameOutcome::Aborted));
+    // replayed with the same colours
+    assert_eq!(aborted.games[1].creator_color, PieceColor::White);
+
+    play(&app, &alice, id, GameResult::Mate(Color::White)).await;
+    play(&app, &alice, id, GameResult::Draw).await;
+    let scores = play(&app, &alice, id, GameResult::Draw).await;
+    assert_eq!(scores.series.status, ChallengeStatus::Accepted);
+    assert_eq!(scores.creator_score, 1.0);
+    assert_eq!(scores.opponent_score, 0.0);
+
+    // alice is black in game 5
+    let done = play(&app, &alice, id, GameResult::Mate(Color::Black)).await;
+    assert_eq!(done.series.status, ChallengeStatus::Completed);
+    assert_eq!(done.games.len(), 5);
+    assert_eq!(done.creator_score, 2.0);
+    // 1% of a 3000 sat pot
+    assert_eq!(app.balance_of("alice").await, 10_000 - 2000 + 3000 - 30);
+    assert_eq!(app.balance_of("bob").await, 10_000 - 1000);
+}
+
+#[rocket::async_test]
+async fn only_the_opponent_can_accept_once() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let carol = app.login("carol").await;
+    app.fund("alice", 10_000).await;
+    app.fund("bob", 10_000).await;
+
+    let (_, body) = app.post("/api/match", &alice, json!({ "opp_username": "bob", "sats": STAKE, "target": 3 })).await;
+    let series: MatchSeries = serde_json::from_str(&body).unwrap();
+    let accept = format!("/api/match/{}/accept", series.id);
+
+    let (status, _) = app.post_empty(&accept, &alice).await;
+    assert_eq!(status, Status::BadRequest);
+    let (status, _) = app.post_empty(&accept, &carol).await;
+    assert_eq!(status, Status::BadRequest);
+    let (status, _) = app.get(&format!("/api/match/{}", series.id), &carol).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    let (status, _) = app.post_empty(&accept, &bob).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = app.post_empty(&accept, &bob).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(app.balance_of("bob").await, 10_000 - STAKE);
+
+    let (status, _) = app.post_empty("/api/match/9999/accept", &bob).await;
+    assert_eq!(status, Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn invalid_matches_are_rejected() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.login("bob").await;
+    app.fund("alice", 500).await;
+
+    for bad in [
+        json!({ "opp_username": "bob", "sats": STAKE, "target": 0 }),
+        json!({ "opp_username": "bob", "sats": STAKE, "target": 16 }),
+        json!({ "opp_username": "bob", "sats": 0, "target": 3 }),
+        json!({ "opp_username": "alice", "sats": 100, "target": 3 }),
+        json!({ "opp_username": "bob", "sats": 100, "target": 3, "time_limit": 100 }),
+        json!({ "opp_username": "bob", "sats": 100, "target": 3, "format": "most of" }),
+    ] {
+        let (status, _) = app.post("/api/match", &alice, bad).await;
+        assert_eq!(status, Status::BadRequest);
+    }
+
+    let (status, _) = app.post("/api/match", &alice, json!({ "opp_username": "bob", "sats": STAKE, "target": 3 })).await;
+    assert_eq!(status, Status::PaymentRequired);
+    assert_eq!(app.balance_of("alice").await, 500);
+}
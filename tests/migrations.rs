@@ -0,0 +1,137 @@
+mod common;
+
+use common::test_db;
+use lightningchess::db::MIGRATOR;
+use lightningchess::models::{ChallengeStatus, TransactionState};
+use rocket::error::ErrorKind;
+use rocket::local::asynchronous::Client;
+use rocket::{Build, Rocket};
+
+fn figment(run_migrations: bool) -> rocket::figment::Figment {
+    rocket::Config::figment()
+        .merge(("run_migrations", run_migrations))
+        .merge(("log_level", "off"))
+}
+
+async fn assert_ignite_fails(rocket: Rocket<Build>) {
+    match Client::tracked(rocket).await {
+        Ok(_) => panic!("ignite should have failed"),
+        // inspecting the kind marks the error as handled
+        Err(e) => assert!(matches!(e.kind(), ErrorKind::FailedFairings(_)))
+    }
+}
+
+#[rocket::async_test]
+async fn ignite_applies_migrations() {
+    let db = match test_db().await { Some(db) => db, None => return };
+
+    let rocket = lightningchess::rocket(db.pool.clone()).configure(figment(true));
+    assert!(Client::tracked(rocket).await.is_ok());
+
+    let applied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM _sqlx_migrations WHERE success")
+        .fetch_one(&db.pool).await.unwrap();
+    assert_eq!(applied as usize, MIGRATOR.iter().count());
+}
+
+#[rocket::async_test]
+async fn ignite_fails_on_unmigrated_schema_when_disabled() {
+    let db = match test_db().await { Some(db) => db, None => return };
+
+    let rocket = lightningchess::rocket(db.pool.clone()).configure(figment(false));
+    assert_ignite_fails(rocket).await;
+
+    MIGRATOR.run(&db.pool).await.unwrap();
+    let rocket = lightningchess::rocket(db.pool.clone()).configure(figment(false));
+    assert!(Client::tracked(rocket).await.is_ok());
+}
+
+#[rocket::async_test]
+async fn ignite_fails_on_modified_migration_when_disabled() {
+    let db = match test_db().await { Some(db) => db, None => return };
+
+    MIGRATOR.run(&db.pool).await.unwrap();
+    sqlx::query("UPDATE _sqlx_migrations SET checksum='\\x00'")
+        .execute(&db.pool).await.unwrap();
+
+    let rocket = lightningchess::rocket(db.pool.clone()).configure(figment(false));
+    assert_ignite_fails(rocket).await;
+}
+
+#[rocket::async_test]
+async fn status_transitions_match_database() {
+    let db = match test_db().await { Some(db) => db, None => return };
+    MIGRATOR.run(&db.pool).await.unwrap();
+
+    let statuses = [ChallengeStatus::WaitingForAcceptance, ChallengeStatus::Accepted, ChallengeStatus::Completed];
+    for from in statuses {
+        for to in statuses {
+            let allowed: bool = sqlx::query_scalar("SELECT challenge_status_transition($1, $2)")
+                .bind(from)
+                .bind(to)
+                .fetch_one(&db.pool).await.unwrap();
+            assert_eq!(allowed, from.can_become(to), "{from:?} -> {to:?}");
+        }
+    }
+
+    let states = [TransactionState::Open, TransactionState::Accepted, TransactionState::Settled, TransactionState::Canceled, TransactionState::Failed];
+    for from in states {
+        for to in states {
+            let allowed: bool = sqlx::query_scalar("SELECT transaction_state_transition($1, $2)")
+                .bind(from)
+                .bind(to)
+                .fetch_one(&db.pool).await.unwrap();
+            assert_eq!(allowed, from.can_become(to), "{from:?} -> {to:?}");
+        }
+    }
+}
+
+#[rocket::async_test]
+async fn invalid_status_transition_is_rejected_by_database() {
+    let db = match test_db().await { Some(db) => db, None => return };
+    MIGRATOR.run(&db.pool).await.unwrap();
+    let alice = lightningchess::db::ensure_user(&db.pool, "alice").await.unwrap();
+    let bob = lightningchess::db::ensure_user(&db.pool, "bob").await.unwrap();
+
+    let id: i32 = sqlx::query_scalar("INSERT INTO challenge (username, opp_username, sats, opponent_sats, user_id, opp_user_id, fee_msat) VALUES ('alice', 'bob', 100, 100, $1, $2, 2000) RETURNING id")
+        .bind(alice)
+        .bind(bob)
+        .fetch_one(&db.pool).await.unwrap();
+    let skipped = sqlx::query("UPDATE challenge SET status=$1 WHERE id=$2")
+        .bind(ChallengeStatus::Completed)
+        .bind(id)
+        .execute(&db.pool).await;
+    assert!(skipped.is_err());
+    let unknown = sqlx::query("UPDATE challenge SET status='DONE' WHERE id=$1")
+        .bind(id)
+        .execute(&db.pool).await;
+    assert!(unknown.is_err());
+
+    let id: i32 = sqlx::query_scalar("INSERT INTO lightningchess_transaction (username, ttype, detail, amount_msat, state, user_id) VALUES ('alice', 'withdrawal', '', -100000, 'SETTLED', $1) RETURNING transaction_id")
+        .bind(alice)
+        .fetch_one(&db.pool).await.unwrap();
+    let reopened = sqlx::query("UPDATE lightningchess_transaction SET state=$1 WHERE transaction_id=$2")
+        .bind(TransactionState::Open)
+        .bind(id)
+        .execute(&db.pool).await;
+    assert!(reopened.is_err());
+}
+
+#[rocket::async_test]
+async fn balances_cannot_go_negative() {
+    let db = match test_db().await { Some(db) => db, None => return };
+    MIGRATOR.run(&db.pool).await.unwrap();
+    let alice = lightningchess::db::ensure_user(&db.pool, "alice").await.unwrap();
+    sqlx::query("UPDATE lightningchess_balance SET balance_msat=100000 WHERE user_id=$1")
+        .bind(alice)
+        .execute(&db.pool).await.unwrap();
+
+    let overdraft = sqlx::query("UPDATE lightningchess_balance SET balance_msat=balance_msat - 100001 WHERE user_id=$1")
+        .bind(alice)
+        .execute(&db.pool).await;
+    assert!(lightningchess::db::is_insufficient_funds(&overdraft.unwrap_err()));
+
+    let unknown_user = sqlx::query("INSERT INTO lightningchess_balance (username, balance, user_id) VALUES ('mallory', 100, $1)")
+        .bind(alice + 1000)
+        .execute(&db.pool).await;
+    assert!(unknown_user.is_err());
+}
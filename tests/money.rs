@@ -0,0 +1,285 @@
+mod common;
+
+use common::spawn_app;
+use lightningchess::models::{Balance, Transaction, TransactionPage, TransactionState, TransactionSummary, TransactionType};
+use rocket::http::Status;
+use serde_json::json;
+
+#[rocket::async_test]
+async fn deposit_is_credited_once_invoice_is_paid() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+
+    let (status, body) = app.post("/api/invoice", &alice, json!({ "sats": 5000 })).await;
+    assert_eq!(status, Status::Ok);
+    let invoice: Transaction = serde_json::from_str(&body).unwrap();
+    assert_eq!(invoice.state, TransactionState::Open);
+    assert_eq!(invoice.amount_msat, 0);
+    assert_eq!(app.lnd.invoices()[0].value_msat, 5_000_000);
+
+    let (_, body) = app.get("/api/balance", &alice).await;
+    let balance: Balance = serde_json::from_str(&body).unwrap();
+    assert_eq!(balance.balance_msat, 0);
+
+    app.lnd.pay_invoice(invoice.payment_request.as_ref().unwrap());
+    let (_, body) = app.get("/api/balance", &alice).await;
+    let balance: Balance = serde_json::from_str(&body).unwrap();
+    assert_eq!(balance.balance_msat, 5_000_000);
+
+    // a settled invoice is only credited once
+    let (_, body) = app.get("/api/balance", &alice).await;
+    let balance: Balance = serde_json::from_str(&body).unwrap();
+    assert_eq!(balance.balance_msat, 5_000_000);
+
+    let (status, body) = app.post_empty(&format!("/api/transaction/{}", invoice.transaction_id), &alice).await;
+    assert_eq!(status, Status::Ok);
+    let invoice: Transaction = serde_json::from_str(&body).unwrap();
+    assert_eq!(invoice.state, TransactionState::Settled);
+    assert_eq!(invoice.amount_msat, 5_000_000);
+}
+
+#[rocket::async_test]
+async fn deposit_can_be_in_msat() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+
+    let (status, body) = app.post("/api/invoice", &alice, json!({ "msat": 1500 })).await;
+    assert_eq!(status, Status::Ok);
+    let invoice: Transaction = serde_json::from_str(&body).unwrap();
+    assert_eq!(app.lnd.invoices()[0].value_msat, 1500);
+
+    app.lnd.pay_invoice(invoice.payment_request.as_ref().unwrap());
+    let (_, body) = app.get("/api/balance", &alice).await;
+    let balance: Balance = serde_json::from_str(&body).unwrap();
+    assert_eq!(balance.balance_msat, 1500);
+
+    for bad in [json!({ "sats": 1, "msat": 1000 }), json!({}), json!({ "msat": 0 })] {
+        let (status, _) = app.post("/api/invoice", &alice, bad.clone()).await;
+        assert_eq!(status, Status::BadRequest, "{bad}");
+    }
+}
+
+#[rocket::async_test]
+async fn canceled_invoice_is_not_credited() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+
+    let (_, body) = app.post("/api/invoice", &alice, json!({ "sats": 5000 })).await;
+    let invoice: Transaction = serde_json::from_str(&body).unwrap();
+    app.lnd.cancel_invoice(invoice.payment_request.as_ref().unwrap());
+
+    let (_, body) = app.get("/api/balance", &alice).await;
+    let balance: Balance = serde_json::from_str(&body).unwrap();
+    assert_eq!(balance.balance_msat, 0);
+}
+
+#[rocket::async_test]
+async fn withdrawal_pays_invoice_and_debits_balance() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let out = app.lnd.add_payable(4000, false);
+
+    let (status, body) = app.post("/api/send-payment", &alice, json!({ "payment_request": out })).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body, r#"{"complete":true}"#);
+    assert_eq!(app.lnd.payments(), vec![out]);
+    assert_eq!(app.balance_of("alice").await, 6000);
+
+    let (_, body) = app.get("/api/transactions", &alice).await;
+    let transactions = serde_json::from_str::<TransactionPage>(&body).unwrap().transactions;
+    assert_eq!(transactions[0].ttype, TransactionType::Withdrawal);
+    assert_eq!(transactions[0].state, TransactionState::Settled);
+    assert_eq!(transactions[0].amount_msat, -4_000_000);
+}
+
+#[rocket::async_test]
+async fn failed_withdrawal_leaves_balance_untouched() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let out = app.lnd.add_payable(4000, true);
+
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "payment_request": out })).await;
+    assert_eq!(status, Status::InternalServerError);
+    assert_eq!(app.lnd.payments().len(), 1);
+    assert_eq!(app.balance_of("alice").await, 10_000);
+
+    let (_, body) = app.get("/api/transactions", &alice).await;
+    let transactions = serde_json::from_str::<TransactionPage>(&body).unwrap().transactions;
+    assert_eq!(transactions[0].state, TransactionState::Failed);
+}
+
+#[rocket::async_test]
+async fn withdrawal_above_balance_is_rejected() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 1000).await;
+    let out = app.lnd.add_payable(4000, false);
+
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "payment_request": out })).await;
+    assert_eq!(status, Status::PaymentRequired);
+    assert!(app.lnd.payments().is_empty());
+    assert_eq!(app.balance_of("alice").await, 1000);
+
+    let (_, body) = app.get("/api/transactions", &alice).await;
+    let transactions = serde_json::from_str::<TransactionPage>(&body).unwrap().transactions;
+    assert!(transactions.is_empty());
+}
+
+#[rocket::async_test]
+async fn undecodable_payment_request_is_rejected() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+
+    let (status, _) = app.post("/api/send-payment", &alice, json!({ "payment_request": "garbage" })).await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(app.lnd.payments().is_empty());
+}
+
+#[rocket::async_test]
+async fn transactions_can_only_be_looked_up_by_owner() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+
+    let (_, body) = app.post("/api/invoice", &alice, json!({ "sats": 5000 })).await;
+    let invoice: Transaction = serde_json::from_str(&body).unwrap();
+    let path = format!("/api/transaction/{}", invoice.transaction_id);
+
+    let (status, _) = app.post_empty(&path, &alice).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = app.post_empty(&path, &bob).await;
+    assert_eq!(status, Status::Unauthorized);
+    let (status, _) = app.post_empty("/api/transaction/abc", &alice).await;
+    assert_eq!(status, Status::BadRequest);
+
+    let (_, body) = app.get("/api/transactions", &bob).await;
+    let transactions = serde_json::from_str::<TransactionPage>(&body).unwrap().transactions;
+    assert!(transactions.is_empty());
+}
+
+#[rocket::async_test]
+async fn unknown_token_is_sent_to_login() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+
+    let (status, _) = app.get("/api/balance", "not-a-token").await;
+    assert_eq!(status, Status::SeeOther);
+}
+
+#[rocket::async_test]
+async fn transactions_have_rfc3339_timestamps() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+
+    let (_, body) = app.post("/api/invoice", &alice, json!({ "sats": 5000 })).await;
+    let invoice: Transaction = serde_json::from_str(&body).unwrap();
+    assert_eq!(invoice.created_at, invoice.updated_at);
+
+    app.lnd.pay_invoice(invoice.payment_request.as_ref().unwrap());
+    app.get("/api/balance", &alice).await;
+
+    let (_, body) = app.get("/api/transactions", &alice).await;
+    let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let transactions = page["transactions"].as_array().unwrap();
+    let created_at = chrono::DateTime::parse_from_rfc3339(transactions[0]["created_at"].as_str().unwrap()).unwrap();
+    let updated_at = chrono::DateTime::parse_from_rfc3339(transactions[0]["updated_at"].as_str().unwrap()).unwrap();
+    assert_eq!(created_at, invoice.created_at);
+    // settling the deposit touched the row
+    assert!(updated_at > created_at);
+}
+
+async fn page(app: &common::TestApp, token: &str, query: &str) -> TransactionPage {
+    let (status, body) = app.get(&format!("/api/transactions?{query}"), token).await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[rocket::async_test]
+async fn transactions_are_paged_by_cursor() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    for _ in 0..5 {
+        app.post("/api/invoice", &alice, json!({ "sats": 100 })).await;
+    }
+
+    let mut seen = vec![];
+    let mut query = "limit=2".to_string();
+    loop {
+        let p = page(&app, &alice, &query).await;
+        assert!(p.transactions.len() <= 2);
+        seen.extend(p.transactions.iter().map(|t| t.transaction_id));
+        match p.next_cursor {
+            Some(cursor) => query = format!("limit=2&cursor={cursor}"),
+            None => break
+        }
+    }
+    assert_eq!(seen.len(), 5);
+    assert!(seen.windows(2).all(|w| w[0] > w[1]), "newest first without repeats: {seen:?}");
+}
+
+#[rocket::async_test]
+async fn transactions_can_be_filtered() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 10_000).await;
+    let (_, body) = app.post("/api/invoice", &alice, json!({ "sats": 100 })).await;
+    let paid: Transaction = serde_json::from_str(&body).unwrap();
+    app.post("/api/invoice", &alice, json!({ "sats": 200 })).await;
+    app.lnd.pay_invoice(paid.payment_request.as_ref().unwrap());
+    app.get("/api/balance", &alice).await;
+    let out = app.lnd.add_payable(1000, false);
+    app.post("/api/send-payment", &alice, json!({ "payment_request": out })).await;
+
+    let invoices = page(&app, &alice, "ttype=invoice").await.transactions;
+    assert_eq!(invoices.len(), 2);
+    let settled = page(&app, &alice, "ttype=invoice&state=SETTLED").await.transactions;
+    assert_eq!(settled.len(), 1);
+    assert_eq!(settled[0].transaction_id, paid.transaction_id);
+    let withdrawals = page(&app, &alice, "ttype=withdrawal").await.transactions;
+    assert_eq!(withdrawals.len(), 1);
+
+    let future = page(&app, &alice, "from=2100-01-01T00:00:00Z").await.transactions;
+    assert!(future.is_empty());
+    let past = page(&app, &alice, "to=2100-01-01T00:00:00%2B01:00").await.transactions;
+    assert_eq!(past.len(), 3);
+    let linked = page(&app, &alice, "challenge_id=1").await.transactions;
+    assert!(linked.is_empty());
+
+    for bad in ["ttype=bogus", "state=open", "from=yesterday", "cursor=x", "limit=0"] {
+        let (status, _) = app.get(&format!("/api/transactions?{bad}"), &alice).await;
+        assert_eq!(status, Status::BadRequest, "{bad}");
+    }
+}
+
+#[rocket::async_test]
+async fn summary_totals_deposits_and_withdrawals() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let (_, body) = app.post("/api/invoice", &alice, json!({ "sats": 5000 })).await;
+    let invoice: Transaction = serde_json::from_str(&body).unwrap();
+    app.post("/api/invoice", &alice, json!({ "sats": 700 })).await;
+    app.lnd.pay_invoice(invoice.payment_request.as_ref().unwrap());
+    app.get("/api/balance", &alice).await;
+    let out = app.lnd.add_payable(1500, false);
+    let fail = app.lnd.add_payable(500, true);
+    app.post("/api/send-payment", &alice, json!({ "payment_request": out })).await;
+    app.post("/api/send-payment", &alice, json!({ "payment_request": fail })).await;
+
+    let (status, body) = app.get("/api/transactions/summary", &alice).await;
+    assert_eq!(status, Status::Ok);
+    let summary: TransactionSummary = serde_json::from_str(&body).unwrap();
+    assert_eq!(summary.deposited, 5_000_000);
+    assert_eq!(summary.withdrawn, 1_500_000);
+    assert_eq!(summary.won, 0);
+    assert_eq!(summary.lost, 0);
+    assert_eq!(summary.fees, 0);
+
+    let (_, body) = app.get("/api/transactions/summary?from=2100-01-01T00:00:00Z", &alice).await;
+    let summary: TransactionSummary = serde_json::from_str(&body).unwrap();
+    assert_eq!(summary.deposited, 0);
+
+    let (status, _) = app.get("/api/transactions/summary?to=soon", &alice).await;
+    assert_eq!(status, Status::BadRequest);
+}
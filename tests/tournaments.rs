@@ -0,0 +1,449 @@
+mod common;
+
+use common::mock_lichess::{Color, GameResult};
+use common::{spawn_app, TestApp, ADMIN};
+use lightningchess::models::{PairingResult, Tournament, TournamentDetail, TournamentEntry, TournamentStatus, TransactionSummary};
+use rocket::http::Status;
+use serde_json::{json, Value};
+
+const BUY_IN: i64 = 1000;
+
+fn in_an_hour() -> String {
+    (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339()
+}
+
+async fn create(app: &TestApp, token: &str, terms: Value) -> Tournament {
+    let mut request = json!({ "name": "Friday blitz", "buy_in": BUY_IN, "min_players": 2, "rounds": 1, "time_limit": 180, "increment": 0, "starts_at": in_an_hour() });
+    request.as_object_mut().unwrap().extend(terms.as_object().unwrap().clone());
+    let (status, body) = app.post("/api/tournament", token, request).await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str(&body).unwrap()
+}
+
+// funds and logs in each player, who then join in order
+async fn join_all(app: &TestApp, tournament_id: i32, players: &[&str]) -> Vec<String> {
+    app.fund(ADMIN, 0).await;
+    let mut tokens = Vec::new();
+    for player in players {
+        let token = app.login(player).await;
+        app.fund(player, 10_000).await;
+        let (status, _) = app.post_empty(&format!("/api/tournament/{tournament_id}/join"), &token).await;
+        assert_eq!(status, Status::Ok);
+        tokens.push(token);
+    }
+    tokens
+}
+
+// entries close at starts_at, so move it into the past rather than wait
+async fn start(app: &TestApp, tournament_id: i32, token: &str) -> TournamentDetail {
+    sqlx::query("UPDATE tournament SET starts_at=now() - INTERVAL '1 minute' WHERE id=$1")
+        .bind(tournament_id)
+        .execute(&app.pool).await.unwrap();
+    poll(app, tournament_id, token).await
+}
+
+async fn poll(app: &TestApp, tournament_id: i32, token: &str) -> TournamentDetail {
+    let (status, _) = app.get("/api/balance", token).await;
+    assert_eq!(status, Status::Ok);
+    lookup(app, tournament_id).await
+}
+
+async fn lookup(app: &TestApp, tournament_id: i32) -> TournamentDetail {
+    let (status, body) = app.get(&format!("/api/tournament/{tournament_id}"), "").await;
+    assert_eq!(status, Status::Ok);
+    serde_json::from_str(&body).unwrap()
+}
+
+fn game_between(detail: &TournamentDetail, round: i32, white: &str) -> String {
+    detail.pairings.iter()
+        .find(|p| p.round == round && p.white_username == white)
+        .and_then(|p| p.lichess_game_id.clone())
+        .unwrap()
+}
+
+fn entry<'a>(detail: &'a TournamentDetail, username: &str) -> &'a TournamentEntry {
+    detail.standings.iter().find(|e| e.username == username).unwrap()
+}
+
+#[rocket::async_test]
+async fn unfilled_tournament_is_refunded() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    let tournament = create(&app, &organiser, json!({ "min_players": 3 })).await;
+    let tokens = join_all(&app, tournament.id, &["alice", "bob"]).await;
+    assert_eq!(app.balance_of("alice").await, 10_000 - BUY_IN);
+
+    let detail = start(&app, tournament.id, &tokens[0]).await;
+    assert_eq!(detail.tournament.status, TournamentStatus::Canceled);
+    assert!(detail.pairings.is_empty());
+    assert_eq!(app.balance_of("alice").await, 10_000);
+    assert_eq!(app.balance_of("bob").await, 10_000);
+
+    // refunded once
+    poll(&app, tournament.id, &tokens[1]).await;
+    assert_eq!(app.balance_of("bob").await, 10_000);
+}
+
+#[rocket::async_test]
+async fn swiss_pays_out_by_place() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    let tournament = create(&app, &organiser, json!({ "min_players": 4, "rounds": 2, "prizes": [70, 30] })).await;
+    let tokens = join_all(&app, tournament.id, &["alice", "bob", "carol", "dave"]).await;
+
+    // round 1 pairs by entry order
+    let detail = start(&app, tournament.id, &tokens[0]).await;
+    assert_eq!(detail.tournament.status, TournamentStatus::Running);
+    assert_eq!(detail.tournament.current_round, 1);
+    assert_eq!(detail.pairings.len(), 2);
+    assert_eq!(detail.pairings[0].white_username, "alice");
+    assert_eq!(detail.pairings[0].black_username.as_deref(), Some("bob"));
+    assert_eq!(detail.pairings[1].white_username, "carol");
+    assert_eq!(detail.pairings[1].black_username.as_deref(), Some("dave"));
+
+    // each player sends their own game
+    poll(&app, tournament.id, &tokens[3]).await;
+    let created = app.lichess.challenges();
+    assert_eq!(created.len(), 2);
+    assert_eq!(created[0].challenger, "alice");
+    assert_eq!(created[0].dest_user, "bob");
+    assert_eq!(created[0].body["color"], "white");
+    assert_eq!(created[1].challenger, "dave");
+    assert_eq!(created[1].body["color"], "black");
+
+    let detail = lookup(&app, tournament.id).await;
+    app.lichess.set_game_result(&game_between(&detail, 1, "alice"), GameResult::Mate(Color::White));
+    app.lichess.set_game_result(&game_between(&detail, 1, "carol"), GameResult::Resign(Color::White));
+    let detail = poll(&app, tournament.id, &tokens[1]).await;
+    assert_eq!(detail.tournament.current_round, 2);
+    assert_eq!(detail.pairings[0].result, Some(PairingResult::White));
+
+    // winners meet, and colours swap for players who had white
+    let round_two: Vec<_> = detail.pairings.iter().filter(|p| p.round == 2).collect();
+    assert_eq!(round_two[0].white_username, "carol");
+    assert_eq!(round_two[0].black_username.as_deref(), Some("alice"));
+    assert_eq!(round_two[1].white_username, "dave");
+    assert_eq!(round_two[1].black_username.as_deref(), Some("bob"));
+
+    poll(&app, tournament.id, &tokens[0]).await;
+    poll(&app, tournament.id, &tokens[1]).await;
+    let detail = lookup(&app, tournament.id).await;
+    app.lichess.set_game_result(&game_between(&detail, 2, "carol"), GameResult::Draw);
+    app.lichess.set_game_result(&game_between(&detail, 2, "dave"), GameResult::Mate(Color::Black));
+    let detail = poll(&app, tournament.id, &tokens[2]).await;
+    assert_eq!(detail.tournament.status, TournamentStatus::Completed);
+
+    // alice and carol tie for first and share both prizes
+    let net = 4 * BUY_IN - 40;
+    assert_eq!(entry(&detail, "alice").place, Some(1));
+    assert_eq!(entry(&detail, "carol").place, Some(1));
+    assert_eq!(entry(&detail, "alice").prize_msat, Some(net / 2 * 1000));
+    assert_eq!(entry(&detail, "bob").place, Some(3));
+    assert_eq!(entry(&detail, "bob").prize_msat, Some(0));
+    assert_eq!(entry(&detail, "dave").place, Some(4));
+    assert_eq!(app.balance_of("alice").await, 10_000 - BUY_IN + net / 2);
+    assert_eq!(app.balance_of("carol").await, 10_000 - BUY_IN + net / 2);
+    assert_eq!(app.balance_of("bob").await, 10_000 - BUY_IN);
+    assert_eq!(app.balance_of(ADMIN).await, 40);
+
+    let (_, body) = app.get("/api/transactions/summary", &tokens[1]).await;
+    let summary: TransactionSummary = serde_json::from_str(&body).unwrap();
+    assert_eq!(summary.lost, BUY_IN * 1000);
+    let (_, body) = app.get("/api/transactions/summary", &tokens[0]).await;
+    let summary: TransactionSummary = serde_json::from_str(&body).unwrap();
+    assert_eq!(summary.won, (net / 2 - BUY_IN) * 1000);
+    assert_eq!(summary.fees, 20 * 1000);
+}
+
+#[rocket::async_test]
+async fn summary_covers_a_settled_tournament() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    let tournament = create(&app, &organiser, json!({ "prizes": [90, 10] })).await;
+    let tokens = join_all(&app, tournament.id, &["alice", "bob"]).await;
+    let detail = start(&app, tournament.id, &tokens[0]).await;
+    poll(&app, tournament.id, &tokens[1]).await;
+    app.lichess.set_game_result(&game_between(&detail, 1, "alice"), GameResult::Mate(Color::White));
+    let detail = poll(&app, tournament.id, &tokens[0]).await;
+    assert_eq!(detail.tournament.status, TournamentStatus::Completed);
+
+    // a 20 sat fee leaves 1980 sats, 1782 for alice and 198 for bob
+    let (_, body) = app.get("/api/transactions/summary", &tokens[0]).await;
+    let alice: TransactionSummary = serde_json::from_str(&body).unwrap();
+    assert_eq!(alice.won, (1782 - BUY_IN) * 1000);
+    assert_eq!(alice.lost, 0);
+    assert_eq!(alice.fees, 18 * 1000);
+    // a prize below the buy-in is the rest of the buy-in lost
+    let (_, body) = app.get("/api/transactions/summary", &tokens[1]).await;
+    let bob: TransactionSummary = serde_json::from_str(&body).unwrap();
+    assert_eq!(bob.won, 0);
+    assert_eq!(bob.lost, (BUY_IN - 198) * 1000);
+    assert_eq!(bob.fees, 2 * 1000);
+}
+
+#[rocket::async_test]
+async fn odd_player_out_gets_a_bye() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    let tournament = create(&app, &organiser, json!({ "min_players": 3, "prizes": [60, 40] })).await;
+    let tokens = join_all(&app, tournament.id, &["alice", "bob", "carol"]).await;
+
+    let detail = start(&app, tournament.id, &tokens[0]).await;
+    let bye = detail.pairings.iter().find(|p| p.black_user_id.is_none()).unwrap();
+    assert_eq!(bye.white_username, "carol");
+    assert_eq!(bye.result, Some(PairingResult::Bye));
+    assert_eq!(entry(&detail, "carol").half_points, 2);
+
+    let game = game_between(&detail, 1, "alice");
+    app.lichess.set_game_result(&game, GameResult::Timeout(Color::Black));
+    let detail = poll(&app, tournament.id, &tokens[0]).await;
+    assert_eq!(detail.tournament.status, TournamentStatus::Completed);
+    // bob and carol share 1st and 2nd, the odd sat goes to the house
+    let net = 3 * BUY_IN - 30;
+    assert_eq!(entry(&detail, "bob").prize_msat, Some(net / 2 * 1000));
+    assert_eq!(entry(&detail, "carol").prize_msat, Some(net / 2 * 1000));
+    assert_eq!(entry(&detail, "alice").prize_msat, Some(0));
+    assert_eq!(app.balance_of(ADMIN).await, 3 * BUY_IN - 2 * (net / 2));
+}
+
+#[rocket::async_test]
+async fn no_shows_forfeit_after_deadline() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    let tournament = create(&app, &organiser, json!({ "min_players": 4, "prizes": [100] })).await;
+    let tokens = join_all(&app, tournament.id, &["alice", "bob", "carol", "dave"]).await;
+
+    // alice sends bob his game and he declines it, carol and dave never turn up
+    let detail = start(&app, tournament.id, &tokens[0]).await;
+    app.lichess.decline_challenge(&game_between(&detail, 1, "alice"));
+    let detail = poll(&app, tournament.id, &tokens[0]).await;
+    assert_eq!(detail.tournament.status, TournamentStatus::Running);
+    assert!(detail.pairings.iter().all(|p| p.result.is_none()));
+
+    sqlx::query("UPDATE tournament_pairing SET created_at=now() - INTERVAL '16 minutes', waiting_since=now() - INTERVAL '16 minutes' WHERE tournament_id=$1")
+        .bind(tournament.id)
+        .execute(&app.pool).await.unwrap();
+    let detail = poll(&app, tournament.id, &tokens[0]).await;
+    assert_eq!(detail.pairings[0].result, Some(PairingResult::White));
+    assert_eq!(detail.pairings[1].result, Some(PairingResult::Forfeit));
+    assert_eq!(entry(&detail, "carol").half_points, 0);
+    assert_eq!(entry(&detail, "dave").half_points, 0);
+    assert_eq!(detail.tournament.status, TournamentStatus::Completed);
+    assert_eq!(app.balance_of("alice").await, 10_000 - BUY_IN + 4 * BUY_IN - 40);
+}
+
+#[rocket::async_test]
+async fn lichess_failures_hold_up_only_their_pairing() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    let tournament = create(&app, &organiser, json!({})).await;
+    let tokens = join_all(&app, tournament.id, &["alice", "bob"]).await;
+
+    // the round still starts, the pairing remembers its challenge wasn't sent
+    app.lichess.set_rate_limited(true);
+    let detail = start(&app, tournament.id, &tokens[0]).await;
+    assert_eq!(detail.tournament.status, TournamentStatus::Running);
+    assert_eq!(detail.pairings[0].lichess_game_id, None);
+    assert!(detail.pairings[0].send_failed_at.is_some());
+
+    app.lichess.set_rate_limited(false);
+    let detail = poll(&app, tournament.id, &tokens[0]).await;
+    let game_id = game_between(&detail, 1, "alice");
+    assert_eq!(detail.pairings[0].send_failed_at, None);
+
+    // a game lichess can't be asked about isn't a no-show, however late it is
+    app.lichess.set_game_result(&game_id, GameResult::Started);
+    sqlx::query("UPDATE tournament_pairing SET waiting_since=now() - INTERVAL '16 minutes' WHERE tournament_id=$1")
+        .bind(tournament.id)
+        .execute(&app.pool).await.unwrap();
+    app.lichess.set_rate_limited(true);
+    let detail = poll(&app, tournament.id, &tokens[1]).await;
+    assert_eq!(detail.pairings[0].result, None);
+    assert_eq!(detail.tournament.status, TournamentStatus::Running);
+
+    app.lichess.set_rate_limited(false);
+    app.lichess.set_game_result(&game_id, GameResult::Mate(Color::Black));
+    let detail = poll(&app, tournament.id, &tokens[1]).await;
+    assert_eq!(detail.pairings[0].result, Some(PairingResult::Black));
+    assert_eq!(detail.tournament.status, TournamentStatus::Completed);
+}
+
+#[rocket::async_test]
+async fn aborted_games_are_sent_again() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    let tournament = create(&app, &organiser, json!({})).await;
+    let tokens = join_all(&app, tournament.id, &["alice", "bob"]).await;
+
+    let detail = start(&app, tournament.id, &tokens[0]).await;
+    let first = game_between(&detail, 1, "alice");
+    app.lichess.set_game_result(&first, GameResult::Aborted);
+    let detail = poll(&app, tournament.id, &tokens[1]).await;
+    assert_eq!(detail.tournament.status, TournamentStatus::Running);
+    assert_eq!(detail.pairings[0].result, None);
+
+    // bob's poll cleared it, his next one sends it again
+    let detail = poll(&app, tournament.id, &tokens[1]).await;
+    let second = game_between(&detail, 1, "alice");
+    assert_ne!(first, second);
+    assert_eq!(app.lichess.challenges().last().unwrap().challenger, "bob");
+}
+
+#[rocket::async_test]
+async fn entries_are_checked() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    let tournament = create(&app, &organiser, json!({ "max_players": 2 })).await;
+    let join = format!("/api/tournament/{}/join", tournament.id);
+    let leave = format!("/api/tournament/{}/leave", tournament.id);
+    let tokens = join_all(&app, tournament.id, &["alice"]).await;
+
+    let (status, _) = app.post_empty(&join, &tokens[0]).await;
+    assert_eq!(status, Status::Conflict);
+
+    let poor = app.login("poor").await;
+    app.fund("poor", BUY_IN - 1).await;
+    let (status, _) = app.post_empty(&join, &poor).await;
+    assert_eq!(status, Status::PaymentRequired);
+
+    join_all(&app, tournament.id, &["bob"]).await;
+    let carol = app.login("carol").await;
+    app.fund("carol", 10_000).await;
+    let (status, _) = app.post_empty(&join, &carol).await;
+    assert_eq!(status, Status::Conflict);
+
+    let (status, _) = app.post_empty(&leave, &tokens[0]).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.balance_of("alice").await, 10_000);
+    let (status, _) = app.post_empty(&leave, &tokens[0]).await;
+    assert_eq!(status, Status::NotFound);
+
+    let (status, _) = app.post_empty(&join, &carol).await;
+    assert_eq!(status, Status::Ok);
+
+    sqlx::query("UPDATE tournament SET starts_at=now() - INTERVAL '1 minute' WHERE id=$1")
+        .bind(tournament.id)
+        .execute(&app.pool).await.unwrap();
+    let (status, _) = app.post_empty(&join, &tokens[0]).await;
+    assert_eq!(status, Status::BadRequest);
+    let (status, _) = app.post_empty(&leave, &carol).await;
+    assert_eq!(status, Status::BadRequest);
+    let (status, _) = app.post_empty("/api/tournament/9999/join", &carol).await;
+    assert_eq!(status, Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn invalid_tournaments_are_rejected() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    let past = (chrono::Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+
+    for bad in [
+        json!({ "prizes": [60, 30] }),
+        json!({ "prizes": [50, 30, 20] }),
+        json!({ "prizes": [110, -10] }),
+        json!({ "min_players": 1 }),
+        json!({ "max_players": 1 }),
+        json!({ "rounds": 0 }),
+        json!({ "buy_in": 0 }),
+        json!({ "name": " " }),
+        json!({ "time_limit": 100 }),
+        json!({ "starts_at": past }),
+    ] {
+        let mut request = json!({ "name": "Friday blitz", "buy_in": BUY_IN, "min_players": 2, "rounds": 1, "starts_at": in_an_hour() });
+        request.as_object_mut().unwrap().extend(bad.as_object().unwrap().clone());
+        let (status, _) = app.post("/api/tournament", &organiser, request).await;
+        assert_eq!(status, Status::BadRequest);
+    }
+
+    let open = create(&app, &organiser, json!({})).await;
+    assert_eq!(open.prizes, vec![100]);
+    let (status, body) = app.get("/api/tournaments", "").await;
+    assert_eq!(status, Status::Ok);
+    let page: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(page["tournaments"][0]["id"], open.id);
+    let (status, _) = app.get("/api/tournaments?status=finished", "").await;
+    assert_eq!(status, Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn linked_arena_pays_out_from_lichess_results() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    app.lichess.add_tournament("arena123", false, "Club");
+    let (status, body) = app.post("/api/tournament", &organiser, json!({
+        "kind": "lichess arena", "lichess_tournament_id": "arena123", "buy_in": BUY_IN, "min_players": 2, "prizes": [70, 30]
+    })).await;
+    assert_eq!(status, Status::Ok);
+    let tournament: Tournament = serde_json::from_str(&body).unwrap();
+    assert_eq!(tournament.name, "Club Arena");
+    assert_eq!(tournament.time_limit, Some(180));
+    assert_eq!(tournament.increment, Some(2));
+    assert_eq!(tournament.rounds, None);
+    let tokens = join_all(&app, tournament.id, &["alice", "bob", "carol"]).await;
+
+    let detail = start(&app, tournament.id, &tokens[0]).await;
+    assert_eq!(detail.tournament.status, TournamentStatus::Running);
+    assert!(detail.pairings.is_empty());
+    assert!(app.lichess.challenges().is_empty());
+    let detail = poll(&app, tournament.id, &tokens[1]).await;
+    assert_eq!(detail.tournament.status, TournamentStatus::Running);
+
+    // bob never played, zed isn't an entrant
+    app.lichess.finish_tournament("arena123", &["Carol", "zed", "alice"]);
+    let detail = poll(&app, tournament.id, &tokens[1]).await;
+    assert_eq!(detail.tournament.status, TournamentStatus::Completed);
+    let net = 3 * BUY_IN - 30;
+    assert_eq!(detail.standings[0].username, "carol");
+    assert_eq!(entry(&detail, "carol").place, Some(1));
+    assert_eq!(entry(&detail, "carol").prize_msat, Some(net * 70 / 100 * 1000));
+    assert_eq!(entry(&detail, "alice").place, Some(2));
+    assert_eq!(entry(&detail, "alice").prize_msat, Some(net * 30 / 100 * 1000));
+    assert_eq!(entry(&detail, "bob").place, Some(3));
+    assert_eq!(entry(&detail, "bob").prize_msat, Some(0));
+    assert_eq!(app.balance_of("carol").await, 10_000 - BUY_IN + net * 70 / 100);
+    assert_eq!(app.balance_of(ADMIN).await, 30);
+}
+
+#[rocket::async_test]
+async fn linked_swiss_is_refunded_when_unfilled() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    app.lichess.add_tournament("swiss123", true, "Club Swiss");
+    let tournament = create(&app, &organiser, json!({
+        "kind": "lichess swiss", "lichess_tournament_id": "swiss123", "name": "Our swiss", "rounds": null, "min_players": 3
+    })).await;
+    assert_eq!(tournament.name, "Our swiss");
+    let tokens = join_all(&app, tournament.id, &["alice", "bob"]).await;
+
+    let detail = start(&app, tournament.id, &tokens[0]).await;
+    assert_eq!(detail.tournament.status, TournamentStatus::Canceled);
+    assert_eq!(app.balance_of("alice").await, 10_000);
+}
+
+#[rocket::async_test]
+async fn invalid_links_are_rejected() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let organiser = app.login("organiser").await;
+    app.lichess.add_tournament("arena123", false, "Club");
+
+    for bad in [
+        json!({ "kind": "lichess arena", "lichess_tournament_id": "nope" }),
+        json!({ "kind": "lichess swiss", "lichess_tournament_id": "arena123" }),
+        json!({ "kind": "lichess arena" }),
+        json!({ "kind": "lichess arena", "lichess_tournament_id": "arena123", "rounds": 3 }),
+        json!({ "kind": "swiss", "lichess_tournament_id": "arena123", "rounds": 3 }),
+        json!({ "kind": "swiss" }),
+    ] {
+        let mut request = json!({ "buy_in": BUY_IN, "min_players": 2 });
+        request.as_object_mut().unwrap().extend(bad.as_object().unwrap().clone());
+        let (status, _) = app.post("/api/tournament", &organiser, request).await;
+        assert_eq!(status, Status::BadRequest);
+    }
+
+    let link = json!({ "kind": "lichess arena", "lichess_tournament_id": "arena123", "buy_in": BUY_IN, "min_players": 2 });
+    let (status, _) = app.post("/api/tournament", &organiser, link.clone()).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = app.post("/api/tournament", &organiser, link).await;
+    assert_eq!(status, Status::Conflict);
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::spawn_app;
+use lightningchess::models::Challenge;
+use rocket::http::Status;
+use serde_json::json;
+
+#[rocket::async_test]
+async fn first_login_provisions_user_and_balance() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let token = app.lichess.add_account("Alice");
+    app.lichess.add_oauth_code("code-1", &token);
+
+    let response = app.client.get("/callback?code=code-1").dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+
+    let (lichess_id, username, logged_in): (String, String, bool) = sqlx::query_as(
+        "SELECT lichess_id, username, last_login IS NOT NULL FROM users")
+        .fetch_one(&app.pool).await.unwrap();
+    assert_eq!(lichess_id, "alice");
+    assert_eq!(username, "Alice");
+    assert!(logged_in);
+    assert_eq!(app.balance_of("alice").await, 0);
+}
+
+#[rocket::async_test]
+async fn new_user_without_funds_is_payment_required() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.login("bob").await;
+
+    let (status, _) = app.post("/api/challenge", &alice, json!({
+        "time_limit": 300,
+        "increment": 0,
+        "color": "white",
+        "sats": 100,
+        "opp_username": "bob"
+    })).await;
+    assert_eq!(status, Status::PaymentRequired);
+}
+
+#[rocket::async_test]
+async fn opponent_is_matched_by_lichess_id() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    let bob = app.login("Bob").await;
+    app.fund("alice", 1000).await;
+    app.fund("bob", 1000).await;
+
+    let (_, body) = app.post("/api/challenge", &alice, json!({
+        "time_limit": 300,
+        "increment": 0,
+        "color": "white",
+        "sats": 100,
+        "opp_username": "BOB"
+    })).await;
+    let challenge: Challenge = serde_json::from_str(&body).unwrap();
+
+    let (status, _) = app.post("/api/accept-challenge", &bob, json!({ "id": challenge.id })).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(app.balance_of("bob").await, 900);
+}
+
+#[rocket::async_test]
+async fn token_without_login_is_refused() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    // a valid lichess token that never went through the callback
+    let token = app.lichess.add_account("carol");
+
+    let (status, _) = app.get("/api/balance", &token).await;
+    assert_ne!(status, Status::Ok);
+    let users: i64 = sqlx::query_scalar("SELECT count(*) FROM users").fetch_one(&app.pool).await.unwrap();
+    assert_eq!(users, 0);
+}
+
+#[rocket::async_test]
+async fn unknown_opponent_is_not_found() {
+    let app = match spawn_app().await { Some(app) => app, None => return };
+    let alice = app.login("alice").await;
+    app.fund("alice", 1000).await;
+
+    let (status, _) = app.post("/api/challenge", &alice, json!({
+        "time_limit": 300,
+        "increment": 0,
+        "color": "white",
+        "sats": 100,
+        "opp_username": "nobody"
+    })).await;
+    assert_eq!(status, Status::NotFound);
+    let (status, _) = app.post("/api/match", &alice, json!({ "opp_username": "nobody", "sats": 100, "target": 3 })).await;
+    assert_eq!(status, Status::NotFound);
+
+    let users: i64 = sqlx::query_scalar("SELECT count(*) FROM users WHERE lichess_id='nobody'").fetch_one(&app.pool).await.unwrap();
+    assert_eq!(users, 0);
+    assert_eq!(app.balance_of("alice").await, 1000);
+}